@@ -0,0 +1,196 @@
+//! User-defined aliases, which expand into sequences of BAPS3 commands.
+//!
+//! An alias is defined with the words `NAME = COMMAND [; COMMAND ...]`, where
+//! each command is anything that could appear on a line of a script (see
+//! `script`).  Within the commands, `$1`, `$2` and so on are replaced by the
+//! arguments the alias is invoked with, and a lone `$*` by all of them.
+
+use std::borrow::ToOwned;
+
+use super::{ Baps3Error, Baps3Result };
+use super::script::{ Line, split_commands, step };
+
+/// A named sequence of commands.
+pub struct Alias {
+    pub name: String,
+
+    /// The commands, each as a list of words.
+    pub body: Vec<Vec<String>>
+}
+
+impl Alias {
+    /// Builds an alias from the words of its body.
+    ///
+    /// Commands are separated by words ending in `;`.  Returns `None` if the
+    /// body is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::alias::Alias;
+    /// let a = Alias::new("intro", &["load", "/j/intro.wav;", "play"]).unwrap();
+    /// assert_eq!(a.body.len(), 2);
+    /// assert_eq!(&*a.definition(), "intro = load /j/intro.wav; play");
+    /// ```
+    pub fn new(name: &str, words: &[&str]) -> Option<Alias> {
+        let body = split_commands(words);
+
+        if body.is_empty() {
+            None
+        } else {
+            Some(Alias { name: name.to_owned(), body: body })
+        }
+    }
+
+    /// Returns the number of arguments the alias needs.
+    pub fn arity(&self) -> usize {
+        self.body.iter()
+                 .flat_map(|c| c.iter())
+                 .flat_map(|w| placeholders(&**w).into_iter())
+                 .max()
+                 .unwrap_or(0)
+    }
+
+    /// Expands the alias with the given arguments into script lines, one per
+    /// command.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::alias::Alias;
+    /// let a = Alias::new("jingle", &["load", "/j/$1.wav"]).unwrap();
+    /// assert_eq!(a.arity(), 1);
+    /// assert!(a.expand(&["intro"]).is_ok());
+    /// assert!(a.expand(&[]).is_err());
+    /// ```
+    pub fn expand(&self, args: &[&str]) -> Baps3Result<Vec<Line>> {
+        let bad = |&: line: usize, reason: String| Baps3Error::BadScript {
+            path:   format!("alias {}", self.name),
+            line:   line,
+            reason: reason
+        };
+
+        let arity = self.arity();
+        if args.len() < arity {
+            return Err(bad(0, format!("needs {} argument(s), got {}",
+                                      arity, args.len())));
+        }
+
+        let mut lines = vec![];
+        for (n, command) in self.body.iter().enumerate() {
+            let mut words = vec![];
+            for w in command.iter() {
+                if &**w == "$*" {
+                    words.extend(args.iter().map(|a| (*a).to_owned()));
+                } else {
+                    words.push(substitute(&**w, args));
+                }
+            }
+
+            match step(&Path::new("."), &*words) {
+                Ok(Some(st)) => lines.push(Line { number: n + 1, step: st }),
+                Ok(None)     => (),
+                Err(reason)  => return Err(bad(n + 1, reason.to_owned()))
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// Returns the alias as it would be defined.
+    pub fn definition(&self) -> String {
+        let commands: Vec<String> = self.body.iter()
+                                             .map(|c| c.connect(" "))
+                                             .collect();
+        format!("{} = {}", self.name, commands.connect("; "))
+    }
+}
+
+/// Returns the numbers of the `$N` placeholders in `word`.
+fn placeholders(word: &str) -> Vec<usize> {
+    let mut found = vec![];
+    let mut chars = word.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' { continue; }
+
+        let mut n = 0;
+        while let Some(d) = chars.peek().and_then(|d| d.to_digit(10)) {
+            n = n * 10 + d as usize;
+            chars.next();
+        }
+        if 0 < n { found.push(n); }
+    }
+
+    found
+}
+
+/// Replaces each `$N` placeholder in `word` with the `N`th of `args`.
+///
+/// Placeholders with no matching argument are left alone.
+fn substitute(word: &str, args: &[&str]) -> String {
+    let mut out   = String::new();
+    let mut chars = word.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+
+        let mut digits = String::new();
+        while let Some(&d) = chars.peek() {
+            if !d.is_digit(10) { break; }
+            digits.push(d);
+            chars.next();
+        }
+
+        match digits.parse::<usize>() {
+            Some(n) if 0 < n && n <= args.len() => out.push_str(args[n - 1]),
+            _ => {
+                out.push('$');
+                out.push_str(&*digits);
+            }
+        }
+    }
+
+    out
+}
+
+/// A set of aliases.
+pub struct Aliases {
+    aliases: Vec<Alias>
+}
+
+impl Aliases {
+    pub fn new() -> Aliases {
+        Aliases { aliases: vec![] }
+    }
+
+    /// Defines an alias, replacing any existing one with the same name.
+    pub fn define(&mut self, alias: Alias) {
+        self.remove(&*alias.name);
+        self.aliases.push(alias);
+    }
+
+    /// Removes an alias.  Returns false if there was no such alias.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.aliases.len();
+        self.aliases.retain(|a| &*a.name != name);
+        self.aliases.len() != before
+    }
+
+    /// Looks up an alias by name.
+    pub fn get(&self, name: &str) -> Option<&Alias> {
+        self.aliases.iter().find(|a| &*a.name == name)
+    }
+
+    /// Returns the names of all aliases, for completion.
+    pub fn names(&self) -> Vec<String> {
+        self.aliases.iter().map(|a| a.name.clone()).collect()
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<Alias> {
+        self.aliases.iter()
+    }
+}
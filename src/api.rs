@@ -0,0 +1,292 @@
+//! The HTTP/JSON API that `baps3-http` serves for a BAPS3 server.
+//!
+//! A `Bridge` keeps one session with the server, mirroring its state, and
+//! answers the requests that `http::serve` hands it: commands are passed on
+//! to the server, and its messages are passed on to any server-sent event
+//! streams.
+//!
+//! Web pages may only use the API from the origins it is told to allow, as
+//! anything else could be a page on any site the user happens to visit.
+//! Those origins get the CORS headers that let browsers read the answers.
+
+use std::borrow::ToOwned;
+use std::io::net::tcp::TcpStream;
+use std::mem;
+use std::time::Duration;
+
+use rustc_serialize::json;
+
+use baps3_protocol::proto::Message;
+
+use super::{ Baps3Error, Baps3Result };
+use super::format::{ Format, timestamp };
+use super::http::{ self, Request, Response, origin_allowed };
+use super::path::{ PathMap, map_path };
+use super::session::Session;
+
+/// How long an event stream may take to accept an event, in milliseconds,
+/// before we give up on it.  Events are sent from the bridge's loop, so a
+/// stalled client would otherwise hold up everything else.
+const EVENT_TIMEOUT_MS: u64 = 500;
+
+/// How long browsers may remember the answer to a preflight, in seconds.
+const PREFLIGHT_MAX_AGE: u32 = 600;
+
+/// The endpoints that take GET; every other endpoint takes POST.
+const GET_ENDPOINTS: &'static [&'static str] = &["/state", "/events"];
+
+/// An HTTP/JSON API for one BAPS3 server.
+///
+/// # Examples
+///
+/// Answering requests on behalf of a mock BAPS3 server:
+///
+/// ```rust
+/// use std::io::{ Acceptor, BufReader, BufferedReader, Listener };
+/// use std::io::net::tcp::{ TcpListener, TcpStream };
+/// use std::thread::Thread;
+/// use std::time::Duration;
+/// use baps3_cli::api::Bridge;
+/// use baps3_cli::http::read_request;
+/// use baps3_cli::session::Session;
+///
+/// let mut mock = TcpListener::bind("127.0.0.1:0").listen().unwrap();
+/// let addr     = mock.socket_name().unwrap();
+/// Thread::spawn(move || {
+///     let mut c = mock.accept().unwrap();
+///     c.write_str("OHAI mock\nFEATURES FileLoad PlayStop Seek\n").unwrap();
+///
+///     let mut r = BufferedReader::new(c.clone());
+///     while let Ok(line) = r.read_line() {
+///         let cmd = line.trim();
+///         if cmd.starts_with("load ") {
+///             let _ = c.write_str(&*format!("FILE {}\n", &cmd[5..]));
+///         }
+///         if cmd.starts_with("seek ") {
+///             let _ = c.write_str(&*format!("TIME {}\n", &cmd[5..]));
+///         }
+///         let _ = c.write_str(&*format!("OK {}\n", cmd));
+///     }
+/// });
+///
+/// let session    = Session::new(&*addr.to_string(), &[], Duration::seconds(1), false);
+/// let mut bridge = Bridge::new(session, vec![], vec!["http://studio.example".to_string()]);
+/// let req        = |&: text: &str| read_request(&mut BufReader::new(text.as_bytes())).unwrap();
+///
+/// let r = bridge.respond(&req("GET /state HTTP/1.1\r\n\r\n"));
+/// assert_eq!(r.status, 200);
+/// assert!(r.body.contains(r#""ident":"mock""#));
+///
+/// let r = bridge.respond(&req("POST /load?file=%2Fa.mp3 HTTP/1.1\r\n\r\n"));
+/// assert_eq!(r.status, 200);
+/// assert!(r.body.contains(r#""file":"/a.mp3""#));
+/// assert_eq!(bridge.respond(&req("POST /load?file=a.mp3 HTTP/1.1\r\n\r\n")).status, 400);
+/// assert_eq!(bridge.respond(&req("POST /load HTTP/1.1\r\n\r\n")).status, 400);
+/// assert_eq!(bridge.respond(&req("GET /load HTTP/1.1\r\n\r\n")).status, 405);
+///
+/// let r = bridge.respond(&req("POST /seek HTTP/1.1\r\n\
+///                              Content-Type: application/json\r\n\
+///                              Content-Length: 16\r\n\r\n\
+///                              {\"pos\": 1500000}"));
+/// assert_eq!(r.status, 200);
+/// assert!(r.body.contains(r#""time":1500000"#));
+/// assert_eq!(bridge.respond(&req("POST /seek?pos=soon HTTP/1.1\r\n\r\n")).status, 400);
+/// assert_eq!(bridge.respond(&req("POST /seek HTTP/1.1\r\n\
+///                                 Content-Type: text/plain\r\n\
+///                                 Content-Length: 16\r\n\r\n\
+///                                 {\"pos\": 1500000}")).status, 415);
+///
+/// // Other sites may not use the API, but allowed ones get CORS headers.
+/// assert_eq!(bridge.respond(&req("POST /stop HTTP/1.1\r\n\
+///                                 Origin: http://evil.example\r\n\r\n")).status, 403);
+/// let r = bridge.respond(&req("OPTIONS /seek HTTP/1.1\r\n\
+///                              Origin: http://studio.example\r\n\r\n"));
+/// assert_eq!(r.status, 204);
+/// assert_eq!(r.header("Access-Control-Allow-Origin"), Some("http://studio.example"));
+/// assert_eq!(r.header("Access-Control-Allow-Methods"), Some("POST"));
+///
+/// // Server-sent events carry the server's messages.
+/// let mut listener = TcpListener::bind("127.0.0.1:0").listen().unwrap();
+/// let mut browser  = TcpStream::connect(listener.socket_name().unwrap()).unwrap();
+/// let stream       = listener.accept().unwrap();
+/// assert_eq!(bridge.handle(&req("GET /events HTTP/1.1\r\n\r\n"), stream), 200);
+/// bridge.respond(&req("POST /load?file=%2Fb.mp3 HTTP/1.1\r\n\r\n"));
+/// drop(bridge);
+///
+/// let events = browser.read_to_string().unwrap();
+/// assert!(events.contains("Content-Type: text/event-stream"));
+/// assert!(events.contains("event: FILE\ndata: [\"FILE\",\"/b.mp3\"]\n\n"));
+/// ```
+pub struct Bridge {
+    pub session: Session,
+    maps:        Vec<PathMap>,
+
+    /// The origins web pages may use the API from.
+    origins:     Vec<String>,
+
+    /// Clients listening to server-sent events.
+    streams:     Vec<TcpStream>
+}
+
+impl Bridge {
+    /// Creates a bridge to the server behind `session`, mapping loaded paths
+    /// with `maps` and allowing web pages from `origins`.
+    pub fn new(session: Session, maps: Vec<PathMap>, origins: Vec<String>) -> Bridge {
+        Bridge { session: session, maps: maps, origins: origins, streams: vec![] }
+    }
+
+    /// Passes a message from the server on to the mirror and event streams.
+    pub fn notify(&mut self, msg: &Message) {
+        self.session.update(msg);
+
+        // A stream that failed or timed out may have been left with half an
+        // event, so it can't be used again.
+        let data    = Format::Json.message(msg);
+        let streams = mem::replace(&mut self.streams, vec![]);
+        for mut s in streams.into_iter() {
+            if http::write_event(&mut s, msg.word(), &*data).is_ok() {
+                self.streams.push(s);
+            }
+        }
+    }
+
+    /// Waits up to `tick` for a message from the server and acts on it.
+    pub fn poll_server(&mut self, tick: Duration) {
+        match self.session.recv_timeout(tick) {
+            Ok(Some(msg)) => self.notify(&msg),
+            Ok(None)      => (),
+            Err(e)        => {
+                let _ = ::std::io::stderr().write_line(&*format!(
+                    "{} {}: {}", timestamp(), self.session.addr(), e));
+            }
+        }
+    }
+
+    /// Returns the mirror as JSON, connecting first if need be.
+    fn state_json(&mut self) -> Baps3Result<String> {
+        try!(self.session.connect());
+        Ok(json::encode(&self.session.state()))
+    }
+
+    /// Sends a command, passing on anything the server says meanwhile, and
+    /// returns the resulting state.
+    fn command(&mut self, msg: &Message) -> Baps3Result<String> {
+        let others = try!(self.session.send_collecting(msg));
+        for m in others.iter() {
+            self.notify(m);
+        }
+        self.state_json()
+    }
+
+    /// Builds the command for a POST to `path`, if there is one.
+    fn command_for(&self, req: &Request) -> Option<Baps3Result<Message>> {
+        let missing = |&: name: &str| Baps3Error::CmdInvalid {
+            advice: format!("missing parameter: {}", name)
+        };
+
+        Some(match &*req.path {
+            "/play"  => Ok(Message::new("play")),
+            "/stop"  => Ok(Message::new("stop")),
+            "/eject" => Ok(Message::new("eject")),
+            "/load"  => req.param("file").ok_or(missing("file")).and_then(|f| {
+                if f.starts_with("/") {
+                    Ok(Message::new("load").arg(&*map_path(&*self.maps, &*f)))
+                } else {
+                    Err(Baps3Error::InvalidPath { path: f })
+                }
+            }),
+            "/seek"  => req.param("pos").ok_or(missing("pos")).and_then(|p| {
+                match p.parse::<u64>() {
+                    Some(us) => Ok(Message::new("seek").arg(&*us.to_string())),
+                    None     => Err(Baps3Error::CmdInvalid {
+                        advice: "pos should be a whole number of microseconds".to_owned()
+                    })
+                }
+            }),
+            _ => return None
+        })
+    }
+
+    /// Returns the method `path` takes, if it is an endpoint.
+    fn method_for(&self, req: &Request) -> Option<&'static str> {
+        if GET_ENDPOINTS.contains(&&*req.path) {
+            Some("GET")
+        } else if self.command_for(req).is_some() {
+            Some("POST")
+        } else {
+            None
+        }
+    }
+
+    /// Answers a request for anything but an event stream, without regard to
+    /// where it came from.
+    fn route(&mut self, req: &Request) -> Response {
+        let ok = |&: r: Baps3Result<String>| match r {
+            Ok(body) => Response::json(200, body),
+            Err(e)   => Response::error(&e)
+        };
+
+        // Bodies are only ever JSON; insisting on saying so means a browser
+        // can't send one without asking us first.
+        let json_body = req.header("Content-Type")
+                           .map_or(false, |t| t.starts_with("application/json"));
+        if !req.body.is_empty() && !json_body {
+            return Response::text(415, "expected an application/json body");
+        }
+
+        match (&*req.method, self.method_for(req)) {
+            (_, None) => Response::text(404, "no such endpoint"),
+            ("OPTIONS", Some(m)) =>
+                Response::text(204, "")
+                    .with_header("Access-Control-Allow-Methods", m)
+                    .with_header("Access-Control-Allow-Headers", "Content-Type")
+                    .with_header("Access-Control-Max-Age",
+                                 &*PREFLIGHT_MAX_AGE.to_string()),
+            ("GET", Some("GET")) => ok(self.state_json()),
+            ("POST", Some("POST")) => match self.command_for(req) {
+                Some(Ok(msg)) => ok(self.command(&msg)),
+                Some(Err(e))  => Response::error(&e),
+                None          => Response::text(404, "no such endpoint")
+            },
+            (_, Some(m)) => Response::text(405, &*format!("use {}", m))
+        }
+    }
+
+    /// The CORS headers for answering a request, which must be from an
+    /// allowed origin.
+    fn cors_headers(req: &Request) -> Vec<(String, String)> {
+        match req.header("Origin") {
+            Some(o) => vec![("Access-Control-Allow-Origin".to_owned(), o.to_owned()),
+                            ("Vary".to_owned(), "Origin".to_owned())],
+            None    => vec![]
+        }
+    }
+
+    /// Answers a request for anything but an event stream.
+    pub fn respond(&mut self, req: &Request) -> Response {
+        if !origin_allowed(req, &*self.origins) {
+            return Response::text(403, "origin not allowed");
+        }
+
+        let mut resp = self.route(req);
+        resp.headers.extend(Bridge::cors_headers(req).into_iter());
+        resp
+    }
+
+    /// Answers a request on `stream`, keeping the stream if it asked for
+    /// events, and returns the status it was answered with.
+    pub fn handle(&mut self, req: &Request, mut stream: TcpStream) -> u16 {
+        let events = &*req.method == "GET" && &*req.path == "/events";
+        if !events || !origin_allowed(req, &*self.origins) {
+            let resp = self.respond(req);
+            let _    = resp.write_to(&mut stream);
+            return resp.status;
+        }
+
+        stream.set_write_timeout(Some(EVENT_TIMEOUT_MS));
+        if http::start_event_stream(&mut stream, &*Bridge::cors_headers(req)).is_ok() {
+            self.streams.push(stream);
+        }
+        200
+    }
+}
@@ -0,0 +1,346 @@
+//! As-run logs: records of what a BAPS3 server actually played, built from
+//! the notifications it sends, for compliance and royalty reporting.
+//!
+//! Each record is one play of one file, from when the server started playing
+//! it to when it ended or was stopped for good (by loading another file,
+//! ejecting, or our losing the connection).  Pausing and resuming the same
+//! file continues the same play.
+
+use std::borrow::ToOwned;
+use std::cmp;
+use std::io::{ Append, File, IoResult, Write };
+
+use rustc_serialize::json;
+
+use baps3_protocol::proto::Message;
+
+use clock::{ self, Timespec };
+
+use super::format::timestamp_of;
+
+/// How a play came to an end.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum Ending {
+    /// The server reached the end of the file.
+    Natural,
+
+    /// The file was stopped before its end.
+    Stopped
+}
+
+impl Ending {
+    /// Returns the name of the ending, as written in logs.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Ending::Natural => "end",
+            Ending::Stopped => "stopped"
+        }
+    }
+}
+
+/// One play of one file.
+#[derive(Clone, PartialEq, Show)]
+pub struct Play {
+    pub file:   String,
+
+    /// When the server started, and last stopped, playing the file.
+    pub start:  Timespec,
+    pub end:    Timespec,
+
+    /// How much of the file was played, in microseconds.
+    pub played: u64,
+
+    pub ending: Ending
+}
+
+/// A play in progress.
+struct Current {
+    start:  Timespec,
+    end:    Timespec,
+    played: u64
+}
+
+/// Turns a server's notifications into plays.
+pub struct Recorder {
+    file:     Option<String>,
+    playing:  bool,
+
+    /// The last TIME reported, and when.
+    position: Option<(u64, Timespec)>,
+
+    current:  Option<Current>
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder { file: None, playing: false, position: None, current: None }
+    }
+
+    /// Updates the recorder with a message from the server, received at
+    /// `now`, returning the play it finished, if any.
+    ///
+    /// How much was played is worked out from TIME, counting each advance
+    /// only as far as wall-clock time allows, so that seeking ahead doesn't
+    /// count as playing.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// extern crate baps3_cli;
+    /// extern crate baps3_protocol;
+    /// extern crate time;
+    /// use baps3_cli::asrun::{ Ending, Recorder };
+    /// use baps3_protocol::proto::Message;
+    /// use time::Timespec;
+    ///
+    /// # fn main() {
+    /// let at = |&: sec: i64| Timespec { sec: sec, nsec: 0 };
+    /// let mut r = Recorder::new();
+    /// assert!(r.update(&Message::new("FILE").arg("/a.mp3"), at(0)).is_none());
+    /// r.update(&Message::new("STATE").arg("Playing"), at(1));
+    /// r.update(&Message::new("TIME").arg("0"), at(1));
+    /// r.update(&Message::new("TIME").arg("2000000"), at(3));
+    /// // A seek ahead; only the 1s of wall-clock time counts.
+    /// r.update(&Message::new("TIME").arg("60000000"), at(4));
+    ///
+    /// let play = r.update(&Message::new("END"), at(5)).unwrap();
+    /// assert_eq!(&*play.file, "/a.mp3");
+    /// assert_eq!((play.start, play.end), (at(1), at(5)));
+    /// assert_eq!(play.played, 3000000);
+    /// assert_eq!(play.ending, Ending::Natural);
+    ///
+    /// // Playing the same file again is a new play.
+    /// r.update(&Message::new("STATE").arg("Playing"), at(6));
+    /// r.update(&Message::new("STATE").arg("Stopped"), at(7));
+    /// let play = r.update(&Message::new("FILE").arg("/b.mp3"), at(9)).unwrap();
+    /// assert_eq!((play.start, play.end), (at(6), at(7)));
+    /// assert_eq!(play.ending, Ending::Stopped);
+    /// # }
+    /// ```
+    ///
+    /// The server may report that it is playing before saying what, and may
+    /// change files without stopping:
+    ///
+    /// ```rust
+    /// extern crate baps3_cli;
+    /// extern crate baps3_protocol;
+    /// extern crate time;
+    /// use baps3_cli::asrun::{ Ending, Recorder };
+    /// use baps3_protocol::proto::Message;
+    /// use time::Timespec;
+    ///
+    /// # fn main() {
+    /// let at = |&: sec: i64| Timespec { sec: sec, nsec: 0 };
+    /// let mut r = Recorder::new();
+    /// r.update(&Message::new("STATE").arg("Playing"), at(0));
+    /// r.update(&Message::new("FILE").arg("/a.mp3"), at(1));
+    ///
+    /// let play = r.update(&Message::new("FILE").arg("/b.mp3"), at(4)).unwrap();
+    /// assert_eq!(&*play.file, "/a.mp3");
+    /// assert_eq!((play.start, play.end), (at(1), at(4)));
+    ///
+    /// let play = r.update(&Message::new("END"), at(6)).unwrap();
+    /// assert_eq!(&*play.file, "/b.mp3");
+    /// assert_eq!((play.start, play.end), (at(4), at(6)));
+    /// assert_eq!(play.ending, Ending::Natural);
+    /// # }
+    /// ```
+    pub fn update(&mut self, msg: &Message, now: Timespec) -> Option<Play> {
+        match msg.as_str_vec().as_slice() {
+            ["FILE", file] => {
+                let play = self.finish(Ending::Stopped, now);
+                self.file     = Some(file.to_owned());
+                self.position = None;
+
+                // A server that is already playing, whether because it told
+                // us so before naming the file or because it moved straight
+                // on to another, is playing the new file from now on.
+                if self.playing {
+                    self.current = Some(Current { start:  now,
+                                                  end:    now,
+                                                  played: 0 });
+                }
+                play
+            },
+            ["STATE", "Ejected"] => {
+                let play = self.finish(Ending::Stopped, now);
+                self.file     = None;
+                self.playing  = false;
+                self.position = None;
+                play
+            },
+            ["STATE", st] => {
+                let playing = st == "Playing";
+                if playing && !self.playing && self.file.is_some() {
+                    if self.current.is_none() {
+                        self.current = Some(Current { start:  now,
+                                                      end:    now,
+                                                      played: 0 });
+                    }
+                    // Don't count time spent paused as time spent playing.
+                    self.position = self.position.map(|(p, _)| (p, now));
+                }
+                if !playing && self.playing {
+                    if let Some(ref mut c) = self.current { c.end = now; }
+                }
+                self.playing = playing;
+                None
+            },
+            ["TIME", t] => {
+                if let Some(pos) = t.parse::<u64>() {
+                    if let (true, Some((last, at)), Some(c))
+                         = (self.playing, self.position, self.current.as_mut()) {
+                        let wall = (now - at).num_microseconds().unwrap_or(0);
+                        if last < pos && 0 < wall {
+                            c.played += cmp::min(pos - last, wall as u64);
+                        }
+                    }
+                    self.position = Some((pos, now));
+                }
+                None
+            },
+            ["END"] => {
+                let play = self.finish(Ending::Natural, now);
+                self.playing = false;
+                play
+            },
+            _ => None
+        }
+    }
+
+    /// Finishes any play in progress, because we lost the connection at
+    /// `now`, and forgets what we knew about the server.
+    pub fn disconnected(&mut self, now: Timespec) -> Option<Play> {
+        let play = self.finish(Ending::Stopped, now);
+        *self = Recorder::new();
+        play
+    }
+
+    fn finish(&mut self, ending: Ending, now: Timespec) -> Option<Play> {
+        let playing = self.playing;
+        match (self.current.take(), &self.file) {
+            (Some(c), &Some(ref file)) =>
+                Some(Play { file:   file.clone(),
+                            start:  c.start,
+                            end:    if playing { now } else { c.end },
+                            played: c.played,
+                            ending: ending }),
+            _ => None
+        }
+    }
+}
+
+/// A way of writing as-run logs.
+#[derive(Copy, PartialEq, Show)]
+pub enum LogFormat {
+    /// Comma-separated values, with a header line.
+    Csv,
+
+    /// One JSON object per line.
+    Json
+}
+
+/// Quotes a CSV field, if it needs it.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::asrun::csv_field;
+/// assert_eq!(&*csv_field("/a.mp3"), "/a.mp3");
+/// assert_eq!(&*csv_field("/a, \"b\".mp3"), "\"/a, \"\"b\"\".mp3\"");
+/// ```
+pub fn csv_field(s: &str) -> String {
+    if s.contains_char(',') || s.contains_char('"') || s.contains_char('\n') {
+        format!("\"{}\"", s.replace("\"", "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+impl LogFormat {
+    /// Looks up a format by the name used on the command line.
+    pub fn from_name(name: &str) -> Option<LogFormat> {
+        match name {
+            "csv"  => Some(LogFormat::Csv),
+            "json" => Some(LogFormat::Json),
+            _      => None
+        }
+    }
+
+    /// Returns the extension of log files in this format.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            LogFormat::Csv  => "csv",
+            LogFormat::Json => "jsonl"
+        }
+    }
+
+    /// Returns the line that starts each log file, if any.
+    pub fn header(&self) -> Option<&'static str> {
+        match *self {
+            LogFormat::Csv  => Some("start,end,played,ending,file"),
+            LogFormat::Json => None
+        }
+    }
+
+    /// Formats `play` as one line of a log.
+    ///
+    /// Times are local, as `timestamp` gives them, and how much was played
+    /// is in seconds.
+    pub fn record(&self, play: &Play) -> String {
+        let start  = timestamp_of(play.start);
+        let end    = timestamp_of(play.end);
+        let played = format!("{:.3}", play.played as f64 / 1000000.0);
+
+        match *self {
+            LogFormat::Csv =>
+                format!("{},{},{},{},{}",
+                        start, end, played, play.ending.name(), csv_field(&*play.file)),
+            LogFormat::Json =>
+                format!("{{\"start\":{},\"end\":{},\"played\":{},\"ending\":{},\"file\":{}}}",
+                        json::encode(&start),
+                        json::encode(&end),
+                        played,
+                        json::encode(&play.ending.name()),
+                        json::encode(&play.file))
+        }
+    }
+}
+
+/// An as-run log in a directory, starting a new file each day.
+///
+/// Files are named `asrun-YYYY-MM-DD` with the format's extension, by the
+/// local date on which each play started.
+pub struct Log {
+    dir:    Path,
+    format: LogFormat,
+
+    /// The date of the open file, and the file.
+    open:   Option<(String, File)>
+}
+
+impl Log {
+    pub fn new(dir: Path, format: LogFormat) -> Log {
+        Log { dir: dir, format: format, open: None }
+    }
+
+    /// Appends `play` to the log.
+    pub fn write(&mut self, play: &Play) -> IoResult<()> {
+        let day = clock::at(play.start).strftime("%Y-%m-%d").unwrap().to_string();
+
+        let stale = self.open.as_ref().map_or(true, |&(ref d, _)| *d != day);
+        if stale {
+            let path = self.dir.join(format!("asrun-{}.{}", day, self.format.extension()));
+            let fresh = !path.exists();
+            let mut file = try!(File::open_mode(&path, Append, Write));
+            if let (true, Some(h)) = (fresh, self.format.header()) {
+                try!(file.write_line(h));
+            }
+            self.open = Some((day, file));
+        }
+
+        let file = &mut self.open.as_mut().unwrap().1;
+        try!(file.write_line(&*self.format.record(play)));
+        file.flush()
+    }
+}
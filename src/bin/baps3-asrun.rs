@@ -0,0 +1,105 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate "time" as clock;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::time::Duration;
+
+use baps3_cli::{ Baps3Error, Baps3Result };
+use baps3_cli::asrun::{ Log, LogFormat, Play, Recorder };
+use baps3_cli::config::resolve_target;
+use baps3_cli::format::timestamp;
+use baps3_cli::session::Session;
+
+docopt!(Args, concat!("
+Keeps an as-run log of what a BAPS3 server plays.
+
+Usage:
+  baps3-asrun -h
+  baps3-asrun --completions <shell>
+  baps3-asrun [-v] [-f <format>] [-d <dir>] [-r <secs>] [-t <target>]
+
+One record is written for each play of a file, once it ends: the time it
+started, the time it ended, how many seconds were played, whether it reached
+the end or was stopped, and the file.
+
+Options:
+  -h, --help             Show this message.
+  -d, --dir <dir>        Write logs to this directory, one file a day, rather
+                         than to standard output.
+  -f, --format <format>  The log format: csv or json (JSON lines).
+                         [Default: csv]
+  -r, --retry <secs>     How long to wait before reconnecting when the
+                         server goes away.
+                         [Default: 5]
+", common_options!()), flag_retry: i64);
+
+/// How long we wait for the server in one go, in milliseconds.
+const TICK_MS: i64 = 200;
+
+/// Where records go.
+enum Output {
+    Stdout(LogFormat),
+    Dir(Log)
+}
+
+impl Output {
+    fn write(&mut self, play: &Play) {
+        match *self {
+            Output::Stdout(format) => println!("{}", format.record(play)),
+            Output::Dir(ref mut log) => if let Err(e) = log.write(play) {
+                // Keep going: the next record may well be written.
+                werr!("{} {}: {}\n", timestamp(), play.file, e);
+            }
+        }
+    }
+}
+
+fn run(args: Args) -> Baps3Result<()> {
+    let target = try!(resolve_target(&*args.flag_target));
+    let format = try!(LogFormat::from_name(&*args.flag_format).ok_or(Baps3Error::CmdInvalid {
+        advice: format!("unknown format: {}", args.flag_format)
+    }));
+
+    let mut output = if args.flag_dir.is_empty() {
+        if let Some(h) = format.header() { println!("{}", h); }
+        Output::Stdout(format)
+    } else {
+        Output::Dir(Log::new(Path::new(&*args.flag_dir), format))
+    };
+
+    let mut session  = Session::new(&*target.addr,
+                                    &[],
+                                    Duration::seconds(args.flag_retry),
+                                    args.flag_verbose);
+    let mut recorder = Recorder::new();
+    let mut connects = 0;
+
+    loop {
+        let msg = session.recv_timeout(Duration::milliseconds(TICK_MS));
+
+        // Anything still playing when we lose the server, or reconnect to it,
+        // is as good as stopped: we can't tell what happened in between.
+        if session.connects != connects || session.get().is_none() {
+            connects = session.connects;
+            if let Some(play) = recorder.disconnected(clock::get_time()) {
+                output.write(&play);
+            }
+        }
+
+        match msg {
+            Ok(Some(m)) => if let Some(play) = recorder.update(&m, clock::get_time()) {
+                output.write(&play);
+            },
+            Ok(None)    => (),
+            Err(e)      => werr!("{} {}: {}\n", timestamp(), session.addr(), e)
+        }
+    }
+}
+
+command_main!(run);
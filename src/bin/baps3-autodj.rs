@@ -0,0 +1,292 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate "time" as clock;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::sync::mpsc::{ channel, Receiver };
+use std::time::Duration;
+
+use clock::Timespec;
+
+use baps3_protocol::proto::Message;
+
+use baps3_cli::{ Baps3Error, Baps3Result };
+use baps3_cli::config::resolve_target;
+use baps3_cli::format::timestamp;
+use baps3_cli::path::{ PathMap, command_maps, map_path, server_path };
+use baps3_cli::queue::{ self, Queue };
+use baps3_cli::server::{ self, Downstream, Event, ack };
+use baps3_cli::session::{ Session, is_connection_error };
+
+docopt!(Args, concat!("
+Plays files one after another on a BAPS3 server, loading and playing the next
+whenever the current one ends.
+
+Usage:
+  baps3-autodj -h
+  baps3-autodj --completions <shell>
+  baps3-autodj [-sv] [-n <count>] [-c <addr>] [-r <secs>] [-t <target>] [-m <map>...] <source>
+
+The files come from <source>, which is either a directory (searched for audio
+files) or a playlist with one file per line.  Each file is logged on standard
+output as it starts playing.
+
+While running, the following BAPS3 commands are accepted on the control
+address:
+
+  skip       Play the next file now.
+  pause      Stop advancing when the current file ends.
+  resume     Start advancing again, straight away if nothing is playing.
+  next FILE  Play FILE after the current one, before the rest of the queue.
+  queue      List the files put in with next that are still to come.
+
+Options:
+  -h, --help               Show this message.
+  -c, --control <addr>     The address to accept control commands on.
+                           [Default: localhost:1351]
+  -m, --map <map>          Rewrites paths beginning with CLIENT to begin with
+                           SERVER instead, given as CLIENT=SERVER.  Applied
+                           before any rules for the target.
+  -n, --no-repeat <count>  When shuffling, play at least this many other files
+                           before repeating one.
+                           [Default: 0]
+  -r, --retry <secs>       How long to wait before reconnecting when the
+                           server goes away.
+                           [Default: 5]
+  -s, --shuffle            Play the files in a random order, rather than the
+                           order they are listed or named in.
+", common_options!()), flag_no_repeat: usize, flag_retry: i64);
+
+/// How long we wait for the server in one go, in milliseconds, before
+/// checking the control channel.
+const TICK_MS: i64 = 100;
+
+/// How long to wait for the server's initial state dump, in milliseconds,
+/// before deciding whether anything is playing.
+const DUMP_TIMEOUT_MS: i64 = 500;
+
+struct AutoDj {
+    session: Session,
+    queue:   Queue,
+    maps:    Vec<PathMap>,
+
+    /// Whether advancing to the next file is suspended.
+    paused:  bool,
+
+    /// The connection we last checked the server's playback on, by the
+    /// session's count.
+    connects: u64,
+
+    /// When the current connection was made, if we have yet to check whether
+    /// the server is playing anything.
+    unchecked_since: Option<Timespec>,
+
+    /// Whether the server has said END since we last acted on it.
+    ended: bool,
+
+    clients: Vec<Downstream>
+}
+
+impl AutoDj {
+    /// Waits briefly for a message from the server and acts on it.
+    fn poll_server(&mut self) {
+        match self.session.recv_timeout(Duration::milliseconds(TICK_MS)) {
+            Ok(Some(msg)) => self.notify(&msg),
+            Ok(None)      => (),
+            Err(e)        => werr!("{} {}: {}\n", timestamp(), self.session.addr(), e)
+        }
+
+        // Acted on here, rather than as it arrives, so that an END heard
+        // while advancing is handled like any other.
+        if self.ended {
+            self.ended = false;
+            if !self.paused {
+                self.advance();
+            }
+        }
+
+        if self.session.connects != self.connects {
+            self.connects        = self.session.connects;
+            self.unchecked_since = Some(clock::get_time());
+        }
+
+        // Once we know what the server is up to after connecting, get it
+        // playing if it isn't already.
+        if let Some(since) = self.unchecked_since {
+            let complete = self.session.state().map_or(false, |s| s.is_complete());
+            let waited   = Duration::milliseconds(DUMP_TIMEOUT_MS)
+                           < clock::get_time() - since;
+            if complete || waited {
+                self.unchecked_since = None;
+                if !self.paused && !self.is_playing() {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    /// Takes in a notification from the server.
+    fn notify(&mut self, msg: &Message) {
+        self.session.update(msg);
+
+        if msg.word() == "END" {
+            self.ended = true;
+        }
+    }
+
+    fn is_playing(&self) -> bool {
+        self.session.state()
+                    .and_then(|s| s.state.as_ref())
+                    .map_or(false, |s| &**s == "Playing")
+    }
+
+    /// Sends `msg`, keeping our mirror up to date with anything the server
+    /// says meanwhile.
+    ///
+    /// Returns whether the server said END meanwhile.
+    fn send(&mut self, msg: &Message) -> Baps3Result<bool> {
+        let msgs = try!(self.session.send_collecting(msg));
+        for m in msgs.iter() {
+            self.session.update(m);
+        }
+        Ok(msgs.iter().any(|m| m.word() == "END"))
+    }
+
+    /// Loads and plays the next file in the queue.
+    ///
+    /// Files the server refuses to load are skipped, but we give up after
+    /// going once round the queue, or if the server goes away.
+    fn advance(&mut self) {
+        for _ in range(0, self.queue.len() + self.queue.pending().len()) {
+            let file = match self.queue.next() {
+                Some(f) => f,
+                None    => break
+            };
+
+            // An END before the load is acknowledged is for the file being
+            // replaced, but one after it means the new file has already
+            // finished.
+            let loaded = self.send(&Message::new("load").arg(&*file));
+            match loaded.and_then(|_| self.send(&Message::new("play"))) {
+                Ok(ended) => {
+                    println!("{} playing {}", timestamp(), file);
+                    self.ended = ended;
+                    return;
+                },
+                Err(ref e) if is_connection_error(e) => {
+                    // We'll pick up again when the session reconnects.
+                    werr!("{} {}: {}\n", timestamp(), self.session.addr(), e);
+                    self.queue.put_back(&*file);
+                    return;
+                },
+                Err(e) => werr!("{} skipping {}: {}\n", timestamp(), file, e)
+            }
+        }
+
+        werr!("{} nothing left to play\n", timestamp());
+    }
+
+    /// Handles a control command, returning the messages to send back before
+    /// its acknowledgement.
+    fn control(&mut self, msg: &Message) -> (Vec<Message>, Baps3Result<()>) {
+        let invalid = |&: advice: &str| {
+            Err(Baps3Error::CmdInvalid { advice: advice.to_string() })
+        };
+
+        let result = match (msg.word(), &*msg.args()) {
+            ("skip", []) => {
+                self.advance();
+                Ok(())
+            },
+            ("pause", []) => {
+                self.paused = true;
+                Ok(())
+            },
+            ("resume", []) => {
+                self.paused = false;
+                if self.session.get().is_some() && !self.is_playing() {
+                    self.advance();
+                }
+                Ok(())
+            },
+            ("next", [file]) => server_path(file, &*self.maps).map(|sp| {
+                self.queue.insert_next(&*sp);
+            }),
+            ("queue", []) => {
+                let files = self.queue.pending();
+                return (files.iter().map(|f| Message::new("QUEUED").arg(&**f)).collect(),
+                        Ok(()));
+            },
+            ("skip", _) | ("pause", _) | ("resume", _) | ("queue", _) =>
+                invalid("expected no arguments"),
+            ("next", _) => invalid("expected one file"),
+            _           => invalid("unknown command")
+        };
+
+        (vec![], result)
+    }
+
+    /// Handles something that happened to a control client.
+    fn client_event(&mut self, ev: Event) {
+        match ev {
+            Event::Connected(mut d) => {
+                if d.send(&Message::new("OHAI").arg("baps3-autodj")).is_ok() {
+                    self.clients.push(d);
+                }
+            },
+            Event::Message(id, msg) => {
+                let (mut replies, result) = self.control(&msg);
+                replies.push(ack(&msg, &result));
+                if let Some(d) = self.clients.iter_mut().find(|d| d.id == id) {
+                    for r in replies.iter() {
+                        let _ = d.send(r);
+                    }
+                }
+            },
+            Event::Disconnected(id) => self.clients.retain(|d| d.id != id)
+        }
+    }
+
+    fn run(&mut self, events: Receiver<Event>) {
+        loop {
+            self.poll_server();
+            while let Ok(ev) = events.try_recv() {
+                self.client_event(ev);
+            }
+        }
+    }
+}
+
+fn run(args: Args) -> Baps3Result<()> {
+    let target = try!(resolve_target(&*args.flag_target));
+    let maps   = try!(command_maps(&*args.flag_map, &target));
+    let files  = try!(queue::read_source(&Path::new(&*args.arg_source)));
+    let pool: Vec<String> = files.iter().map(|f| map_path(&*maps, &**f)).collect();
+
+    let (tx, rx) = channel();
+    try!(server::listen(&*args.flag_control, tx));
+
+    let mut dj = AutoDj {
+        session:         Session::new(&*target.addr,
+                                      &["FileLoad", "PlayStop", "End"],
+                                      Duration::seconds(args.flag_retry),
+                                      args.flag_verbose),
+        queue:           Queue::new(pool, args.flag_shuffle, args.flag_no_repeat),
+        maps:            maps,
+        paused:          false,
+        connects:        0,
+        unchecked_since: None,
+        ended:           false,
+        clients:         vec![]
+    };
+
+    dj.run(rx);
+    Ok(())
+}
+
+command_main!(run);
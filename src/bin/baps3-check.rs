@@ -0,0 +1,305 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate "time" as clock;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::io::timer::Timer;
+use std::os;
+use std::sync::mpsc::channel;
+use std::thread::Thread;
+use std::time::Duration;
+
+use baps3_cli::{ Baps3, Baps3Error, exit_with, verbose_logger };
+use baps3_cli::completion;
+use baps3_cli::config::resolve_target;
+use baps3_cli::state::{ ServerState, read_state };
+use baps3_cli::time::format_micros;
+
+docopt!(Args, concat!("
+Checks the health of a BAPS3 server, as a Nagios plugin.
+
+Usage:
+  baps3-check -h
+  baps3-check --completions <shell>
+  baps3-check [-lpv] [-a <secs>] [-w <secs>] [-c <secs>] [-T <secs>] [-f <feature>...] [-t <target>]
+
+Prints one line of status, with performance data, and exits with 0 (OK),
+1 (WARNING), 2 (CRITICAL) or 3 (UNKNOWN).
+
+Options:
+  -h, --help               Show this message.
+  -a, --advancing <secs>   Critical unless TIME advances within this many
+                           seconds.  Implies -p.
+  -c, --critical <secs>    Critical if the handshake takes longer than this.
+  -f, --feature <feature>  Critical unless the server has this feature.
+  -l, --loaded             Critical unless a file is loaded.
+  -p, --playing            Critical unless the server is playing.
+  -T, --timeout <secs>     Critical if connecting, the handshake and reading
+                           the server's state take longer than this
+                           altogether.
+                           [Default: 10]
+  -w, --warning <secs>     Warn if the handshake takes longer than this.
+", common_options!()));
+
+/// A plugin's verdict, with the exit codes Nagios expects.
+#[derive(Copy, PartialEq, PartialOrd)]
+enum Status {
+    Ok       = 0,
+    Warning  = 1,
+    Critical = 2,
+    Unknown  = 3
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match *self {
+            Status::Ok       => "OK",
+            Status::Warning  => "WARNING",
+            Status::Critical => "CRITICAL",
+            Status::Unknown  => "UNKNOWN"
+        }
+    }
+}
+
+/// What the check found.
+struct Report {
+    status:   Status,
+
+    /// What was wrong, most severe first, or what was right if nothing was.
+    findings: Vec<(Status, String)>,
+
+    /// Performance data, as label=value pairs.
+    perf:     Vec<String>
+}
+
+impl Report {
+    fn new() -> Report {
+        Report { status: Status::Ok, findings: vec![], perf: vec![] }
+    }
+
+    fn find(&mut self, status: Status, what: String) {
+        if self.status < status { self.status = status; }
+        self.findings.push((status, what));
+    }
+
+    fn print(mut self) -> Status {
+        let status = self.status;
+        self.findings.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        let findings: Vec<String> = self.findings.into_iter()
+                                                 .map(|(_, f)| f)
+                                                 .collect();
+
+        println!("BAPS3 {} - {} | {}",
+                 status.label(),
+                 findings.connect("; "),
+                 self.perf.connect(" "));
+        status
+    }
+}
+
+fn parse_secs(s: &str, what: &str) -> Result<Option<f64>, String> {
+    if s.is_empty() {
+        return Ok(None);
+    }
+    match s.parse::<f64>() {
+        Some(n) if 0.0 <= n => Ok(Some(n)),
+        _ => Err(format!("{} should be a number of seconds: {}", what, s))
+    }
+}
+
+/// Maps an error from talking to the server onto a verdict.
+fn error_status(err: &Baps3Error) -> Status {
+    match *err {
+        Baps3Error::BadConfig   { .. } => Status::Unknown,
+        Baps3Error::InvalidPath { .. } => Status::Unknown,
+        _                              => Status::Critical
+    }
+}
+
+/// Connects to the server at `addr` and reads its state, giving up after
+/// `timeout`.
+///
+/// This happens in the background, as neither connecting nor the handshake
+/// has a deadline of its own.  Returns the connection, how long the handshake
+/// took, and the server's state.
+fn connect<L>(log:      L,
+              addr:     String,
+              features: Vec<String>,
+              timeout:  Duration) -> Result<(Baps3<L>, Duration, ServerState), Baps3Error>
+where L: Fn(&str) + Send + 'static {
+    let (tx, rx) = channel();
+
+    Thread::spawn(move || {
+        let features: Vec<&str> = features.iter().map(|f| &**f).collect();
+        let started = clock::get_time();
+        let result  = Baps3::new(log, &*addr, &*features).and_then(|mut b| {
+            let handshake = clock::get_time() - started;
+            read_state(&mut b).map(|s| (b, handshake, s))
+        });
+        let _ = tx.send(result);
+    });
+
+    let mut timer = try!(Timer::new());
+    let expired   = timer.oneshot(timeout);
+
+    select! {
+        res = rx.recv() => res.unwrap_or_else(|_| Err(Baps3Error::HungUp)),
+        _ = expired.recv() => Err(Baps3Error::TimedOut)
+    }
+}
+
+/// Checks that TIME moves on from `state` within `within`, returning
+/// whether it did.
+fn time_advances<L: Fn(&str)>(baps3: &mut Baps3<L>,
+                              state:  &mut ServerState,
+                              within: Duration) -> Result<bool, Baps3Error> {
+    let start    = state.time;
+    let deadline = clock::get_time() + within;
+
+    loop {
+        let now = clock::get_time();
+        if deadline <= now {
+            return Ok(false);
+        }
+        if let Some(msg) = try!(baps3.recv_timeout(deadline - now)) {
+            state.update(&msg);
+            if state.time.is_some() && state.time != start {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+fn check(args: Args) -> Status {
+    let mut report = Report::new();
+
+    let thresholds = (parse_secs(&*args.flag_warning, "-w"),
+                      parse_secs(&*args.flag_critical, "-c"),
+                      parse_secs(&*args.flag_advancing, "-a"),
+                      parse_secs(&*args.flag_timeout, "-T"));
+    let (warning, critical, advancing, timeout) = match thresholds {
+        (Ok(w), Ok(c), Ok(a), Ok(Some(t))) => (w, c, a, t),
+        (Ok(_), Ok(_), Ok(_), Ok(None)) => {
+            report.find(Status::Unknown, "-T should not be empty".to_string());
+            return report.print();
+        },
+        (Err(e), _, _, _) | (_, Err(e), _, _) |
+        (_, _, Err(e), _) | (_, _, _, Err(e)) => {
+            report.find(Status::Unknown, e);
+            return report.print();
+        }
+    };
+
+    let target = match resolve_target(&*args.flag_target) {
+        Ok(t)  => t,
+        Err(e) => {
+            report.find(Status::Unknown, e.to_string());
+            return report.print();
+        }
+    };
+
+    let verbose  = args.flag_verbose;
+    let log      = move |&:s:&str| verbose_logger(verbose, s);
+    let features = args.flag_feature.clone();
+    let within   = Duration::microseconds((timeout * 1000000.0) as i64);
+
+    let (mut baps3, handshake, mut state) =
+        match connect(log, target.addr.clone(), features, within) {
+            Ok(c)  => c,
+            Err(Baps3Error::TimedOut) => {
+                report.find(Status::Critical,
+                            format!("{}: timed out after {}s", target.addr, timeout));
+                return report.print();
+            },
+            Err(e) => {
+                report.find(error_status(&e), format!("{}: {}", target.addr, e));
+                return report.print();
+            }
+        };
+    let handshake = handshake.num_microseconds().map_or(0.0, |us| {
+        us as f64 / 1000000.0
+    });
+
+    report.perf.push(format!("handshake={:.6}s;{};{};0",
+                             handshake,
+                             warning.map_or(String::new(), |w| w.to_string()),
+                             critical.map_or(String::new(), |c| c.to_string())));
+    match (warning, critical) {
+        (_, Some(c)) if c < handshake =>
+            report.find(Status::Critical,
+                        format!("handshake took {:.3}s", handshake)),
+        (Some(w), _) if w < handshake =>
+            report.find(Status::Warning,
+                        format!("handshake took {:.3}s", handshake)),
+        _ => ()
+    }
+
+    let playing = state.state.as_ref().map_or(false, |s| &**s == "Playing");
+    if (args.flag_playing || advancing.is_some()) && !playing {
+        report.find(Status::Critical,
+                    format!("not playing ({})",
+                            state.state.clone().unwrap_or("state unknown".to_string())));
+    }
+    if args.flag_loaded && state.file.is_none() {
+        report.find(Status::Critical, "no file loaded".to_string());
+    }
+
+    if let (Some(a), true) = (advancing, playing) {
+        let within = Duration::microseconds((a * 1000000.0) as i64);
+        match time_advances(&mut baps3, &mut state, within) {
+            Ok(true)  => (),
+            Ok(false) => report.find(Status::Critical,
+                                     format!("TIME stalled for {}s", a)),
+            Err(e)    => report.find(error_status(&e), e.to_string())
+        }
+    }
+
+    if let Some(us) = state.time {
+        report.perf.push(format!("position={:.3}s", us as f64 / 1000000.0));
+    }
+
+    if report.findings.is_empty() {
+        let loaded = match (&state.file, state.time) {
+            (&Some(ref f), Some(us)) => format!(" {} at {}", f, format_micros(us)),
+            (&Some(ref f), None)     => format!(" {}", f),
+            _                        => String::new()
+        };
+        report.find(Status::Ok,
+                    format!("{} {}{}",
+                            state.ident,
+                            state.state.clone().unwrap_or("(state unknown)".to_string()),
+                            loaded));
+    }
+
+    baps3.quit();
+    report.print()
+}
+
+fn main() {
+    let args: Args = match Args::docopt().decode() {
+        Ok(a) => a,
+        // Plugins must report bad usage as UNKNOWN, rather than with
+        // docopt's usual exit status.
+        Err(ref e) if e.fatal() => {
+            println!("BAPS3 UNKNOWN - {}", e);
+            os::set_exit_status(Status::Unknown as isize);
+            return;
+        },
+        Err(e) => e.exit()
+    };
+
+    if !args.flag_completions.is_empty() {
+        match completion::own_script(&*args.flag_completions) {
+            Ok(script) => print!("{}", script),
+            Err(e)     => exit_with(Err(e))
+        }
+        return;
+    }
+
+    os::set_exit_status(check(args) as isize);
+}
@@ -1,68 +1,277 @@
 #![feature(plugin)]
 
 extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
 extern crate libc;
+
+extern crate "rustc-serialize" as rustc_serialize;
 extern crate docopt;
 #[plugin] #[no_link] extern crate docopt_macros;
 
 use std::borrow::ToOwned;
-use std::sync::mpsc::{ channel, Receiver, Select, Sender };
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::RingBuf;
+use std::io::timer::Timer;
+use std::os;
+use std::sync::{ Arc, Mutex };
+use std::sync::mpsc::{ channel, Receiver, Sender };
+use std::time::Duration;
 
+use baps3_cli::{ Baps3, Baps3Error, Baps3Result, acknowledgement,
+                 feature_commands, verbose_logger };
+use baps3_cli::alias::{ Alias, Aliases };
+use baps3_cli::config::{ config_file, default_timeout, resolve_target };
+use baps3_cli::format::Format;
+use baps3_cli::script;
+use baps3_cli::state::ServerState;
+use baps3_cli::transcript::Transcript;
+use baps3_cli::tui::{ Tui, progress_bar };
+use baps3_cli::edit::{ Completer, LineEditor, complete_path, complete_word };
+use baps3_cli::time::format_micros;
 use baps3_protocol::client::{Client, Request, Response};
 use baps3_protocol::proto::{Unpacker, Message};
 use baps3_protocol::util::slicify;
 
-fn commands() {
-    println!("Commands: ");
-    println!("  !c HOST:PORT - connect (if not connected)");
-    println!("  !d           - disconnect (if connected)");
-    println!("  !h           - this help message");
-    println!("  !t           - report current time");
-    println!("  !T           - toggle whether to report time");
-    println!("  !q           - quit");
-    println!("");
-    println!("Anything not prefixed with ! is sent to the server.");
+docopt!(Args, concat!("
+An interactive command-line client for BAPS3 servers.
+
+Usage:
+  baps3-cli -h
+  baps3-cli --completions <shell>
+  baps3-cli [-k] [--tui] [--log <file>]
+  baps3-cli [-kv] [-t <target>] --script <file>
+
+Options:
+  -h, --help             Show this message.
+  -k, --keep-going       Carry on with a script after one of its commands
+                         fails.
+  --tui                  Run full-screen, with a status header above the
+                         message log.  The header shows the time left in
+                         the file for servers that report its LENGTH.
+  --log <file>           Append a timestamped transcript of the session to
+                         <file>.
+  --script <file>        Run the commands in <file> against the target one
+                         by one, waiting for each to be acknowledged, and
+                         then exit.
+", common_options!()));
+
+/// Where the interactive client's output goes.
+enum Screen {
+    /// Straight to standard output, line by line.
+    Plain,
+
+    /// Into the log of a full-screen interface.
+    Full(Tui)
+}
+
+impl Screen {
+    fn say(&mut self, line: &str) {
+        match *self {
+            Screen::Plain       => println!("{}", line),
+            Screen::Full(ref mut t) => t.log(line)
+        }
+    }
+}
+
+macro_rules! say(
+    ($out:expr, $($arg:tt)*) => ( $out.say(&*format!($($arg)*)) )
+);
+
+/// The number of rows in the full-screen interface's header.
+const HEADER_ROWS: usize = 2;
+
+/// The width of the progress bar in the full-screen interface's header.
+const PROGRESS_WIDTH: usize = 20;
+
+/// How long to wait for a script command to be acknowledged, in
+/// milliseconds, if `$BAPS3_TIMEOUT` is unset.
+const ACK_TIMEOUT_MS: i64 = 10000;
+
+fn commands(out: &mut Screen) {
+    say!(out, "Commands: ");
+    say!(out, "  !c [NAME] HOST:PORT - open a session (named HOST:PORT by default)");
+    say!(out, "  !d [NAME]           - close the active (or named) session");
+    say!(out, "  !s NAME             - switch the active session");
+    say!(out, "  !ls                 - list sessions");
+    say!(out, "  !h                  - this help message");
+    say!(out, "  !t                  - report current time");
+    say!(out, "  !T                  - toggle whether to report time");
+    say!(out, "  !q                  - quit");
+    say!(out, "  !source FILE        - run a script, waiting for each command");
+    say!(out, "  !alias [NAME = CMD [; CMD ...]]");
+    say!(out, "                      - list aliases, or define one ($1.. are arguments)");
+    say!(out, "  !unalias NAME       - remove an alias");
+    say!(out, "  !w [FILE]           - write a transcript to FILE (or stop writing one)");
+    say!(out, "  @NAME [COMMAND]     - send COMMAND to the named session (or switch to it)");
+    say!(out, "");
+    say!(out, "Anything not prefixed with ! is sent to the active session.");
+    say!(out, "Tab completes commands, and paths after load.");
+}
+
+/// The meta-commands, for completion.
+const META_COMMANDS: &'static [&'static str] = &[
+    "!c", "!d", "!s", "!ls", "!h", "!t", "!T", "!q", "!source", "!alias",
+    "!unalias", "!w"
+];
+
+/// Completes meta-commands, the commands the active server's features allow,
+/// and paths after `load`.
+struct CliCompleter {
+    /// The features of the active session's server; empty when disconnected.
+    features: Arc<Mutex<Vec<String>>>,
+
+    /// The names of the user's aliases.
+    aliases: Arc<Mutex<Vec<String>>>
+}
+
+impl Completer for CliCompleter {
+    fn complete(&self, line: &str) -> (usize, Vec<String>) {
+        let start = line.rfind(' ').map(|i| i + 1).unwrap_or(0);
+        let word  = &line[start..];
+
+        let candidates = if start == 0 {
+            let features     = self.features.lock().unwrap();
+            let fs: Vec<&str> = features.iter().map(|f| &**f).collect();
+
+            let aliases      = self.aliases.lock().unwrap();
+
+            let mut words = META_COMMANDS.to_vec();
+            words.push_all(&*feature_commands(&*fs));
+            words.extend(aliases.iter().map(|a| &**a));
+            complete_word(word, &*words)
+        } else if line.starts_with("load ")
+               || line.starts_with("!source ")
+               || line.starts_with("!w ") {
+            complete_path(word)
+        } else {
+            vec![]
+        };
+
+        (start, candidates)
+    }
 }
 
 fn main() {
-    let (int_request_tx, int_request_rx) = channel();
+    let args = parse_args!();
 
-    std::thread::Thread::spawn(move || { stdin_loop(int_request_tx)});
+    if args.flag_script.is_empty() {
+        interactive_main(args.flag_keep_going, args.flag_tui, &*args.flag_log);
+    } else {
+        script_main(args);
+    }
+}
 
-    println!("Currently disconnected.");
-    println!("Type !h <newline> for command help");
+/// Runs a script non-interactively, failing if any of it fails.
+fn script_main(Args { flag_keep_going,
+                      flag_script,
+                      flag_target,
+                      flag_verbose, .. }: Args) {
+    let log    = |&:s:&str| verbose_logger(flag_verbose, s);
+    let result = resolve_target(&*flag_target)
+      .and_then(|target| Baps3::new(log, &*target.addr, &[]))
+      .and_then(|mut baps3| {
+          let res = script::run(&Path::new(&*flag_script),
+                                flag_keep_going,
+                                &mut |&mut: msg: &Message| {
+              println!("> {} {:?}", msg.word(), msg.args());
+              baps3.send(msg)
+          });
+          baps3.quit();
+          res
+      });
 
-    'l: for msg in int_request_rx.iter() {
-        match msg {
-            Request::Quit => break,
-            Request::SendMessage(msg) => match msg.as_str_vec().as_slice() {
-                ["!c", dest] => match Client::new(dest) {
-                    Ok(client) => {
-                        let quit = client_main_loop(client, &int_request_rx);
-                        println!("Disconnected");
+    match result {
+        Ok(0)  => (),
+        Ok(n)  => {
+            werr!("{} command(s) failed\n", n);
+            os::set_exit_status(1);
+        },
+        Err(e) => {
+            werr!("error: {}\n", e);
+            os::set_exit_status(1);
+        }
+    }
+}
 
-                        if quit { break 'l };
-                    },
-                    Err(e) => println!("{}", e)
-                },
-                ["!h"] => commands(),
-                ["!q"] => return,
-                _ => println!("can't do that, disconnected!")
+/// Something that happened, for the main loop to handle.
+enum Event {
+    /// The user typed something in.
+    Input(Request),
+
+    /// The server of the session with the given ID said something.
+    Server(usize, Response)
+}
+
+fn interactive_main(keep_going: bool, full_screen: bool, log: &str) {
+    let screen = if full_screen {
+        match Tui::new(HEADER_ROWS) {
+            Ok(t)  => Screen::Full(t),
+            Err(e) => {
+                werr!("error: {}\n", e);
+                os::set_exit_status(1);
+                return;
+            }
+        }
+    } else {
+        Screen::Plain
+    };
+
+    let (event_tx, event_rx) = channel();
+    let features = Arc::new(Mutex::new(vec![]));
+    let aliases  = Arc::new(Mutex::new(vec![]));
+
+    // The rc file is run as if typed in, before anything that actually is.
+    if let Some(rc) = config_file("clirc") {
+        if let Ok(src) = std::io::File::open(&rc).read_to_string() {
+            let mut u = Unpacker::new();
+            for line in src.lines().filter(|l| !l.trim().starts_with("#")) {
+                send_message(&event_tx, &mut u, &*format!("{}\n", line));
             }
         }
     }
 
+    let completer = CliCompleter { features: features.clone(),
+                                   aliases:  aliases.clone() };
+    let input_tx  = event_tx.clone();
+    std::thread::Thread::spawn(move || { stdin_loop(input_tx, completer)});
+
+    let mut cli = Cli::new(screen, event_tx, features, aliases, keep_going);
+    if !log.is_empty() { cli.start_transcript(log); }
+    say!(cli.screen, "Currently disconnected.");
+    say!(cli.screen, "Type !h <newline> for command help");
+    cli.refresh();
+
+    loop {
+        let event = match cli.deferred.pop_front() {
+            Some(e) => e,
+            None    => match event_rx.recv() {
+                Ok(e)  => e,
+                Err(_) => break
+            }
+        };
+
+        if !cli.handle(event, &event_rx) { break; }
+        cli.refresh();
+    }
+
+    cli.close_all();
+    if let Screen::Full(ref mut t) = cli.screen { t.close(); }
     println!("Quitting");
 }
 
 fn stdin_loop(
-    request_tx: Sender<Request>
+    event_tx: Sender<Event>,
+    completer: CliCompleter
 ) {
     let mut u = Unpacker::new();
+    let mut editor = LineEditor::new(config_file("cli_history"));
 
-    for line in std::io::stdin().lock().lines() {
-        match line {
-            Ok(l) => send_message(&request_tx, &mut u, l.as_slice()),
+    loop {
+        match editor.read_line("baps3> ", &completer) {
+            // The unpacker needs the newline to know the line is complete.
+            Ok(Some(l)) => send_message(&event_tx, &mut u, &*format!("{}\n", l)),
+            Ok(None) => break,
             Err(e) => {
                 println!("{}", e);
                 return;
@@ -70,22 +279,48 @@ fn stdin_loop(
         }
     }
 
-    request_tx.send(Request::Quit);
+    event_tx.send(Event::Input(Request::Quit));
 }
 
-fn send_message(tx: &Sender<Request>, unpacker: &mut Unpacker, message: &str) {
+fn send_message(tx: &Sender<Event>, unpacker: &mut Unpacker, message: &str) {
     for pline in unpacker.feed(message.as_slice()).iter() {
         if let [ref cmd, args..] = pline.as_slice() {
             let mut msg = Message::new(&**cmd);
             for arg in args.iter() {
                 msg = msg.arg(&**arg);
             }
-            tx.send(Request::SendMessage(msg));
+            tx.send(Event::Input(Request::SendMessage(msg)));
         }
     }
 }
 
+/// Forwards everything a session's server says to the main loop.
+fn pump(id: usize, response_rx: Receiver<Response>, event_tx: Sender<Event>) {
+    for response in response_rx.iter() {
+        if let Err(_) = event_tx.send(Event::Server(id, response)) {
+            return;
+        }
+    }
+
+    // Make sure the main loop hears about the session going away, even if
+    // the client didn't say so.
+    let _ = event_tx.send(Event::Server(id, Response::Gone));
+}
+
+/// One connection to a BAPS3 server.
 struct CliClient {
+    /// A unique number identifying this session's connection.
+    id: usize,
+
+    /// The name the user gave this session.
+    name: String,
+
+    /// The address of the server.
+    addr: String,
+
+    /// What we know of the server's state.
+    server: ServerState,
+
     /// The last time-stamp reported by the server.
     last_time: String,
 
@@ -98,28 +333,37 @@ struct CliClient {
 
 impl CliClient {
     /// Creates a new CliClient.
-    fn new(tx: &Sender<Request>) -> CliClient {
-        CliClient { last_time:   "0:00".to_owned(),
-                    report_time: true,
+    fn new(id: usize,
+           name: &str,
+           addr: &str,
+           tx: &Sender<Request>,
+           report_time: bool) -> CliClient {
+        CliClient { id:          id,
+                    name:        name.to_owned(),
+                    addr:        addr.to_owned(),
+                    server:      ServerState::new("", &[]),
+                    last_time:   "0:00".to_owned(),
+                    report_time: report_time,
                     tx:          tx.clone() }
     }
 
     /// Toggles whether to report time.
-    fn toggle_time(&mut self) {
+    fn toggle_time(&mut self, out: &mut Screen) {
         self.report_time = !self.report_time;
-        println!("i time reporting: {}",
-                 if self.report_time { "on" } else { "off" });
+        say!(out, "{}: i time reporting: {}",
+             self.name,
+             if self.report_time { "on" } else { "off" });
     }
 
     /// Reports the current time.
-    fn report_time(&self) {
-        println!("T {}", self.last_time);
+    fn report_time(&self, out: &mut Screen) {
+        say!(out, "{}: T {}", self.name, self.last_time);
     }
 
     /// Handles a TIME notification for this CliClient.
-    fn time(&mut self, t: &str) {
+    fn time(&mut self, t: &str, out: &mut Screen) {
         if let Some(ti) = t.parse::<i64>() {
-            let d = std::time::Duration::microseconds(ti);
+            let d = Duration::microseconds(ti);
             let s = format!("{}{:02}:{:02}",
                 if 0 < d.num_hours() { format!("{}:", d.num_hours()) }
                 else                 { String::new()                 },
@@ -127,79 +371,496 @@ impl CliClient {
                 d.num_seconds() % 60);
             if s != self.last_time {
                 self.last_time = s;
-                if self.report_time { self.report_time() };
+                if self.report_time { self.report_time(out) };
             }
         }
     }
 
-    fn forward(&self, word: &str, args: &[&str]) {
-        println!("> {} {:?}", word, args);
+    /// Handles a message from the server.
+    fn message(&mut self, msg: &Message, out: &mut Screen) {
+        self.server.update(msg);
+
+        match &*msg.as_str_vec() {
+            [ "TIME", t ] => self.time(t, out),
+            [ word, args.. ] => say!(out, "{}: < {} {:?}", self.name, word, args),
+            [] => ()
+        }
+    }
+
+    /// Describes the session for the full-screen interface's header.
+    fn header(&self, sessions: usize) -> Vec<String> {
+        vec![
+            format!(" {} - {} ({}), {} session(s)",
+                    self.name,
+                    if self.server.ident.is_empty() { "?" }
+                    else { &*self.server.ident },
+                    self.addr,
+                    sessions),
+            format!(" {:8} {:>8}{}  {}",
+                    self.server.state.clone().unwrap_or("?".to_owned()),
+                    self.last_time,
+                    self.progress(),
+                    self.server.file.clone().unwrap_or("(no file)".to_owned()))
+        ]
+    }
+
+    /// Describes how far through the file the server is, for the header, if
+    /// the server has told us how long the file is.
+    fn progress(&self) -> String {
+        match self.server.length {
+            Some(length) => {
+                let time = self.server.time.unwrap_or(0);
+                format!(" / {}  -{} {}",
+                        format_micros(length),
+                        format_micros(length - cmp::min(time, length)),
+                        progress_bar(time, length, PROGRESS_WIDTH))
+            },
+            None => String::new()
+        }
+    }
+
+    /// Sends a command to the server, returning the message sent.
+    fn forward(&self, word: &str, args: &[&str], out: &mut Screen) -> Message {
+        say!(out, "{}: > {} {:?}", self.name, word, args);
 
         let mut msg = Message::new(word);
         for arg in args.iter() {
             msg = msg.arg(*arg)
         }
 
-        self.tx.send(Request::SendMessage(msg));
+        self.tx.send(Request::SendMessage(msg.clone()));
+        msg
     }
 
-    fn quit(&self, entire_program: bool) -> bool {
+    fn quit(&self) {
         self.tx.send(Request::Quit);
-        entire_program
     }
 }
 
-/// Returns true if the outer main loop must exit.
-fn client_main_loop(Client {
-    request_tx,
-    response_rx
-}: Client, int_request_rx: &Receiver<Request>) -> bool {
-    let mut state = CliClient::new(&request_tx);
+/// The state of the interactive client: a set of sessions, one of which may
+/// be active.
+struct Cli {
+    sessions: Vec<CliClient>,
 
-    let sel = Select::new();
+    /// Where output goes.
+    screen: Screen,
 
-    let mut reqh = sel.handle(int_request_rx);
-    unsafe { reqh.add(); }
+    /// The name of the session commands go to by default.
+    active: Option<String>,
 
-    let mut resh = sel.handle(&response_rx);
-    unsafe { resh.add(); }
+    /// The ID to give the next session.
+    next_id: usize,
 
-    loop {
-        let id = sel.wait();
-        if id == reqh.id() {
-            match reqh.recv() {
-                Ok(Request::SendMessage(msg)) =>
-                  match msg.as_str_vec().as_slice() {
-                    ["!d"]         => return state.quit(false),
-                    ["!h"]         => commands(),
-                    ["!q"]         => return state.quit(true),
-                    ["!t"]         => state.report_time(),
-                    ["!T"]         => state.toggle_time(),
-                    [word, args..] => state.forward(word, args),
-                    []             => ()
+    /// Events that arrived while we were busy, to handle before any others.
+    deferred: RingBuf<Event>,
+
+    /// Where sessions send their servers' responses.
+    event_tx: Sender<Event>,
+
+    /// The features of the active session, shared with the completer.
+    features: Arc<Mutex<Vec<String>>>,
+
+    /// The user's aliases.
+    aliases: Aliases,
+
+    /// The names of the user's aliases, shared with the completer.
+    alias_names: Arc<Mutex<Vec<String>>>,
+
+    /// The transcript being written, if any.
+    transcript: Option<Transcript>,
+
+    /// Whether scripts carry on after a command fails.
+    keep_going: bool
+}
+
+impl Cli {
+    fn new(screen: Screen,
+           event_tx: Sender<Event>,
+           features: Arc<Mutex<Vec<String>>>,
+           alias_names: Arc<Mutex<Vec<String>>>,
+           keep_going: bool) -> Cli {
+        Cli { sessions:    vec![],
+              screen:      screen,
+              active:      None,
+              next_id:     0,
+              deferred:    RingBuf::new(),
+              event_tx:    event_tx,
+              features:    features,
+              aliases:     Aliases::new(),
+              alias_names: alias_names,
+              transcript:  None,
+              keep_going:  keep_going }
+    }
+
+    /// Handles an event.  Returns false if the program should quit.
+    fn handle(&mut self, event: Event, event_rx: &Receiver<Event>) -> bool {
+        match event {
+            Event::Input(Request::Quit) => return false,
+            Event::Input(Request::SendMessage(msg)) => {
+                if msg.word().starts_with("!") {
+                    let active  = self.active.clone().unwrap_or(String::new());
+                    let command = Format::Raw.message(&msg);
+                    self.record(|t| t.meta(&*active, &*command));
+                }
+
+                match msg.as_str_vec().as_slice() {
+                    ["!c", dest]       => self.open(dest, dest),
+                    ["!c", name, dest] => self.open(name, dest),
+                    ["!d"]             => match self.active.clone() {
+                        Some(name) => self.close(&*name),
+                        None       => say!(self.screen, "can't do that, disconnected!")
+                    },
+                    ["!d", name]       => self.close(name),
+                    ["!w"]             => self.stop_transcript(),
+                    ["!w", file]       => self.start_transcript(file),
+                    ["!s", name]       => self.switch(name),
+                    ["!ls"]            => self.list(),
+                    ["!h"]             => commands(&mut self.screen),
+                    ["!q"]             => return false,
+                    ["!t"]             => { self.with_active(|c, o| c.report_time(o)); },
+                    ["!T"]             => { self.with_active(|c, o| c.toggle_time(o)); },
+                    ["!source", f]     => self.source(f, event_rx),
+                    ["!alias"]         => for a in self.aliases.iter() {
+                        say!(self.screen, "i alias {}", a.definition());
+                    },
+                    ["!alias", name, "=", body..] => self.define(name, body),
+                    ["!unalias", name] => if self.aliases.remove(name) {
+                        *self.alias_names.lock().unwrap() = self.aliases.names();
+                    } else {
+                        say!(self.screen, "no alias named {}", name);
+                    },
+                    [to] if to.starts_with("@") => self.switch(&to[1..]),
+                    [to, word, args..] if to.starts_with("@") => {
+                        let sent = {
+                            let screen = &mut self.screen;
+                            match self.sessions.iter_mut()
+                                               .find(|c| &*c.name == &to[1..]) {
+                                Some(c) => Some(c.forward(word, args, screen)),
+                                None    => {
+                                    say!(screen, "no session named {}", &to[1..]);
+                                    None
+                                }
+                            }
+                        };
+                        if let Some(m) = sent {
+                            self.record(|t| t.outgoing(&to[1..], &m));
+                        }
+                    },
+                    [word, args..] if self.aliases.get(word).is_some() =>
+                        self.alias(word, args, event_rx),
+                    [word, args..]     => {
+                        let sent = self.with_active(|c, o| {
+                            (c.name.clone(), c.forward(word, args, o))
+                        });
+                        if let Some((name, m)) = sent {
+                            self.record(|t| t.outgoing(&*name, &m));
+                        }
+                    },
+                    []                 => ()
+                }
+            },
+            Event::Server(id, response) => self.response(id, response)
+        }
+
+        true
+    }
+
+    /// Handles a response from the server of the session with ID `id`.
+    fn response(&mut self, id: usize, response: Response) {
+        let name = match self.sessions.iter_mut().find(|c| c.id == id) {
+            // Responses from sessions we've already closed don't matter.
+            None => return,
+            Some(c) => match response {
+                Response::Message(m) => {
+                    if let Some(ref mut t) = self.transcript {
+                        t.incoming(&*c.name, &m);
+                    }
+                    c.message(&m, &mut self.screen);
+                    if self.active.as_ref() != Some(&c.name) { return; }
+
+                    // The active session's features may have just changed.
+                    *self.features.lock().unwrap() = c.server.features.clone();
+                    return;
                 },
-                Ok(req) => if let Err(_) = request_tx.send(req) {
-                    // The client has disappeared, so disconnect.
-                    return false;
+                Response::ClientError(e) => {
+                    say!(self.screen, "{}: ! {}", c.name, e);
+                    if let Some(ref mut t) = self.transcript {
+                        t.event(&*c.name, &*format!("error: {}", e));
+                    }
+                    c.name.clone()
                 },
-                Err(_) => return false
+                Response::Gone => c.name.clone()
             }
-        } else {
-            match resh.recv() {
-                Ok(Response::Gone) => return false,
-                Ok(Response::ClientError(e)) => {
-                    println!("! {}", e);
-                    return false;
-                },
-                Ok(Response::Message(m)) => match &*m.as_str_vec() {
-                    [ "TIME", t ] => state.time(t),
-                    [ word, args.. ] => println!("< {} {:?}", word, args),
-                    [] => ()
-                },
-                Err(_) => {
-                    return false;
-                }
+        };
+
+        self.remove(&*name);
+        say!(self.screen, "{}: Disconnected", name);
+        self.record(|t| t.event(&*name, "server disconnected"));
+    }
+
+    /// Opens a new session called `name` to `dest`, and makes it active.
+    fn open(&mut self, name: &str, dest: &str) {
+        if self.session(name).is_some() {
+            say!(self.screen, "already have a session named {}", name);
+            return;
+        }
+
+        match Client::new(dest) {
+            Ok(Client { request_tx, response_rx }) => {
+                let id       = self.next_id;
+                let event_tx = self.event_tx.clone();
+                self.next_id += 1;
+
+                std::thread::Thread::spawn(move || {
+                    pump(id, response_rx, event_tx)
+                });
+
+                // The header shows the time in full-screen mode.
+                let report_time = match self.screen {
+                    Screen::Plain   => true,
+                    Screen::Full(_) => false
+                };
+                self.sessions.push(CliClient::new(id, name, dest, &request_tx,
+                                                  report_time));
+                self.record(|t| t.event(name, &*format!("connected to {}", dest)));
+                self.switch(name);
+            },
+            Err(e) => say!(self.screen, "{}", e)
+        }
+    }
+
+    /// Closes the session called `name`.
+    fn close(&mut self, name: &str) {
+        match self.session(name) {
+            Some(c) => c.quit(),
+            None    => {
+                say!(self.screen, "no session named {}", name);
+                return;
+            }
+        }
+
+        self.remove(name);
+        say!(self.screen, "{}: Disconnected", name);
+        self.record(|t| t.event(name, "disconnected"));
+    }
+
+    /// Closes every session.
+    fn close_all(&mut self) {
+        for c in self.sessions.iter() {
+            c.quit();
+        }
+        self.sessions.clear();
+    }
+
+    /// Forgets about the session called `name`.
+    fn remove(&mut self, name: &str) {
+        self.sessions.retain(|c| &*c.name != name);
+
+        if self.active.as_ref().map(|a| &**a) == Some(name) {
+            self.active = None;
+            self.features.lock().unwrap().clear();
+        }
+    }
+
+    /// Makes the session called `name` active.
+    fn switch(&mut self, name: &str) {
+        let features = match self.session(name) {
+            Some(c) => c.server.features.clone(),
+            None    => {
+                say!(self.screen, "no session named {}", name);
+                return;
+            }
+        };
+
+        self.active = Some(name.to_owned());
+        *self.features.lock().unwrap() = features;
+        say!(self.screen, "i active session: {}", name);
+    }
+
+    /// Lists the sessions, marking the active one.
+    fn list(&mut self) {
+        if self.sessions.is_empty() {
+            say!(self.screen, "i no sessions");
+        }
+
+        for c in self.sessions.iter() {
+            say!(self.screen, "{} {} ({})",
+                     if self.active.as_ref() == Some(&c.name) { "*" } else { " " },
+                     c.name,
+                     c.addr);
+        }
+    }
+
+    /// Finds the session called `name`.
+    fn session(&mut self, name: &str) -> Option<&mut CliClient> {
+        self.sessions.iter_mut().find(|c| &*c.name == name)
+    }
+
+    /// Runs `f` on the active session, if any, returning its result.
+    fn with_active<R, F>(&mut self, f: F) -> Option<R>
+    where F: FnOnce(&mut CliClient, &mut Screen) -> R {
+        let screen = &mut self.screen;
+
+        match self.active.clone() {
+            Some(name) => self.sessions.iter_mut()
+                                       .find(|c| c.name == name)
+                                       .map(|c| f(c, screen)),
+            None       => {
+                say!(screen, "can't do that, disconnected!");
+                None
+            }
+        }
+    }
+
+    /// Runs `f` on the transcript, if one is being written.
+    fn record<F: FnOnce(&mut Transcript)>(&mut self, f: F) {
+        if let Some(ref mut t) = self.transcript { f(t) }
+    }
+
+    /// Starts writing a transcript to `file`, stopping any other.
+    fn start_transcript(&mut self, file: &str) {
+        self.stop_transcript();
+
+        match Transcript::open(&Path::new(file)) {
+            Ok(t)  => {
+                self.transcript = Some(t);
+                self.record(|t| t.event("", "transcript started"));
+                say!(self.screen, "i writing transcript to {}", file);
+            },
+            Err(e) => say!(self.screen, "! {}: {}", file, e)
+        }
+    }
+
+    /// Stops writing the transcript, if any.
+    fn stop_transcript(&mut self) {
+        self.record(|t| t.event("", "transcript stopped"));
+        self.transcript = None;
+    }
+
+    /// Brings the full-screen interface's header up to date.
+    fn refresh(&mut self) {
+        let sessions = self.sessions.len();
+        let header   = match self.active.clone().and_then(|n| self.session(&*n)) {
+            Some(c) => c.header(sessions),
+            None    => vec![format!(" Disconnected, {} session(s)", sessions)]
+        };
+
+        if let Screen::Full(ref mut t) = self.screen {
+            t.set_header(header);
+        }
+    }
+
+    /// Defines an alias.
+    fn define(&mut self, name: &str, body: &[&str]) {
+        match Alias::new(name, body) {
+            Some(a) => {
+                self.aliases.define(a);
+                *self.alias_names.lock().unwrap() = self.aliases.names();
+            },
+            None => say!(self.screen, "alias {} needs at least one command", name)
+        }
+    }
+
+    /// Runs a script on the active session, waiting for each command to be
+    /// acknowledged.
+    fn source(&mut self, file: &str, event_rx: &Receiver<Event>) {
+        let keep_going = self.keep_going;
+        self.synchronously(event_rx, |send, report| {
+            script::run_reporting(&Path::new(file), keep_going, send, report)
+        });
+    }
+
+    /// Expands an alias and runs the result on the active session, waiting
+    /// for each command to be acknowledged.
+    fn alias(&mut self, name: &str, args: &[&str], event_rx: &Receiver<Event>) {
+        let lines = match self.aliases.get(name).unwrap().expand(args) {
+            Ok(l)  => l,
+            Err(e) => {
+                say!(self.screen, "! {}", e);
+                return;
             }
+        };
+
+        // Aliases stand in for single commands, so always stop at a failure.
+        self.synchronously(event_rx, |send, report| {
+            script::run_lines_reporting(&*format!("alias {}", name),
+                                        lines,
+                                        false,
+                                        send,
+                                        report)
+        });
+    }
+
+    /// Runs `f` with a function that sends a command to the active session
+    /// and waits for its acknowledgement, and a function that reports a
+    /// failure `f` carried on past.
+    ///
+    /// Each command is given up on with `TimedOut` if it is not acknowledged
+    /// within `$BAPS3_TIMEOUT`, or `ACK_TIMEOUT_MS` if that is unset.
+    /// Anything else that happens while `f` runs is handled once it
+    /// finishes.
+    fn synchronously<F>(&mut self, event_rx: &Receiver<Event>, f: F)
+    where F: FnOnce(&mut FnMut(&Message) -> Baps3Result<()>,
+                    &mut FnMut(&Baps3Error)) -> Baps3Result<usize> {
+        let (id, name, tx) = match self.active.clone().and_then(|n| self.session(&*n)) {
+            Some(c) => (c.id, c.name.clone(), c.tx.clone()),
+            None    => {
+                say!(self.screen, "can't do that, disconnected!");
+                return;
+            }
+        };
+        let timeout = default_timeout()
+            .unwrap_or(Duration::milliseconds(ACK_TIMEOUT_MS));
+
+        // These borrows must end before the result is reported below.
+        let result = {
+            let deferred   = &mut self.deferred;
+            let screen     = RefCell::new(&mut self.screen);
+            let transcript = &mut self.transcript;
+
+            f(&mut |&mut: msg: &Message| {
+                say!(screen.borrow_mut(), "{}: > {} {:?}", name, msg.word(), msg.args());
+                if let Some(ref mut t) = *transcript { t.outgoing(&*name, msg); }
+                try!(tx.send(Request::SendMessage(msg.clone())));
+
+                let mut timer = try!(Timer::new());
+                let expired   = timer.oneshot(timeout);
+
+                loop {
+                    let ev = select! {
+                        ev = event_rx.recv() => try!(ev),
+                        _ = expired.recv() => return Err(Baps3Error::TimedOut)
+                    };
+
+                    match ev {
+                        Event::Server(sid, Response::Message(m)) if sid == id => {
+                            if let Some(r) = acknowledgement(msg.word(),
+                                                             &*msg.args(),
+                                                             &m) {
+                                say!(screen.borrow_mut(), "{}: < {} {:?}",
+                                     name, m.word(), m.args());
+                                if let Some(ref mut t) = *transcript {
+                                    t.incoming(&*name, &m);
+                                }
+                                return r;
+                            }
+                            deferred.push_back(Event::Server(sid, Response::Message(m)));
+                        },
+                        Event::Server(sid, r) if sid == id => {
+                            deferred.push_back(Event::Server(sid, r));
+                            return Err(Baps3Error::HungUp);
+                        },
+                        e => deferred.push_back(e)
+                    }
+                }
+            },
+            &mut |&mut: e: &Baps3Error| say!(screen.borrow_mut(), "! {}", e))
+        };
+
+        match result {
+            Ok(0)  => (),
+            Ok(n)  => say!(self.screen, "i {} command(s) failed", n),
+            Err(e) => say!(self.screen, "! {}", e)
         }
     }
 }
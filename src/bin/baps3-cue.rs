@@ -0,0 +1,132 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::borrow::ToOwned;
+
+use baps3_cli::{ Baps3, Baps3Error, Baps3Result, verbose_logger };
+use baps3_cli::config::resolve_target;
+use baps3_cli::cue::CueStore;
+use baps3_cli::path::{ command_maps, server_path };
+use baps3_cli::state::{ ServerState, read_state };
+use baps3_cli::time::format_micros;
+use baps3_protocol::proto::Message;
+
+docopt!(Args, concat!("
+Sets, lists and seeks to named cue points in files played by a BAPS3 server.
+
+Usage:
+  baps3-cue -h
+  baps3-cue --completions <shell>
+  baps3-cue set [-v] [-t <target>] <name>
+  baps3-cue go [-pv] [-t <target>] <name>
+  baps3-cue list [-v] [-t <target>] [-m <map>...] [<file>]
+  baps3-cue remove [-v] [-t <target>] [-m <map>...] [-f <file>] <name>
+
+Commands:
+  set                    Set the cue <name> in the loaded file to the
+                         server's current position.
+  go                     Seek to the cue <name> in the loaded file.
+  list                   List the cues in <file>, or in the loaded file.
+  remove                 Forget the cue <name> in the given file, or in
+                         the loaded file.
+
+Options:
+  -h, --help             Show this message.
+  -f, --file <file>      The file to remove a cue from, rather than the
+                         file loaded in the server.
+  -m, --map <map>        Rewrites paths beginning with CLIENT to begin with
+                         SERVER instead, given as CLIENT=SERVER.  Applied
+                         before any rules for the target.
+  -p, --play             Play the file after seeking to the cue.
+", common_options!()));
+
+/// Returns the file the server has loaded.
+fn loaded_file(state: &ServerState) -> Baps3Result<String> {
+    state.file.clone().ok_or(Baps3Error::CmdFailed {
+        advice: "no file is loaded".to_owned()
+    })
+}
+
+/// Works out which file a command is about: `file` if given, mapped as
+/// `baps3-load` would, or else the file the server has loaded.
+fn cue_file<L: Fn(&str)>(log: L, args: &Args, file: &str) -> Baps3Result<String> {
+    let target = try!(resolve_target(&*args.flag_target));
+
+    if file.is_empty() {
+        let mut baps3 = try!(Baps3::new(log, &*target.addr, &["FileLoad"]));
+        let state     = try!(read_state(&mut baps3));
+        baps3.quit();
+        loaded_file(&state)
+    } else {
+        let maps = try!(command_maps(&*args.flag_map, &target));
+        server_path(file, &*maps)
+    }
+}
+
+fn cue(args: Args) -> Baps3Result<()> {
+    let log       = |&:s:&str| verbose_logger(args.flag_verbose, s);
+    let mut store = try!(CueStore::load());
+
+    if args.cmd_list {
+        let file = try!(cue_file(log, &args, &*args.arg_file));
+        for c in store.for_file(&*file).iter() {
+            println!("{:>10}  {}", format_micros(c.micros), c.name);
+        }
+        return Ok(());
+    }
+
+    if args.cmd_remove {
+        let file = try!(cue_file(log, &args, &*args.flag_file));
+        if !store.remove(&*file, &*args.arg_name) {
+            return Err(Baps3Error::CmdInvalid {
+                advice: format!("no cue {:?} in {}", args.arg_name, file)
+            });
+        }
+        return store.save();
+    }
+
+    let target    = try!(resolve_target(&*args.flag_target));
+    let features  = if args.cmd_go && args.flag_play { vec!["Seek", "PlayStop"] }
+                    else if args.cmd_go              { vec!["Seek"]             }
+                    else                             { vec!["FileLoad"]         };
+    let mut baps3 = try!(Baps3::new(log, &*target.addr, &*features));
+    let state     = try!(read_state(&mut baps3));
+    let file      = try!(loaded_file(&state));
+
+    let result = if args.cmd_set {
+        match state.time {
+            Some(us) => {
+                verbose_logger(args.flag_verbose,
+                               &*format!("cue {} in {} at {}",
+                                         args.arg_name, file, format_micros(us)));
+                store.set(&*file, &*args.arg_name, us).and_then(|_| store.save())
+            },
+            None => Err(Baps3Error::CmdFailed {
+                advice: "server has not reported its position".to_owned()
+            })
+        }
+    } else {
+        match store.get(&*file, &*args.arg_name).map(|c| c.micros) {
+            Some(us) => baps3.send(&Message::new("seek").arg(&*us.to_string()))
+                             .and_then(|_| if args.flag_play {
+                                 baps3.send(&Message::new("play"))
+                             } else {
+                                 Ok(())
+                             }),
+            None => Err(Baps3Error::CmdInvalid {
+                advice: format!("no cue {:?} in {}", args.arg_name, file)
+            })
+        }
+    };
+
+    baps3.quit();
+    result
+}
+
+command_main!(cue);
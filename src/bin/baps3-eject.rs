@@ -0,0 +1,31 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use baps3_cli::{ exit_with, one_shot_target };
+use baps3_protocol::proto::Message;
+
+docopt!(Args, concat!("
+Unloads the currently loaded file from a BAPS3 server.
+
+Usage:
+  baps3-eject -h
+  baps3-eject --completions <shell>
+  baps3-eject [-v] [-t <target>]
+
+Options:
+  -h, --help             Show this message.
+", common_options!()));
+
+fn main() {
+    let args = parse_args!();
+    exit_with(one_shot_target(&*args.flag_target,
+                              args.flag_verbose,
+                              &["FileLoad"],
+                              &[Message::new("eject")]));
+}
@@ -0,0 +1,277 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate "time" as clock;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use clock::Timespec;
+
+use baps3_protocol::proto::Message;
+
+use baps3_cli::{ Baps3Error, Baps3Result };
+use baps3_cli::config::resolve_target;
+use baps3_cli::format::timestamp;
+use baps3_cli::http::{ self, Request, Response };
+use baps3_cli::metrics::Metrics;
+use baps3_cli::server::message_from_words;
+use baps3_cli::session::Session;
+use baps3_cli::state::ServerState;
+
+docopt!(Args, concat!("
+Exports metrics about BAPS3 servers for Prometheus.
+
+Usage:
+  baps3-exporter -h
+  baps3-exporter --completions <shell>
+  baps3-exporter [-v] [-L <addr>] [-p <command>] [-i <secs>] [-r <secs>] <target>...
+
+Each <target> is the name of a configured target, or a host:port.  A
+connection is kept open to each, and metrics about them all are served at
+/metrics.
+
+Command round-trip latency is measured by sending the -p command to each
+server every -i seconds; without -p, no commands are sent.  FAIL and WHAT
+responses are counted by command word, both for the probe command and for
+any the server sends to all clients.
+
+Options:
+  -h, --help             Show this message.
+  -i, --interval <secs>  How often to send the probe command.
+                         [Default: 10]
+  -L, --listen <addr>    The address to serve metrics on.
+                         [Default: localhost:9350]
+  -p, --probe <command>  A command to time, such as dump, if the server
+                         supports it.
+  -r, --retry <secs>     How long to wait before reconnecting when a server
+                         goes away.
+                         [Default: 5]
+", common_options!(untargeted)), flag_interval: i64, flag_retry: i64);
+
+/// How long we wait for each server in one go, in milliseconds, before
+/// moving on to the next server and checking for scrapes.
+const TICK_MS: i64 = 20;
+
+fn seconds(d: Duration) -> f64 {
+    d.num_microseconds().map_or(0.0, |us| us as f64 / 1000000.0)
+}
+
+/// One of the servers we watch, and what we know about it.
+struct Target {
+    name:       String,
+    session:    Session,
+    next_probe: Timespec,
+
+    /// The round-trip time of each probe so far, summed, and how many.
+    probe_sum:   f64,
+    probe_count: u64,
+
+    /// FAIL and WHAT responses seen, by response and command word.
+    refusals:   BTreeMap<(String, String), u64>
+}
+
+impl Target {
+    fn new(name: &str, session: Session) -> Target {
+        Target { name:        name.to_string(),
+                 session:     session,
+                 next_probe:  clock::get_time(),
+                 probe_sum:   0.0,
+                 probe_count: 0,
+                 refusals:    BTreeMap::new() }
+    }
+
+    fn count_refusal(&mut self, response: &str, word: &str) {
+        let n = self.refusals.entry((response.to_string(), word.to_string()))
+                             .get()
+                             .unwrap_or_else(|v| v.insert(0));
+        *n += 1;
+    }
+
+    /// Updates the mirror with a message from the server.
+    fn notify(&mut self, msg: &Message) {
+        self.session.update(msg);
+
+        match msg.as_str_vec().as_slice() {
+            [r, _, word, ..] if r == "FAIL" || r == "WHAT" =>
+                self.count_refusal(r, word),
+            _ => ()
+        }
+    }
+
+    /// Waits briefly for a message from the server.
+    fn poll(&mut self) {
+        match self.session.recv_timeout(Duration::milliseconds(TICK_MS)) {
+            Ok(Some(msg)) => self.notify(&msg),
+            Ok(None)      => (),
+            Err(e)        => werr!("{} {}: {}\n", timestamp(), self.session.addr(), e)
+        }
+    }
+
+    /// Sends the probe command and times it, if it is due.
+    fn probe(&mut self, cmd: &Message, interval: Duration) {
+        let now = clock::get_time();
+        if now < self.next_probe || self.session.get().is_none() {
+            return;
+        }
+        self.next_probe = now + interval;
+
+        let result = self.session.send_collecting(cmd);
+        let rtt    = clock::get_time() - now;
+        match result {
+            Ok(others) => for m in others.iter() { self.notify(m); },
+            Err(Baps3Error::CmdFailed  { .. }) =>
+                self.count_refusal("FAIL", cmd.word()),
+            Err(Baps3Error::CmdInvalid { .. }) =>
+                self.count_refusal("WHAT", cmd.word()),
+            Err(e) => {
+                werr!("{} {}: {}\n", timestamp(), self.session.addr(), e);
+                return;
+            }
+        }
+        self.probe_sum   += seconds(rtt);
+        self.probe_count += 1;
+    }
+}
+
+/// Renders the metrics for all targets.
+fn metrics(targets: &mut [Target]) -> String {
+    let mut m = Metrics::new();
+
+    m.family("baps3_up", "gauge", "Whether the server is connected.");
+    for t in targets.iter_mut() {
+        let up = t.session.get().is_some();
+        m.sample("baps3_up", &[("target", &*t.name)], if up { 1.0 } else { 0.0 });
+    }
+
+    m.family("baps3_handshake_seconds", "gauge",
+             "How long the most recent handshake with the server took.");
+    for t in targets.iter() {
+        if let Some(d) = t.session.handshake {
+            m.sample("baps3_handshake_seconds",
+                     &[("target", &*t.name)],
+                     seconds(d));
+        }
+    }
+
+    m.family("baps3_command_seconds", "summary",
+             "Round-trip time of the probe command.");
+    for t in targets.iter() {
+        let l: &[(&str, &str)] = &[("target", &*t.name)];
+        m.sample("baps3_command_seconds_sum", l, t.probe_sum);
+        m.sample("baps3_command_seconds_count", l, t.probe_count as f64);
+    }
+
+    m.family("baps3_reconnects_total", "counter",
+             "How many times the server has been reconnected to.");
+    for t in targets.iter() {
+        let reconnects = if t.session.connects == 0 { 0 }
+                         else { t.session.connects - 1 };
+        m.sample("baps3_reconnects_total",
+                 &[("target", &*t.name)],
+                 reconnects as f64);
+    }
+
+    m.family("baps3_refusals_total", "counter",
+             "FAIL and WHAT responses, by response and command word.");
+    for t in targets.iter() {
+        for (&(ref r, ref w), n) in t.refusals.iter() {
+            m.sample("baps3_refusals_total",
+                     &[("target", &*t.name), ("response", &**r), ("command", &**w)],
+                     *n as f64);
+        }
+    }
+
+    // Only connected servers have a mirror, so nothing here goes stale while
+    // a server is down.
+    let states: Vec<(&str, &ServerState)> = targets.iter()
+                                                   .filter_map(|t| {
+                                                       t.session.state()
+                                                                .map(|s| (&*t.name, s))
+                                                   })
+                                                   .collect();
+
+    m.family("baps3_info", "gauge", "The server's identifier, as a label.");
+    for &(name, s) in states.iter() {
+        m.sample("baps3_info", &[("target", name), ("ident", &*s.ident)], 1.0);
+    }
+
+    m.family("baps3_feature", "gauge", "The server's features, one per label.");
+    for &(name, s) in states.iter() {
+        for f in s.features.iter() {
+            m.sample("baps3_feature", &[("target", name), ("feature", &**f)], 1.0);
+        }
+    }
+
+    m.family("baps3_playing", "gauge", "Whether the server is playing.");
+    for &(name, s) in states.iter() {
+        let playing = s.state.as_ref().map_or(false, |st| &**st == "Playing");
+        m.sample("baps3_playing",
+                 &[("target", name)],
+                 if playing { 1.0 } else { 0.0 });
+    }
+
+    m.family("baps3_position_seconds", "gauge",
+             "The server's position in the loaded file.");
+    for &(name, s) in states.iter() {
+        if let Some(us) = s.time {
+            m.sample("baps3_position_seconds",
+                     &[("target", name)],
+                     us as f64 / 1000000.0);
+        }
+    }
+
+    m.finish()
+}
+
+fn respond(targets: &mut [Target], req: &Request) -> Response {
+    match (&*req.method, &*req.path) {
+        ("GET", "/metrics") => Response {
+            status:       200,
+            content_type: "text/plain; version=0.0.4",
+            headers:      vec![],
+            body:         metrics(targets)
+        },
+        (_, "/metrics") => Response::text(405, "use GET"),
+        _               => Response::text(404, "try /metrics")
+    }
+}
+
+fn run(args: Args) -> Baps3Result<()> {
+    let words: Vec<String> = args.flag_probe.words().map(|w| w.to_string()).collect();
+    let probe = message_from_words(&*words);
+    let interval = Duration::seconds(args.flag_interval);
+
+    let mut targets = vec![];
+    for t in args.arg_target.iter() {
+        let target = try!(resolve_target(&**t));
+        targets.push(Target::new(&**t, Session::new(&*target.addr,
+                                                    &[],
+                                                    Duration::seconds(args.flag_retry),
+                                                    args.flag_verbose)));
+    }
+
+    let (tx, rx) = channel();
+    try!(http::serve(&*args.flag_listen, tx));
+
+    loop {
+        for t in targets.iter_mut() {
+            t.poll();
+            if let Some(ref cmd) = probe {
+                t.probe(cmd, interval);
+            }
+        }
+
+        while let Ok((req, mut stream)) = rx.try_recv() {
+            let _ = respond(&mut *targets, &req).write_to(&mut stream);
+        }
+    }
+}
+
+command_main!(run);
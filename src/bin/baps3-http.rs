@@ -0,0 +1,86 @@
+#![feature(plugin)]
+
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use baps3_cli::Baps3Result;
+use baps3_cli::api::Bridge;
+use baps3_cli::config::resolve_target;
+use baps3_cli::format::timestamp;
+use baps3_cli::http;
+use baps3_cli::path::command_maps;
+use baps3_cli::session::Session;
+
+docopt!(Args, concat!("
+Serves an HTTP/JSON API for controlling a BAPS3 server.
+
+Usage:
+  baps3-http -h
+  baps3-http --completions <shell>
+  baps3-http [-v] [-L <addr>] [-r <secs>] [-t <target>] [-m <map>...]
+             [--allow-origin <origin>...]
+
+Endpoints:
+  GET  /state    The server's state, as JSON.
+  GET  /events   A stream of the server's messages, as server-sent events.
+  POST /load     Loads the file given by the file parameter.
+  POST /play     Starts playback.
+  POST /stop     Stops playback.
+  POST /eject    Unloads the current file.
+  POST /seek     Seeks to the pos parameter, in microseconds.
+
+Parameters can be given in the query string or as fields of a JSON object in
+the body.  Commands answer with the server's state; errors are answered with
+a JSON object describing them, and a 4xx status if the request was at fault
+or a 5xx status if the server was.  Bodies must be sent as application/json.
+
+Requests from web pages are refused unless their origin is allowed, so that
+other sites can't use the API through the browsers of people who visit them.
+
+Options:
+  --allow-origin <origin>  Allow requests from web pages at this origin,
+                           such as http://studio.example.  May be given
+                           more than once.
+  -h, --help               Show this message.
+  -L, --listen <addr>      The address to accept HTTP clients on.
+                           [Default: localhost:8350]
+  -m, --map <map>          Rewrites paths beginning with CLIENT to begin with
+                           SERVER instead, given as CLIENT=SERVER.  Applied
+                           before any rules for the target.
+  -r, --retry <secs>       How long to wait before reconnecting when the
+                           server goes away.
+                           [Default: 5]
+", common_options!()), flag_retry: i64);
+
+/// How long we wait for the server in one go, in milliseconds, before
+/// checking for requests.
+const TICK_MS: i64 = 100;
+
+fn run(args: Args) -> Baps3Result<()> {
+    let target = try!(resolve_target(&*args.flag_target));
+    let maps   = try!(command_maps(&*args.flag_map, &target));
+
+    let (tx, rx) = channel();
+    try!(http::serve(&*args.flag_listen, tx));
+
+    let session    = Session::new(&*target.addr,
+                                  &[],
+                                  Duration::seconds(args.flag_retry),
+                                  args.flag_verbose);
+    let mut bridge = Bridge::new(session, maps, args.flag_allow_origin);
+    loop {
+        bridge.poll_server(Duration::milliseconds(TICK_MS));
+        while let Ok((req, stream)) = rx.try_recv() {
+            let status = bridge.handle(&req, stream);
+            println!("{} {} {} {}", timestamp(), req.method, req.path, status);
+        }
+    }
+}
+
+command_main!(run);
@@ -0,0 +1,116 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use rustc_serialize::json;
+
+use baps3_cli::{ Baps3, Baps3Error, Baps3Result, verbose_logger };
+use baps3_cli::config::{ Target, default_format, resolve_target };
+use baps3_cli::list::{ self, Item, Playlist, FEATURE };
+use baps3_cli::path::{ command_maps, server_path };
+
+docopt!(Args, concat!("
+Shows or edits the list of a BAPS3 playlist server.
+
+Usage:
+  baps3-list -h
+  baps3-list --completions <shell>
+  baps3-list [-jv] [-t <target>]
+  baps3-list add [-v] [-t <target>] [-m <map>...] [-a <index>] <file>...
+  baps3-list (remove | select) [-v] [-t <target>] <index>
+  baps3-list clear [-v] [-t <target>]
+
+Commands:
+  add                    Add files to the list, in the order given.
+  remove                 Remove the item at <index> from the list.
+  select                 Load the item at <index> into the player.
+  clear                  Remove every item from the list.
+
+With no command, the list is printed, one item per line, with the selected
+item (if known) marked with a *.  Indices count from 0.
+
+Options:
+  -h, --help             Show this message.
+  -a, --at <index>       Add the files starting at this position, rather
+                         than at the end of the list.
+  -j, --json             Print the list as a JSON object.  This is the
+                         default if $BAPS3_FORMAT is json.
+  -m, --map <map>        Rewrites paths beginning with CLIENT to begin with
+                         SERVER instead, given as CLIENT=SERVER.  Applied
+                         before any rules for the target.
+", common_options!()), flag_at: Option<usize>, arg_index: Option<usize>);
+
+/// Prints `playlist`, either as JSON or one item per line.
+fn show(playlist: &Playlist, as_json: bool) {
+    if as_json {
+        println!("{}", json::encode(playlist));
+        return;
+    }
+
+    for item in playlist.items.iter() {
+        let mark = if playlist.selected == Some(item.index) { "*" } else { " " };
+        println!("{}{:>4}  {}", mark, item.index, item.file);
+    }
+}
+
+/// Finds the item at `index` in the server's current list.
+fn item_at<L: Fn(&str)>(baps3: &mut Baps3<L>, index: usize) -> Baps3Result<Item> {
+    let playlist = try!(list::fetch(baps3));
+    playlist.find(index)
+            .map(|i| i.clone())
+            .ok_or(Baps3Error::CmdInvalid {
+                advice: format!("no item at index {} (list has {} items)",
+                                index, playlist.items.len())
+            })
+}
+
+fn run<L: Fn(&str)>(baps3: &mut Baps3<L>, target: &Target, args: &Args)
+  -> Baps3Result<()> {
+    if args.cmd_add {
+        let maps = try!(command_maps(&*args.flag_map, target));
+
+        let mut at = match args.flag_at {
+            Some(a) => a,
+            None    => try!(list::fetch(baps3)).items.len()
+        };
+        for f in args.arg_file.iter() {
+            let sp = try!(server_path(&**f, &*maps));
+            try!(baps3.send(&list::enqueue(at, &*list::new_hash(), &*sp)));
+            at += 1;
+        }
+    } else if args.cmd_remove || args.cmd_select {
+        // docopt makes sure we have an index for these commands.
+        let item = try!(item_at(baps3, args.arg_index.unwrap_or(0)));
+        try!(baps3.send(&if args.cmd_remove { list::dequeue(&item) }
+                         else               { list::select(&item)  }));
+    } else if args.cmd_clear {
+        // Dequeueing from the end means no other item's index changes.
+        let playlist = try!(list::fetch(baps3));
+        for item in playlist.items.iter().rev() {
+            try!(baps3.send(&list::dequeue(item)));
+        }
+    } else {
+        let as_json = args.flag_json
+                      || default_format().map_or(false, |f| &*f == "json");
+        show(&try!(list::fetch(baps3)), as_json);
+    }
+
+    Ok(())
+}
+
+fn list_main(args: Args) -> Baps3Result<()> {
+    let log       = |&:s:&str| verbose_logger(args.flag_verbose, s);
+    let target    = try!(resolve_target(&*args.flag_target));
+    let mut baps3 = try!(Baps3::new(log, &*target.addr, &[FEATURE]));
+
+    let result = run(&mut baps3, &target, &args);
+    baps3.quit();
+    result
+}
+
+command_main!(list_main);
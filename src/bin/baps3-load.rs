@@ -8,61 +8,123 @@ extern crate docopt;
 #[plugin] #[no_link] extern crate docopt_macros;
 
 use std::borrow::ToOwned;
-use std::os;
-use std::path;
 
 use baps3_cli::{ Baps3, Baps3Error, Baps3Result, verbose_logger };
+use baps3_cli::time::format_micros;
+use baps3_cli::config::resolve_target;
+use baps3_cli::path::{ command_maps, map_path, to_absolute_path_str };
 use baps3_protocol::proto::Message;
 
-docopt!(Args, "
+docopt!(Args, concat!("
 Loads a file into a BAPS3 server.
 
 Usage:
   baps3-load -h
-  baps3-load [-pv] [-t <target>] <file>
+  baps3-load --completions <shell>
+  baps3-load [-pvw] [--progress] [-t <target>] [-m <map>...] <file>
 
 Options:
   -h, --help             Show this message.
+  -m, --map <map>        Rewrites paths beginning with CLIENT to begin with
+                         SERVER instead, given as CLIENT=SERVER.  Applied
+                         before any rules for the target.
   -p, --play             If set, play the file upon loading.
-  -v, --verbose          Prints a trail of miscellaneous information
-                         about the action.
-  -t, --target <target>  The target BAPS3 server (host:port).
-                         [Default: localhost:1350]
-");
+  -w, --wait             If set, wait until the file finishes playing, and
+                         fail if it is stopped or replaced beforehand.
+  --progress             When waiting, report the playback position on
+                         standard error.
+", common_options!()));
 
 fn load(Args { arg_file,
+               flag_map,
                flag_play,
+               flag_progress,
                flag_target,
-               flag_verbose, .. }: Args) -> Baps3Result<()> {
-    let ap        = try!(to_absolute_path_str(&*arg_file));
-    let log       = |&:s:&str| verbose_logger(flag_verbose, s);
-    let mut baps3 = try!(Baps3::new(log, &*flag_target,
-        &*(if flag_play { vec!["FileLoad", "PlayStop"] }
-           else         { vec!["FileLoad"]             })));
+               flag_verbose,
+               flag_wait, .. }: Args) -> Baps3Result<()> {
+    let log    = |&:s:&str| verbose_logger(flag_verbose, s);
+    let target = try!(resolve_target(&*flag_target));
 
-    try!(baps3.send(&Message::new("load").arg(&*ap)));
+    let maps   = try!(command_maps(&*flag_map, &target));
+    for m in maps.iter() {
+        log!(log, "path map: {} -> {}", m.client, m.server);
+    }
+
+    let ap = try!(to_absolute_path_str(&*arg_file));
+    let sp = map_path(&*maps, &*ap);
+    if sp != ap {
+        log!(log, "mapped path {} to {}", ap, sp);
+    }
+
+    let mut features = vec!["FileLoad"];
+    if flag_play { features.push("PlayStop"); }
+    if flag_wait { features.push("End");      }
+
+    let mut baps3 = try!(Baps3::new(log, &*target.addr, &*features));
+
+    try!(baps3.send(&Message::new("load").arg(&*sp)));
 
     if flag_play {
         try!(baps3.send(&Message::new("play")));
     }
 
-    Ok(())
+    let result = if flag_wait { wait(&mut baps3, &*sp, flag_progress) }
+                 else         { Ok(())                                };
+    baps3.quit();
+    result
 }
 
-/// Converts a potentially-relative path string to an absolute path string.
-fn to_absolute_path_str(rel: &str) -> Baps3Result<String> {
-    // This is a convoluted, entangled mess of Results and Options.
-    // I sincerely apologise.
+/// Waits for the server to report the end of the file at `path`.
+///
+/// Fails if the server loads a different file, or stops or ejects this one,
+/// before it ends.
+fn wait<L: Fn(&str)>(baps3: &mut Baps3<L>, path: &str, progress: bool)
+  -> Baps3Result<()> {
+    let mut last_time = String::new();
+    let result = wait_end(baps3, path, |&mut: us| if progress {
+        let ft = format_micros(us);
+        if ft != last_time {
+            werr!("\r{}", ft);
+            last_time = ft;
+        }
+    });
 
-    let badpath = |&:| Baps3Error::InvalidPath { path: rel.to_owned() };
-
-    path::Path::new_opt(rel)
-      .ok_or(badpath())
-      .and_then(|&:p| os::make_absolute(&p).map_err(|_| badpath()))
-      .and_then(|&:ap| ap.as_str().map(|&:s| s.to_string()).ok_or(badpath()))
+    if !last_time.is_empty() { werr!("\n"); }
+    result
 }
 
-fn main() {
-    let args: Args = Args::docopt().decode().unwrap_or_else(|e| e.exit());
-    load(args).unwrap_or_else(|&:e| werr!("error: {}", e));
+/// Reads notifications until END, calling `on_time` for each TIME.
+///
+/// A `STATE Stopped` only counts as an interruption once the file has been seen
+/// playing, as the server may report the state left over from before the load
+/// after acknowledging it.
+fn wait_end<L, F>(baps3: &mut Baps3<L>, path: &str, mut on_time: F)
+  -> Baps3Result<()>
+where L: Fn(&str),
+      F: FnMut(u64) {
+    let interrupted = |&: reason: &str| Err(Baps3Error::Interrupted {
+        reason: reason.to_owned()
+    });
+
+    let mut playing = false;
+
+    loop {
+        let msg = try!(baps3.recv());
+        match msg.as_str_vec().as_slice() {
+            ["END"]               => return Ok(()),
+            ["FILE", f] if f != path
+                                  => return interrupted("file replaced"),
+            ["STATE", "Playing"]  => playing = true,
+            ["STATE", "Stopped"] if playing
+                                  => return interrupted("stopped"),
+            ["STATE", "Ejected"]  => return interrupted("ejected"),
+            ["STATE", "Quitting"] => return interrupted("server quitting"),
+            ["TIME", t]           => if let Some(us) = t.parse::<u64>() {
+                on_time(us)
+            },
+            _ => ()
+        }
+    }
 }
+
+command_main!(load);
@@ -0,0 +1,191 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::io::net::udp::UdpSocket;
+use std::sync::mpsc::{ channel, Receiver, Sender };
+use std::thread::Thread;
+use std::time::Duration;
+
+use baps3_protocol::proto::Message;
+
+use baps3_cli::Baps3Result;
+use baps3_cli::config::resolve_target;
+use baps3_cli::format::timestamp;
+use baps3_cli::osc::{ self, OscMessage };
+use baps3_cli::session::Session;
+
+docopt!(Args, concat!("
+Controls BAPS3 servers with Open Sound Control (OSC) messages over UDP.
+
+Usage:
+  baps3-osc -h
+  baps3-osc --completions <shell>
+  baps3-osc [-v] [-L <addr>] [-s <addr>...] [-r <secs>] <target>...
+
+Each <target> is the name of a configured target, or a host:port.  Commands
+for it are sent to /baps3/TARGET/COMMAND, with the command's arguments as OSC
+arguments, for example /baps3/studio1/play or /baps3/studio1/seek 12.5.
+Numbers given to seek are taken as seconds.
+
+Whenever a target's state, file or position changes, or its file ends, an
+OSC message is sent to each -s address, at /baps3/TARGET/state,
+/baps3/TARGET/file, /baps3/TARGET/time (in seconds) or /baps3/TARGET/end.
+
+Options:
+  -h, --help           Show this message.
+  -L, --listen <addr>  The UDP address to accept OSC messages on.
+                       [Default: localhost:7350]
+  -s, --send <addr>    Sends state changes to this UDP address.
+  -r, --retry <secs>   How long to wait before reconnecting when a server
+                       goes away.
+                       [Default: 5]
+", common_options!(untargeted)), flag_retry: i64);
+
+/// How long we wait for each server in one go, in milliseconds, before
+/// moving on to the next server and checking for OSC messages.
+const TICK_MS: i64 = 20;
+
+/// The largest OSC packet we accept.
+const MAX_PACKET: usize = 65536;
+
+/// One of the servers we control.
+struct Target {
+    /// The name commands and notifications are addressed by.
+    name:     String,
+
+    session:  Session
+}
+
+impl Target {
+    /// Passes a message from the server on to the mirror, returning true if
+    /// listeners should hear about it.
+    fn notify(&mut self, msg: &Message) -> bool {
+        let changed = self.session.update(msg);
+        match msg.word() {
+            "STATE" | "FILE" | "TIME" => changed,
+            "END"                     => true,
+            _                         => false
+        }
+    }
+}
+
+struct Bridge {
+    targets:   Vec<Target>,
+    socket:    UdpSocket,
+
+    /// The addresses state changes are sent to.
+    listeners: Vec<String>
+}
+
+impl Bridge {
+    /// Sends a notification from `targets[i]` out to the listeners.
+    fn announce(&mut self, i: usize, msg: &Message) {
+        if !self.targets[i].notify(msg) {
+            return;
+        }
+
+        let packet = osc::from_message(&*self.targets[i].name, msg).encode();
+        for l in self.listeners.iter() {
+            if let Err(e) = self.socket.send_to(&*packet, &**l) {
+                werr!("{} {}: {}\n", timestamp(), l, e);
+            }
+        }
+    }
+
+    /// Waits briefly for messages from each server and acts on them.
+    fn poll_servers(&mut self) {
+        for i in range(0, self.targets.len()) {
+            let result = self.targets[i].session
+                                        .recv_timeout(Duration::milliseconds(TICK_MS));
+            match result {
+                Ok(Some(msg)) => self.announce(i, &msg),
+                Ok(None)      => (),
+                Err(e)        => werr!("{} {}: {}\n",
+                                       timestamp(), self.targets[i].session.addr(), e)
+            }
+        }
+    }
+
+    /// Runs the command in an OSC message.
+    fn command(&mut self, msg: &OscMessage) {
+        let (name, cmd) = match osc::to_command(msg) {
+            Some(c) => c,
+            None    => {
+                werr!("{} {}: not a BAPS3 command\n", timestamp(), msg.address);
+                return;
+            }
+        };
+        let i = match self.targets.iter().position(|t| t.name == name) {
+            Some(i) => i,
+            None    => {
+                werr!("{} {}: no such target\n", timestamp(), name);
+                return;
+            }
+        };
+
+        match self.targets[i].session.send_collecting(&cmd) {
+            Ok(others) => for m in others.iter() { self.announce(i, m); },
+            Err(e)     => werr!("{} {}: {}: {}\n",
+                                timestamp(), name, cmd.pack().trim_right(), e)
+        }
+    }
+
+    fn run(&mut self, packets: Receiver<OscMessage>) {
+        loop {
+            self.poll_servers();
+            while let Ok(msg) = packets.try_recv() {
+                self.command(&msg);
+            }
+        }
+    }
+}
+
+/// Reads OSC packets from `socket` for as long as anyone is listening.
+fn read_packets(mut socket: UdpSocket, packets: Sender<OscMessage>) {
+    let mut buf = [0u8; MAX_PACKET];
+
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(r)  => r,
+            Err(_) => continue
+        };
+
+        match osc::decode(&buf[..len]) {
+            Some(msgs) => for m in msgs.into_iter() {
+                if packets.send(m).is_err() { return; }
+            },
+            None => werr!("{} {}: malformed OSC packet\n", timestamp(), from)
+        }
+    }
+}
+
+fn run(args: Args) -> Baps3Result<()> {
+    let mut targets = vec![];
+    for t in args.arg_target.iter() {
+        let target = try!(resolve_target(&**t));
+        targets.push(Target { name:     t.clone(),
+                              session:  Session::new(&*target.addr,
+                                                     &[],
+                                                     Duration::seconds(args.flag_retry),
+                                                     args.flag_verbose) });
+    }
+
+    let socket = try!(UdpSocket::bind(&*args.flag_listen));
+    let reader = socket.clone();
+    let (tx, rx) = channel();
+    Thread::spawn(move || read_packets(reader, tx));
+
+    let mut bridge = Bridge { targets:   targets,
+                              socket:    socket,
+                              listeners: args.flag_send.clone() };
+    bridge.run(rx);
+    Ok(())
+}
+
+command_main!(run);
@@ -7,31 +7,25 @@ extern crate "rustc-serialize" as rustc_serialize;
 extern crate docopt;
 #[plugin] #[no_link] extern crate docopt_macros;
 
-use baps3_cli::{one_shot, verbose_logger};
+use baps3_cli::{ exit_with, one_shot_target };
 use baps3_protocol::proto::Message;
 
-docopt!(Args, "
+docopt!(Args, concat!("
 Plays the currently loaded file in a BAPS3 server.
 
 Usage:
   baps3-play -h
+  baps3-play --completions <shell>
   baps3-play [-v] [-t <target>]
 
 Options:
   -h, --help             Show this message.
-  -v, --verbose          Prints a trail of miscellaneous information
-                         about the action.
-  -t, --target <target>  The target BAPS3 server (host:port).
-                         [Default: localhost:1350]
-");
+", common_options!()));
 
 fn main() {
-    let args: Args = Args::docopt().decode().unwrap_or_else(|e| e.exit());
-    let log = |&:s:&str| verbose_logger(args.flag_verbose, s);
-
-    one_shot(log,
-             &*args.flag_target,
-             &["PlayStop"],
-             Message::new("play"))
-      .unwrap_or_else(|e| werr!("error: {}", e));
+    let args = parse_args!();
+    exit_with(one_shot_target(&*args.flag_target,
+                              args.flag_verbose,
+                              &["PlayStop"],
+                              &[Message::new("play")]));
 }
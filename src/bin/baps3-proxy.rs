@@ -0,0 +1,108 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::sync::mpsc::{ channel, Receiver };
+use std::time::Duration;
+
+use baps3_cli::Baps3Result;
+use baps3_cli::config::resolve_target;
+use baps3_cli::format::timestamp;
+use baps3_cli::server::{ self, Downstream, Event, Relay };
+use baps3_cli::session::Session;
+
+docopt!(Args, concat!("
+Shares one connection to a BAPS3 server between many clients.
+
+Usage:
+  baps3-proxy -h
+  baps3-proxy --completions <shell>
+  baps3-proxy [-lv] [-L <addr>] [-r <secs>] [-t <target>]
+
+Clients connect to the proxy as they would to the server, and are greeted
+with the server's OHAI and FEATURES.  Their commands are passed on to the
+server one at a time, with each acknowledgement going back to the client that
+sent the command, and every other message from the server going to all
+clients.
+
+Clients are only accepted while the proxy is connected to the server, and are
+disconnected if the server goes away.
+
+Options:
+  -h, --help             Show this message.
+  -l, --log              Logs each command on standard output, with the
+                         address of the client that sent it and its outcome.
+  -L, --listen <addr>    The address to accept clients on.
+                         [Default: localhost:1352]
+  -r, --retry <secs>     How long to wait before reconnecting when the
+                         server goes away.
+                         [Default: 5]
+", common_options!()), flag_retry: i64);
+
+/// How long we wait for the server in one go, in milliseconds, before
+/// checking on the clients.
+const TICK_MS: i64 = 100;
+
+struct Proxy {
+    relay: Relay<Downstream>
+}
+
+impl Proxy {
+    /// Greets a new client on behalf of the server.
+    fn greet(&mut self, mut client: Downstream) {
+        match self.relay.greeting() {
+            Some(hello) => self.relay.join(client, &*hello),
+            None        => {
+                werr!("{} {}: server not connected, hanging up\n",
+                      timestamp(), client.peer);
+                client.close();
+            }
+        }
+    }
+
+    fn client_event(&mut self, ev: Event) {
+        match ev {
+            Event::Connected(d)     => self.greet(d),
+            Event::Message(id, msg) => self.relay.forward(id, msg),
+            Event::Disconnected(id) => self.relay.disconnected(id)
+        }
+    }
+
+    fn run(&mut self, events: Receiver<Event>) {
+        loop {
+            self.relay.poll_server(Duration::milliseconds(TICK_MS));
+            while let Ok(ev) = events.try_recv() {
+                self.client_event(ev);
+            }
+        }
+    }
+}
+
+fn run(args: Args) -> Baps3Result<()> {
+    let target = try!(resolve_target(&*args.flag_target));
+
+    let mut session = Session::new(&*target.addr,
+                                   &[],
+                                   Duration::seconds(args.flag_retry),
+                                   args.flag_verbose);
+
+    // Connect straight away, so that problems show up before any clients
+    // arrive, but carry on if the server isn't there yet.
+    if let Err(e) = session.connect() {
+        werr!("{} {}: {}\n", timestamp(), target.addr, e);
+    }
+
+    let (tx, rx) = channel();
+    try!(server::listen(&*args.flag_listen, tx));
+
+    let mut proxy = Proxy { relay: Relay::new(session, args.flag_log) };
+    proxy.run(rx);
+    Ok(())
+}
+
+command_main!(run);
@@ -0,0 +1,36 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use baps3_cli::{ Baps3Error, exit_with, one_shot_target };
+use baps3_protocol::proto::Message;
+
+docopt!(Args, concat!("
+Asks a BAPS3 server to shut down.
+
+Usage:
+  baps3-quit -h
+  baps3-quit --completions <shell>
+  baps3-quit [-v] [-t <target>]
+
+Options:
+  -h, --help             Show this message.
+", common_options!()));
+
+fn main() {
+    let args = parse_args!();
+    exit_with(one_shot_target(&*args.flag_target,
+                              args.flag_verbose,
+                              &["End"],
+                              &[Message::new("quit")])
+      .or_else(|e| match e {
+          // A server may well hang up before acknowledging the quit.
+          Baps3Error::HungUp => Ok(()),
+          _                  => Err(e)
+      }));
+}
@@ -0,0 +1,157 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate "time" as clock;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::cmp;
+use std::io::File;
+use std::io::timer::sleep;
+use std::time::Duration;
+
+use clock::Timespec;
+
+use baps3_cli::Baps3Result;
+use baps3_cli::config::resolve_target;
+use baps3_cli::format::{ timestamp, timestamp_of };
+use baps3_cli::path::command_maps;
+use baps3_cli::schedule::{ self, Entry };
+use baps3_cli::script::{ self, Step };
+use baps3_cli::session::Session;
+
+docopt!(Args, concat!("
+Runs BAPS3 commands at given wall-clock times.
+
+Usage:
+  baps3-schedule -h
+  baps3-schedule --completions <shell>
+  baps3-schedule [-nv] [-t <target>] [-m <map>...] [-r <secs>] <schedule>
+
+Each line of the schedule is a time followed by commands separated by ;, for
+example:
+
+  every day 07:00 load news.wav; play
+  every sat,sun 09:59:58.5 stop
+  2015-02-14 12:00 load valentine.mp3; play
+
+Times are local.  Files given to load are taken relative to the schedule.
+Each command is logged on standard output as it is run, with the time it was
+planned for and the time it actually ran.
+
+Options:
+  -h, --help             Show this message.
+  -n, --dry-run          Print when each entry is next due, and exit.
+  -m, --map <map>        Rewrites paths beginning with CLIENT to begin with
+                         SERVER instead, given as CLIENT=SERVER.  Applied
+                         before any rules for the target.
+  -r, --retry <secs>     How long to wait before reconnecting when the
+                         server goes away.
+                         [Default: 5]
+", common_options!()), flag_retry: i64);
+
+/// The longest we wait for the server in one go while idle, so that lost
+/// connections are noticed and remade well before the next entry is due.
+const IDLE_MS: i64 = 1000;
+
+/// Waits until `when`, keeping the connection to the server alive.
+fn wait_until(session: &mut Session, when: Timespec) {
+    loop {
+        let now = clock::get_time();
+        if when <= now {
+            return;
+        }
+
+        // Notifications are of no interest here; we only read them so that
+        // they don't pile up, and so that we notice if the server goes away.
+        if let Err(e) = session.recv_timeout(cmp::min(when - now,
+                                                      Duration::milliseconds(IDLE_MS))) {
+            werr!("{} {}: {}\n", timestamp(), session.addr(), e);
+        }
+    }
+}
+
+/// Runs the commands of `entry`, which was due at `planned`, logging each.
+fn fire(session: &mut Session, entry: &Entry, planned: Timespec) {
+    for line in entry.commands.iter() {
+        let started = clock::get_time();
+
+        let (what, result) = match line.step {
+            Step::Send(ref msg) =>
+                (msg.pack().trim_right().to_string(), session.send(msg)),
+            Step::Sleep(d) => {
+                sleep(d);
+                (format!("!sleep {}ms", d.num_milliseconds()), Ok(()))
+            },
+            Step::Source(ref p) => {
+                let result = script::run(p, false, &mut |&mut: m| session.send(m))
+                                    .map(|_| ());
+                (format!("!source {}", p.display()), result)
+            }
+        };
+
+        println!("{} line {} (planned {}, {:+}ms): {}: {}",
+                 timestamp_of(started),
+                 line.number,
+                 timestamp_of(planned),
+                 (started - planned).num_milliseconds(),
+                 what,
+                 match result {
+                     Ok(_)      => "ok".to_string(),
+                     Err(ref e) => e.to_string()
+                 });
+    }
+}
+
+fn run(args: Args) -> Baps3Result<()> {
+    let target  = try!(resolve_target(&*args.flag_target));
+    let maps    = try!(command_maps(&*args.flag_map, &target));
+    let path    = Path::new(&*args.arg_schedule);
+    let src     = try!(File::open(&path).read_to_string());
+    let entries = try!(schedule::parse(&path, &*src, &*maps));
+
+    let now      = clock::get_time();
+    let mut next: Vec<Option<Timespec>> = entries.iter()
+                                                 .map(|e| e.when.next_after(now))
+                                                 .collect();
+
+    if args.flag_dry_run {
+        for (e, &n) in entries.iter().zip(next.iter()) {
+            println!("{}: {}",
+                     n.map(timestamp_of).unwrap_or("never".to_string()),
+                     e.text);
+        }
+        return Ok(());
+    }
+
+    let mut session = Session::new(&*target.addr,
+                                   &[],
+                                   Duration::seconds(args.flag_retry),
+                                   args.flag_verbose);
+
+    // Connect straight away, so that problems show up before anything is
+    // due, but carry on if the server isn't there yet.
+    if let Err(e) = session.connect() {
+        werr!("{} {}: {}\n", timestamp(), target.addr, e);
+    }
+
+    loop {
+        let due = next.iter()
+                      .enumerate()
+                      .filter_map(|(i, &n)| n.map(|t| (t, i)))
+                      .min();
+        let (when, i) = match due {
+            Some(d) => d,
+            None    => return Ok(())
+        };
+
+        wait_until(&mut session, when);
+        fire(&mut session, &entries[i], when);
+        next[i] = entries[i].when.next_after(when);
+    }
+}
+
+command_main!(run);
@@ -7,11 +7,11 @@ extern crate "rustc-serialize" as rustc_serialize;
 extern crate docopt;
 #[plugin] #[no_link] extern crate docopt_macros;
 
-use baps3_cli::{one_shot, verbose_logger};
+use baps3_cli::{ exit_with, one_shot_target, verbose_logger };
 use baps3_cli::time::TimeUnit;
 use baps3_protocol::proto::Message;
 
-docopt!(Args, "
+docopt!(Args, concat!("
 Seeks to a given position in the currently loaded BAPS3 file.
 
 By default, the position is in microseconds; use one of -H, -M, -S,
@@ -19,12 +19,11 @@ or -m to override this.
 
 Usage:
   baps3-seek -h
+  baps3-seek --completions <shell>
   baps3-seek [-vHMSm] [-t <target>] <pos>
 
 Options:
   -h, --help             Show this message.
-  -v, --verbose          Prints a trail of miscellaneous information
-                         about the action.
   -H, --hours            Interpret <pos> as hours.
                          Overrides -M, -S, and -m.
   -M, --minutes          Interpret <pos> as minutes.
@@ -32,9 +31,7 @@ Options:
   -S, --seconds          Interpret <pos> as seconds.
                          Overrides -m.
   -m, --milliseconds     Interpret <pos> as milliseconds.
-  -t, --target <target>  The target BAPS3 server (host:port).
-                         [Default: localhost:1350]
-", arg_pos: u64);
+", common_options!()), arg_pos: u64);
 
 /// Uses the unit flags to convert `pos` to microseconds.
 fn pos_to_micros<L: Fn(&str)>(log: &L,
@@ -49,7 +46,7 @@ fn pos_to_micros<L: Fn(&str)>(log: &L,
 }
 
 fn main() {
-    let args: Args = Args::docopt().decode().unwrap_or_else(|e| e.exit());
+    let args = parse_args!();
     let log = |&:s:&str| verbose_logger(args.flag_verbose, s);
 
     let pos = pos_to_micros(&log,
@@ -60,9 +57,8 @@ fn main() {
                             args.flag_milliseconds);
     let spos = pos.to_string();
 
-    one_shot(log,
-             &*args.flag_target,
-             &["Seek"],
-             Message::new("seek").arg(&*spos))
-      .unwrap_or_else(|e| werr!("error: {}", e));
+    exit_with(one_shot_target(&*args.flag_target,
+                              args.flag_verbose,
+                              &["Seek"],
+                              &[Message::new("seek").arg(&*spos)]));
 }
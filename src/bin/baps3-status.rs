@@ -0,0 +1,76 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use rustc_serialize::json;
+
+use baps3_cli::{ Baps3, Baps3Result, verbose_logger };
+use baps3_cli::config::{ default_format, resolve_target };
+use baps3_cli::state::{ ServerState, read_state };
+
+docopt!(Args, concat!("
+Reports the current state of a BAPS3 server.
+
+Usage:
+  baps3-status -h
+  baps3-status --completions <shell>
+  baps3-status [-jvw] [-t <target>]
+
+Options:
+  -h, --help             Show this message.
+  -j, --json             Report the state as a JSON object.  This is the
+                         default if $BAPS3_FORMAT is json.
+  -w, --watch            Keep running, reporting the state again whenever
+                         it changes.
+", common_options!()));
+
+fn status(Args { flag_json,
+                 flag_target,
+                 flag_verbose,
+                 flag_watch, .. }: Args) -> Baps3Result<()> {
+    let log       = |&:s:&str| verbose_logger(flag_verbose, s);
+    let target    = try!(resolve_target(&*flag_target));
+    let flag_json = flag_json
+                    || default_format().map_or(false, |f| &*f == "json");
+    let mut baps3 = try!(Baps3::new(log, &*target.addr, &[]));
+    let mut state = try!(read_state(&mut baps3));
+
+    report(&state, flag_json, flag_watch);
+
+    if flag_watch {
+        // The summary only shows whole seconds, so comparing summaries stops
+        // us reporting every single TIME.
+        let mut last = state.summary();
+
+        loop {
+            let msg = try!(baps3.recv());
+            if state.update(&msg) && state.summary() != last {
+                report(&state, flag_json, flag_watch);
+                last = state.summary();
+            }
+        }
+    }
+
+    baps3.quit();
+    Ok(())
+}
+
+/// Prints the state, either as JSON or as a summary.
+///
+/// When watching, summaries replace the previous one on the terminal, and
+/// JSON objects are printed one per line.
+fn report(state: &ServerState, as_json: bool, watch: bool) {
+    if as_json {
+        println!("{}", json::encode(state));
+    } else {
+        if watch { print!("\x1b[2J\x1b[H"); }
+        println!("{}", state.summary());
+    }
+}
+
+command_main!(status);
@@ -0,0 +1,129 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::borrow::ToOwned;
+use std::io::timer::sleep;
+use std::time::Duration;
+
+use baps3_cli::{ Baps3, Baps3Result, verbose_logger };
+use baps3_cli::config::{ default_format, resolve_target };
+use baps3_cli::format::{ Format, timestamp };
+use baps3_protocol::proto::Message;
+
+docopt!(Args, concat!("
+Prints the messages a BAPS3 server sends, as it sends them.
+
+Usage:
+  baps3-tail -h
+  baps3-tail --completions <shell>
+  baps3-tail [-v] [-f <format>] [-o <words> | -x <words>] [-r <secs>]
+             [-t <target>]
+
+Options:
+  -h, --help              Show this message.
+  -f, --format <format>   How to print messages: raw (as sent over the wire),
+                          pretty, or json (one object per line).
+                          Defaults to $BAPS3_FORMAT, or pretty if that
+                          is not set.
+  -o, --only <words>      Only print messages whose command word is in this
+                          comma-separated list, for example TIME,STATE.
+  -x, --exclude <words>   Print every message except those whose command
+                          word is in this comma-separated list.
+  -r, --retry <secs>      How long to wait before reconnecting when the
+                          server goes away.
+                          [Default: 5]
+", common_options!()), flag_only: Option<String>, flag_exclude: Option<String>, flag_retry: i64);
+
+/// Which messages to print.
+enum Filter {
+    All,
+    Only(Vec<String>),
+    Exclude(Vec<String>)
+}
+
+impl Filter {
+    fn from_args(only: &Option<String>, exclude: &Option<String>) -> Filter {
+        let words = |&: s: &String| s.split(',')
+                                     .map(|w| w.trim().to_owned())
+                                     .filter(|w| !w.is_empty())
+                                     .collect();
+
+        match (only, exclude) {
+            (&Some(ref o), _) => Filter::Only(words(o)),
+            (_, &Some(ref x)) => Filter::Exclude(words(x)),
+            _                 => Filter::All
+        }
+    }
+
+    fn allows(&self, msg: &Message) -> bool {
+        let word = msg.word();
+
+        match *self {
+            Filter::All            => true,
+            Filter::Only(ref ws)    =>  ws.iter().any(|w| &**w == word),
+            Filter::Exclude(ref ws) => !ws.iter().any(|w| &**w == word)
+        }
+    }
+}
+
+/// Prints messages from one connection to the server, until it goes away.
+fn tail_once(addr: &str, verbose: bool, format: Format, filter: &Filter)
+  -> Baps3Result<()> {
+    let log       = |&:s:&str| verbose_logger(verbose, s);
+    let mut baps3 = try!(Baps3::new(log, addr, &[]));
+
+    werr!("connected to {} ({})\n", addr, baps3.ident());
+
+    loop {
+        let msg = try!(baps3.recv());
+        if filter.allows(&msg) {
+            println!("{}", format.stamped_message(&*timestamp(), &msg));
+        }
+    }
+}
+
+fn main() {
+    let args = parse_args!();
+
+    let name = if args.flag_format.is_empty() {
+        default_format().unwrap_or("pretty".to_owned())
+    } else {
+        args.flag_format.clone()
+    };
+
+    let format = match Format::from_name(&*name) {
+        Some(f) => f,
+        None    => {
+            werr!("error: unknown format {}\n", name);
+            std::os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let filter = Filter::from_args(&args.flag_only, &args.flag_exclude);
+    let target = match resolve_target(&*args.flag_target) {
+        Ok(t)  => t,
+        Err(e) => {
+            werr!("error: {}\n", e);
+            std::os::set_exit_status(1);
+            return;
+        }
+    };
+
+    loop {
+        if let Err(e) = tail_once(&*target.addr,
+                                  args.flag_verbose,
+                                  format,
+                                  &filter) {
+            werr!("{}: {}; retrying in {}s\n",
+                  target.addr, e, args.flag_retry);
+        }
+        sleep(Duration::seconds(args.flag_retry));
+    }
+}
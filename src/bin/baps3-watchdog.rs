@@ -0,0 +1,224 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate "time" as clock;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::io::IoErrorKind;
+use std::io::process::{ Command, InheritFd, Process };
+use std::time::Duration;
+
+use baps3_protocol::proto::Message;
+
+use baps3_cli::{ Baps3Error, Baps3Result };
+use baps3_cli::config::resolve_target;
+use baps3_cli::format::timestamp;
+use baps3_cli::path::{ command_maps, server_path };
+use baps3_cli::session::Session;
+use baps3_cli::watchdog::{ Alert, Change, Watch, Window };
+
+docopt!(Args, concat!("
+Watches a BAPS3 server for dead air, and raises alerts.
+
+Usage:
+  baps3-watchdog -h
+  baps3-watchdog --completions <shell>
+  baps3-watchdog [-lv] [-s <secs>] [-S <secs>] [-H <hours>...] [-x <command>] [-e <file>] [-r <secs>] [-t <target>] [-m <map>...]
+
+Alerts are raised when the server has not been playing for too long during
+on-air hours (stopped), when its position stops moving while it says it is
+playing (stalled), and when it can't be reached (disconnected).  Each alert
+is logged on standard output when it is raised and when it clears.
+
+Options:
+  -h, --help              Show this message.
+  -e, --fallback <file>   On a stopped or stalled alert, load and play this
+                          file.
+  -H, --hours <hours>     On-air hours, as HH:MM-HH:MM, outside which the
+                          server may be stopped.  May be given more than
+                          once.  Defaults to all day.
+  -l, --syslog            Also log alerts to syslog, with logger(1).
+  -m, --map <map>         Rewrites paths beginning with CLIENT to begin with
+                          SERVER instead, given as CLIENT=SERVER.  Applied
+                          before any rules for the target.
+  -r, --retry <secs>      How long to wait before reconnecting when the
+                          server goes away.
+                          [Default: 5]
+  -s, --stopped <secs>    How long the server may be stopped while on air.
+                          [Default: 10]
+  -S, --stall <secs>      How long the position may stay put while playing.
+                          [Default: 5]
+  -x, --exec <command>    Run this shell command when an alert is raised,
+                          with $BAPS3_ALERT set to stopped, stalled or
+                          disconnected.
+", common_options!()), flag_retry: i64, flag_stopped: i64, flag_stall: i64);
+
+/// How long we wait for the server in one go, in milliseconds, between
+/// health checks.
+const TICK_MS: i64 = 200;
+
+/// What to do about alerts.
+struct Hooks {
+    target:   String,
+    syslog:   bool,
+    exec:     Option<String>,
+    fallback: Option<String>,
+
+    /// Commands we have started and not yet seen finish, with their names.
+    children: Vec<(String, Process)>
+}
+
+impl Hooks {
+    /// Writes a line to syslog.
+    fn log(&mut self, priority: &str, line: &str) {
+        let result = Command::new("logger").arg("-t").arg("baps3-watchdog")
+                                           .arg("-p").arg(priority)
+                                           .arg(line)
+                                           .stdout(InheritFd(1))
+                                           .stderr(InheritFd(2))
+                                           .spawn();
+        match result {
+            Ok(p)  => self.children.push(("logger".to_string(), p)),
+            Err(e) => werr!("{} logger: {}\n", timestamp(), e)
+        }
+    }
+
+    /// Starts the command hook for `alert`.
+    fn spawn(&mut self, command: &str, alert: Alert, line: &str) {
+        let result = Command::new("sh").arg("-c").arg(command)
+                                       .env("BAPS3_ALERT", alert.name())
+                                       .env("BAPS3_ALERT_MESSAGE", line)
+                                       .env("BAPS3_TARGET", &*self.target)
+                                       .stdin(InheritFd(0))
+                                       .stdout(InheritFd(1))
+                                       .stderr(InheritFd(2))
+                                       .spawn();
+        match result {
+            Ok(p)  => self.children.push((command.to_string(), p)),
+            Err(e) => werr!("{} {}: {}\n", timestamp(), command, e)
+        }
+    }
+
+    /// Collects any commands that have finished, reporting those that
+    /// failed.
+    ///
+    /// Commands run alongside the watchdog rather than holding it up, as a
+    /// slow hook must not delay the fallback or the next health check.
+    fn reap(&mut self) {
+        let mut running = vec![];
+        for (name, mut p) in self.children.drain() {
+            p.set_timeout(Some(0));
+            match p.wait() {
+                Ok(status) => if !status.success() {
+                    werr!("{} {}: {}\n", timestamp(), name, status);
+                },
+                Err(ref e) if e.kind == IoErrorKind::TimedOut =>
+                    running.push((name, p)),
+                Err(e) => werr!("{} {}: {}\n", timestamp(), name, e)
+            }
+        }
+        self.children = running;
+    }
+
+    /// Loads and plays the fallback file.
+    fn fall_back(&self, session: &mut Session, file: &str) -> Baps3Result<()> {
+        try!(session.send(&Message::new("load").arg(file)));
+        session.send(&Message::new("play"))
+    }
+
+    /// Acts on an alert being raised or cleared.
+    fn run(&mut self, session: &mut Session, change: Change) {
+        let (alert, line) = match change {
+            Change::Fired(a)   => (a, format!("ALERT {}: {}", a.name(), self.target)),
+            Change::Cleared(a) => (a, format!("CLEARED {}: {}", a.name(), self.target))
+        };
+        println!("{} {}", timestamp(), line);
+        if self.syslog {
+            let priority = match change {
+                Change::Fired(_)   => "daemon.crit",
+                Change::Cleared(_) => "daemon.notice"
+            };
+            self.log(priority, &*line);
+        }
+
+        if let Change::Cleared(_) = change {
+            return;
+        }
+
+        if let Some(command) = self.exec.clone() {
+            self.spawn(&*command, alert, &*line);
+        }
+
+        match (&self.fallback, alert) {
+            (&Some(ref file), Alert::Stopped) | (&Some(ref file), Alert::Stalled) =>
+                match self.fall_back(session, &**file) {
+                    Ok(_)  => println!("{} playing fallback {}", timestamp(), file),
+                    Err(e) => werr!("{} fallback {}: {}\n", timestamp(), file, e)
+                },
+            _ => ()
+        }
+    }
+}
+
+/// Determines whether we are on air now, given the on-air `windows`.
+fn on_air(windows: &[Window]) -> bool {
+    if windows.is_empty() {
+        return true;
+    }
+
+    let tm = clock::now();
+    windows.iter().any(|w| w.contains(tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec))
+}
+
+fn run(args: Args) -> Baps3Result<()> {
+    let target = try!(resolve_target(&*args.flag_target));
+    let maps   = try!(command_maps(&*args.flag_map, &target));
+
+    let mut windows = vec![];
+    for h in args.flag_hours.iter() {
+        windows.push(try!(Window::parse(&**h).ok_or(Baps3Error::CmdInvalid {
+            advice: format!("expected hours like 06:00-23:00, got {}", h)
+        })));
+    }
+
+    let fallback = if args.flag_fallback.is_empty() {
+        None
+    } else {
+        Some(try!(server_path(&*args.flag_fallback, &*maps)))
+    };
+
+    let mut hooks = Hooks {
+        target:   target.addr.clone(),
+        syslog:   args.flag_syslog,
+        exec:     if args.flag_exec.is_empty() { None } else { Some(args.flag_exec.clone()) },
+        fallback: fallback,
+        children: vec![]
+    };
+
+    let mut session = Session::new(&*target.addr,
+                                   &[],
+                                   Duration::seconds(args.flag_retry),
+                                   args.flag_verbose);
+    let mut watch   = Watch::new(Duration::seconds(args.flag_stopped),
+                                 Duration::seconds(args.flag_stall));
+
+    loop {
+        match session.recv_timeout(Duration::milliseconds(TICK_MS)) {
+            Ok(Some(msg)) => { session.update(&msg); },
+            Ok(None)      => (),
+            Err(e)        => werr!("{} {}: {}\n", timestamp(), session.addr(), e)
+        }
+
+        let changes = watch.check(session.state(), on_air(&*windows), clock::get_time());
+        for c in changes.into_iter() {
+            hooks.run(&mut session, c);
+        }
+        hooks.reap();
+    }
+}
+
+command_main!(run);
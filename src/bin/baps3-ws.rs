@@ -0,0 +1,168 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::io::net::tcp::TcpStream;
+use std::sync::mpsc::{ channel, Receiver, Sender };
+use std::time::Duration;
+
+use baps3_protocol::proto::Message;
+
+use baps3_cli::Baps3Result;
+use baps3_cli::config::resolve_target;
+use baps3_cli::format::timestamp;
+use baps3_cli::http::{ self, Request, Response };
+use baps3_cli::server::Relay;
+use baps3_cli::session::Session;
+use baps3_cli::websocket::{ self, Client, Event };
+
+docopt!(Args, concat!("
+Lets browsers talk to a BAPS3 server over WebSockets.
+
+Usage:
+  baps3-ws -h
+  baps3-ws --completions <shell>
+  baps3-ws [-lv] [-L <addr>] [-r <secs>] [-t <target>]
+           [--allow-origin <origin>...]
+
+Each BAPS3 message travels as a text frame holding a JSON array of its
+command word and arguments, such as [\"load\",\"/music/a.mp3\"].  One
+connection to the server is shared between all clients: each is greeted with
+the server's OHAI and FEATURES, gets the acknowledgements of its own
+commands, and gets every other message from the server.
+
+Clients are only accepted while connected to the server, and are disconnected
+if the server goes away.  Browsers connecting from web pages are refused
+unless the page's origin is allowed, so that other sites can't use the server
+through the browsers of people who visit them.
+
+Options:
+  --allow-origin <origin>  Allow clients from web pages at this origin,
+                           such as http://studio.example.  May be given
+                           more than once.
+  -h, --help               Show this message.
+  -l, --log                Logs each command on standard output, with the
+                           address of the client that sent it and its outcome.
+  -L, --listen <addr>      The address to accept WebSocket clients on.
+                           [Default: localhost:8351]
+  -r, --retry <secs>       How long to wait before reconnecting when the
+                           server goes away.
+                           [Default: 5]
+", common_options!()), flag_retry: i64);
+
+/// How long we wait for the server in one go, in milliseconds, before
+/// checking on the clients.
+const TICK_MS: i64 = 100;
+
+struct Bridge {
+    relay:   Relay<Client>,
+    events:  Sender<Event>,
+    next_id: usize,
+
+    /// The origins web pages may connect from.
+    origins: Vec<String>
+}
+
+impl Bridge {
+    /// Answers a WebSocket handshake, and greets the new client on behalf
+    /// of the server.
+    fn accept(&mut self, req: Request, mut stream: TcpStream) {
+        let hello = match self.relay.greeting() {
+            Some(h) => h,
+            None    => {
+                let _ = Response::text(503, "BAPS3 server not connected")
+                                 .write_to(&mut stream);
+                return;
+            }
+        };
+
+        // Browsers let any page open a WebSocket to us, so checking where
+        // the handshake came from is all that stops other sites.
+        if !http::origin_allowed(&req, &*self.origins) {
+            let _ = Response::text(403, "origin not allowed").write_to(&mut stream);
+            return;
+        }
+
+        match websocket::upgrade(&req, &mut stream) {
+            Ok(true)  => (),
+            Ok(false) => {
+                let _ = Response::text(400, "expected a WebSocket handshake")
+                                 .write_to(&mut stream);
+                return;
+            },
+            Err(_)    => return
+        }
+
+        let client    = Client::new(self.next_id, stream);
+        self.next_id += 1;
+
+        client.start_reading(self.events.clone());
+        self.relay.join(client, &*hello);
+    }
+
+    fn client_event(&mut self, ev: Event) {
+        match ev {
+            Event::Message(id, msg)  => self.relay.forward(id, msg),
+            Event::Invalid(id, text) => {
+                if let Some(c) = self.relay.client(id) {
+                    let _ = c.send(&Message::new("WHAT")
+                                            .arg("expected a JSON array of strings")
+                                            .arg(&*text));
+                }
+            },
+            Event::Ping(id, payload) => {
+                if let Some(c) = self.relay.client(id) {
+                    let _ = c.pong(&*payload);
+                }
+            },
+            Event::Disconnected(id)  => self.relay.disconnected(id)
+        }
+    }
+
+    fn run(&mut self,
+           requests: Receiver<(Request, TcpStream)>,
+           events:   Receiver<Event>) {
+        loop {
+            self.relay.poll_server(Duration::milliseconds(TICK_MS));
+            while let Ok((req, stream)) = requests.try_recv() {
+                self.accept(req, stream);
+            }
+            while let Ok(ev) = events.try_recv() {
+                self.client_event(ev);
+            }
+        }
+    }
+}
+
+fn run(args: Args) -> Baps3Result<()> {
+    let target = try!(resolve_target(&*args.flag_target));
+
+    let mut session = Session::new(&*target.addr,
+                                   &[],
+                                   Duration::seconds(args.flag_retry),
+                                   args.flag_verbose);
+
+    // Connect straight away, so that problems show up before any clients
+    // arrive, but carry on if the server isn't there yet.
+    if let Err(e) = session.connect() {
+        werr!("{} {}: {}\n", timestamp(), target.addr, e);
+    }
+
+    let (req_tx, req_rx) = channel();
+    try!(http::serve(&*args.flag_listen, req_tx));
+
+    let (ev_tx, ev_rx) = channel();
+    let mut bridge = Bridge { relay:   Relay::new(session, args.flag_log),
+                              events:  ev_tx,
+                              next_id: 0,
+                              origins: args.flag_allow_origin };
+    bridge.run(req_rx, ev_rx);
+    Ok(())
+}
+
+command_main!(run);
@@ -0,0 +1,240 @@
+#![feature(plugin)]
+
+extern crate baps3_protocol;
+#[macro_use] extern crate baps3_cli;
+
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate docopt;
+#[plugin] #[no_link] extern crate docopt_macros;
+
+use std::borrow::ToOwned;
+use std::io::{ FileType, IoResult, GROUP_EXECUTE, OTHER_EXECUTE, USER_EXECUTE };
+use std::io::fs::{ self, PathExtensions };
+use std::io::process::{ Command, InheritFd, ProcessExit };
+use std::os;
+
+use baps3_cli::completion::{ Shell, Spec, script, usage };
+
+docopt!(Args, concat!("
+Runs a BAPS3 command.
+
+Usage:
+  baps3 -h
+  baps3 --list
+  baps3 --completions <shell>
+  baps3 [-v] [-t <target>] [-T <secs>] [-f <format>] <command> [<args>...]
+
+Options:
+  -h, --help             Show this message.
+  -l, --list             List the available commands, including any
+                         baps3-* programs found on the PATH.
+  -T, --timeout <secs>   How long to wait for the server before giving up.
+                         By default, wait forever.
+  -f, --format <format>  How commands that print messages should format
+                         them: raw, pretty or json.
+", common_options!(), "
+Each command is a separate program; `baps3 COMMAND ARGS...` runs
+`baps3-COMMAND ARGS...`, looking first next to this program and then on the
+PATH.  `baps3 help COMMAND` shows the usage of a command.  The completion
+script printed by `baps3 --completions` covers all of the available commands.
+"));
+
+/// The prefix shared by the programs implementing each command.
+const PREFIX: &'static str = "baps3-";
+
+/// The commands that come with `baps3`, as opposed to plugins found on the
+/// PATH.
+const BUILTIN: &'static [&'static str] = &[
+    "asrun", "autodj", "check", "cli", "cue", "eject", "exporter", "http",
+    "list", "load", "osc", "play", "proxy", "quit", "schedule", "seek",
+    "status", "stop", "tail", "watchdog", "ws"
+];
+
+/// Determines whether `path` is a program we could run.
+fn is_executable(path: &Path) -> bool {
+    path.stat().map(|s| {
+        s.kind == FileType::RegularFile
+        && s.perm.intersects(USER_EXECUTE | GROUP_EXECUTE | OTHER_EXECUTE)
+    }).unwrap_or(false)
+}
+
+/// Returns the directories searched for commands, in order.
+fn search_dirs() -> Vec<Path> {
+    let mut dirs = vec![];
+
+    if let Some(d) = os::self_exe_path() {
+        dirs.push(d);
+    }
+    if let Some(p) = os::getenv("PATH") {
+        dirs.extend(os::split_paths(p).into_iter());
+    }
+
+    dirs
+}
+
+/// Finds the program implementing `command`.
+///
+/// Only plain names are looked up, so that a command can't name a program
+/// outside the search directories.
+fn find_command(command: &str) -> Option<Path> {
+    if command.is_empty() || command.contains("/") || command.contains(".") {
+        return None;
+    }
+
+    search_dirs().into_iter()
+                 .map(|d| d.join(format!("{}{}", PREFIX, command)))
+                 .find(|p| is_executable(p))
+}
+
+/// Finds the program implementing `command`, if it is one of ours installed
+/// alongside this program.
+fn find_builtin(command: &str) -> Option<Path> {
+    if !BUILTIN.contains(&command) {
+        return None;
+    }
+
+    os::self_exe_path().map(|d| d.join(format!("{}{}", PREFIX, command)))
+                       .and_then(|p| if is_executable(&p) { Some(p) } else { None })
+}
+
+/// Lists the names of every command that can be found.
+fn list_commands() -> Vec<String> {
+    let mut commands: Vec<String> = search_dirs().iter()
+        .filter_map(|d| fs::readdir(d).ok())
+        .flat_map(|entries| entries.into_iter())
+        .filter(|p| is_executable(p))
+        .filter_map(|p| p.filename_str().and_then(|n| {
+            // Skip things like manual pages that sit alongside the programs.
+            if n.starts_with(PREFIX) && !n.contains(".") {
+                Some(n[PREFIX.len()..].to_owned())
+            } else {
+                None
+            }
+        }))
+        .collect();
+
+    commands.sort();
+    commands.dedup();
+    commands
+}
+
+/// Runs `program` with `args`, passing our standard streams and the given
+/// environment through, and returns its exit status.
+fn run(program: &Path, args: &[String], env: &[(&str, String)])
+  -> IoResult<isize> {
+    let mut command = Command::new(program);
+    command.args(args)
+           .stdin(InheritFd(0))
+           .stdout(InheritFd(1))
+           .stderr(InheritFd(2));
+    for &(var, ref value) in env.iter() {
+        command.env(var, &**value);
+    }
+
+    Ok(match try!(command.status()) {
+        ProcessExit::ExitStatus(n) => n,
+        ProcessExit::ExitSignal(n) => 128 + n
+    })
+}
+
+/// Prints the completion script for `shell`.
+///
+/// Only our own commands are asked for their usage; plugins are completed
+/// by name alone, as running unknown programs to find out what they accept
+/// isn't safe.
+fn completions(shell: Shell) -> IoResult<()> {
+    let own      = os::self_exe_name().unwrap_or(Path::new("baps3"));
+    let umbrella = Spec::from_usage("baps3", &*try!(usage(&own)));
+
+    let mut commands = vec![];
+    for c in list_commands().iter() {
+        let name  = format!("{}{}", PREFIX, c);
+        let text  = match find_builtin(&**c) {
+            Some(p) => try!(usage(&p)),
+            None    => String::new()
+        };
+        commands.push(Spec::from_usage(&*name, &*text));
+    }
+
+    print!("{}", script(shell, &umbrella, &*commands));
+    Ok(())
+}
+
+/// Turns our global options into the environment variables our commands
+/// read their defaults from.
+fn global_env(args: &Args) -> Vec<(&'static str, String)> {
+    let mut env = vec![];
+
+    if args.flag_verbose {
+        env.push(("BAPS3_VERBOSE", "1".to_owned()));
+    }
+    if !args.flag_target.is_empty() {
+        env.push(("BAPS3_TARGET", args.flag_target.clone()));
+    }
+    if !args.flag_timeout.is_empty() {
+        env.push(("BAPS3_TIMEOUT", args.flag_timeout.clone()));
+    }
+    if !args.flag_format.is_empty() {
+        env.push(("BAPS3_FORMAT", args.flag_format.clone()));
+    }
+
+    env
+}
+
+fn main() {
+    let args: Args = Args::docopt().options_first(true)
+                                   .decode()
+                                   .unwrap_or_else(|e| e.exit());
+
+    if !args.flag_completions.is_empty() {
+        let result = match Shell::from_name(&*args.flag_completions) {
+            Some(shell) => completions(shell),
+            None        => {
+                werr!("error: unknown shell {} (try bash, zsh or fish)\n",
+                      args.flag_completions);
+                os::set_exit_status(1);
+                return;
+            }
+        };
+
+        if let Err(e) = result {
+            werr!("error: {}\n", e);
+            os::set_exit_status(1);
+        }
+        return;
+    }
+
+    if args.flag_list {
+        for c in list_commands().iter() {
+            println!("{}", c);
+        }
+        return;
+    }
+
+    // `baps3 help COMMAND` is `baps3 COMMAND -h`.
+    let (command, rest) = match (&*args.arg_command, args.arg_args.as_slice()) {
+        ("help", [ref c, ..]) => (c.clone(), vec!["-h".to_owned()]),
+        ("help", [])          => {
+            println!("Available commands: {}", list_commands().connect(" "));
+            return;
+        },
+        (c, rest) => (c.to_owned(), rest.to_vec())
+    };
+
+    let program = match find_command(&*command) {
+        Some(p) => p,
+        None    => {
+            werr!("error: unknown command {} (see baps3 --list)\n", command);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    match run(&program, &*rest, &*global_env(&args)) {
+        Ok(status) => os::set_exit_status(status),
+        Err(e)     => {
+            werr!("error: could not run {}: {}\n", program.display(), e);
+            os::set_exit_status(1);
+        }
+    }
+}
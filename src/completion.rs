@@ -0,0 +1,578 @@
+//! Shell completion scripts for the BAPS3 commands.
+//!
+//! The scripts are generated from the usage messages the commands print for
+//! `-h`, so they stay in step with the options the commands actually accept.
+//! A command with no usage message (a plugin we won't run, say) is completed
+//! by name only.
+//! Option values and arguments are completed according to their placeholders:
+//! `<target>` completes named targets from the configuration, `<file>`
+//! completes file paths, and `<format>` and `<shell>` complete their few
+//! possible values.
+
+use std::borrow::ToOwned;
+use std::io::IoResult;
+use std::io::process::Command;
+use std::os;
+
+use super::{ Baps3Error, Baps3Result };
+
+/// A shell we can generate completions for.
+#[derive(Copy)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish
+}
+
+impl Shell {
+    /// Looks up a shell by name.
+    pub fn from_name(name: &str) -> Option<Shell> {
+        match name {
+            "bash" => Some(Shell::Bash),
+            "zsh"  => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            _      => None
+        }
+    }
+}
+
+/// The sort of value an option or argument takes, and so how to complete it.
+#[derive(Clone, PartialEq, Show)]
+pub enum Value {
+    /// Something we can't usefully suggest anything for.
+    Any,
+
+    /// The name of a configured target.
+    Target,
+
+    /// A file path.
+    File,
+
+    /// One of a fixed set of words.
+    Choice(Vec<String>)
+}
+
+impl Value {
+    /// Works out the sort of value from its placeholder in a usage message.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::completion::Value;
+    /// assert_eq!(Value::from_placeholder("[-t <target>]"), Value::Target);
+    /// assert_eq!(Value::from_placeholder("<file>"), Value::File);
+    /// assert_eq!(Value::from_placeholder("<pos>"), Value::Any);
+    /// ```
+    pub fn from_placeholder(token: &str) -> Value {
+        let choice = |&: ws: &[&str]| {
+            Value::Choice(ws.iter().map(|w| (*w).to_owned()).collect())
+        };
+
+        match placeholder(token) {
+            Some("target") => Value::Target,
+            Some("file")   => Value::File,
+            Some("format") => choice(&["raw", "pretty", "json"]),
+            Some("shell")  => choice(&["bash", "zsh", "fish"]),
+            _              => Value::Any
+        }
+    }
+}
+
+/// Returns the name inside the `<...>` placeholder in `token`, if any.
+fn placeholder(token: &str) -> Option<&str> {
+    match (token.find('<'), token.find('>')) {
+        (Some(l), Some(r)) if l < r => Some(&token[l + 1..r]),
+        _                           => None
+    }
+}
+
+/// One option accepted by a command.
+#[derive(Clone, Show)]
+pub struct Opt {
+    /// The short form, such as `-t`.
+    pub short: Option<String>,
+
+    /// The long form, such as `--target`.
+    pub long: Option<String>,
+
+    /// The value the option takes, or `None` for a flag.
+    pub value: Option<Value>,
+
+    /// The first line of the option's description.
+    pub description: String
+}
+
+impl Opt {
+    /// Returns every form of the option.
+    fn names(&self) -> Vec<&str> {
+        self.short.iter().chain(self.long.iter()).map(|n| &**n).collect()
+    }
+}
+
+/// What we know about a command's usage.
+pub struct Spec {
+    /// The program name, such as `baps3-load`.
+    pub name: String,
+
+    /// The first line of the usage message.
+    pub summary: String,
+
+    pub options: Vec<Opt>,
+
+    /// How to complete the command's positional arguments, if it takes any.
+    pub args: Option<Value>
+}
+
+impl Spec {
+    /// Reads a command's docopt usage message.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::completion::{ Spec, Value };
+    /// let s = Spec::from_usage("baps3-load", "
+    /// Loads a file into a BAPS3 server.
+    ///
+    /// Usage:
+    ///   baps3-load [-pv] [-t <target>] <file>
+    ///
+    /// Options:
+    ///   -p, --play             Play the file once loaded.
+    ///
+    /// Common options:
+    ///   -v, --verbose          Be chatty.
+    ///   -t, --target <target>  The target BAPS3 server.
+    /// ");
+    /// assert_eq!(&*s.summary, "Loads a file into a BAPS3 server.");
+    /// assert_eq!(s.options.len(), 3);
+    /// assert_eq!(s.options[2].value, Some(Value::Target));
+    /// assert_eq!(s.args, Some(Value::File));
+    /// ```
+    pub fn from_usage(name: &str, usage: &str) -> Spec {
+        let mut options = vec![];
+        let mut args    = None;
+        let mut section = "";
+
+        for line in usage.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            // Section headings, and any prose, start at the left margin.
+            if !line.starts_with(" ") {
+                section = trimmed;
+                continue;
+            }
+
+            match section {
+                "Usage:" => {
+                    let tokens: Vec<&str> = trimmed.split(' ')
+                                                   .filter(|t| !t.is_empty())
+                                                   .collect();
+                    for (i, t) in tokens.iter().enumerate() {
+                        let is_value = 0 < i && tokens[i - 1]
+                            .trim_left_matches('[')
+                            .trim_left_matches('(')
+                            .starts_with("-");
+
+                        if args.is_none() && !is_value && placeholder(*t).is_some() {
+                            args = Some(Value::from_placeholder(*t));
+                        }
+                    }
+                },
+                "Options:" | "Common options:" if trimmed.starts_with("-") =>
+                    options.push(parse_option(trimmed)),
+                _ => ()
+            }
+        }
+
+        Spec { name:    name.to_owned(),
+               summary: usage.lines()
+                             .map(|l| l.trim())
+                             .find(|l| !l.is_empty())
+                             .unwrap_or("")
+                             .to_owned(),
+               options: options,
+               args:    args }
+    }
+}
+
+/// Parses one option line, such as `-t, --target <target>  The target.`
+fn parse_option(line: &str) -> Opt {
+    // The description is separated from the option by at least two spaces.
+    let (forms, description) = match line.find("  ") {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None    => (line, "")
+    };
+
+    let mut opt = Opt { short:       None,
+                        long:        None,
+                        value:       None,
+                        description: description.to_owned() };
+
+    for t in forms.split(|&: c: char| c == ',' || c == ' ' || c == '=')
+                  .filter(|t| !t.is_empty()) {
+        if t.starts_with("--") {
+            opt.long = Some(t.to_owned());
+        } else if t.starts_with("-") {
+            opt.short = Some(t.to_owned());
+        } else if placeholder(t).is_some() {
+            opt.value = Some(Value::from_placeholder(t));
+        }
+    }
+
+    opt
+}
+
+/// Generates a completion script for `umbrella` (the `baps3` command) and
+/// each of `commands`, which it runs as subcommands.
+pub fn script(shell: Shell, umbrella: &Spec, commands: &[Spec]) -> String {
+    match shell {
+        Shell::Bash => bash(umbrella, commands),
+        Shell::Zsh  => zsh(umbrella, commands),
+        Shell::Fish => fish(umbrella, commands)
+    }
+}
+
+/// Generates a completion script for `command` alone.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::completion::{ Shell, Spec, command_script };
+/// let s = Spec::from_usage("baps3-eject", "
+/// Unloads the currently loaded file from a BAPS3 server.
+///
+/// Usage:
+///   baps3-eject [-v] [-t <target>]
+///
+/// Common options:
+///   -v, --verbose          Be chatty.
+///   -t, --target <target>  The target BAPS3 server.
+/// ");
+/// assert!(command_script(Shell::Bash, &s)
+///             .contains("complete -o filenames -F _baps3_eject baps3-eject"));
+/// assert!(command_script(Shell::Fish, &s)
+///             .contains("complete -c baps3-eject -s t -l target"));
+/// ```
+pub fn command_script(shell: Shell, command: &Spec) -> String {
+    match shell {
+        Shell::Bash => format!("{}{}", bash_prelude(), bash_command(command)),
+        Shell::Zsh  => format!("{}{}{}",
+                               zsh_prelude(&[&*command.name]),
+                               zsh_command(command),
+                               ZSH_DISPATCH),
+        Shell::Fish => format!("{}{}", fish_prelude(), fish_command(command))
+    }
+}
+
+/// Reads the usage message of `program`, as printed for `-h`.
+pub fn usage(program: &Path) -> IoResult<String> {
+    Command::new(program)
+        .arg("-h")
+        .output()
+        .map(|o| String::from_utf8_lossy(&*o.output).into_owned())
+}
+
+/// Generates the completion script for the running command, for the shell
+/// named `shell`, as its `--completions` option asks.
+pub fn own_script(shell: &str) -> Baps3Result<String> {
+    let shell = try!(Shell::from_name(shell).ok_or(Baps3Error::CmdInvalid {
+        advice: format!("unknown shell {} (try bash, zsh or fish)", shell)
+    }));
+    let program = try!(os::self_exe_name().ok_or(Baps3Error::InvalidPath {
+        path: "(this program)".to_owned()
+    }));
+    let name    = program.filename_str().unwrap_or("").to_owned();
+    let spec    = Spec::from_usage(&*name, &*try!(usage(&program)));
+
+    Ok(command_script(shell, &spec))
+}
+
+/// Lists the names of the targets in the configuration file, one per line.
+const TARGETS_SED: &'static str =
+    r"sed -n 's/^[[:space:]]*\[\(.*\)\][[:space:]]*$/\1/p'";
+
+/// Returns the name by which `umbrella` runs `command`.
+fn subcommand<'a>(umbrella: &Spec, command: &'a Spec) -> &'a str {
+    let prefix = format!("{}-", umbrella.name);
+    if command.name.starts_with(&*prefix) {
+        &command.name[prefix.len()..]
+    } else {
+        &*command.name
+    }
+}
+
+/// Returns the name of the shell function completing `program`.
+fn function(program: &str) -> String {
+    format!("_{}", program.replace("-", "_"))
+}
+
+/// The umbrella's own options, with its positional argument being the name
+/// of a subcommand.
+fn umbrella_spec(umbrella: &Spec, commands: &[Spec]) -> Spec {
+    Spec { name:    umbrella.name.clone(),
+           summary: umbrella.summary.clone(),
+           options: umbrella.options.clone(),
+           args:    Some(Value::Choice(commands.iter()
+                                               .map(|c| subcommand(umbrella, c)
+                                                          .to_owned())
+                                               .collect())) }
+}
+
+fn bash_reply(value: &Value) -> String {
+    match *value {
+        Value::Any        => "COMPREPLY=()".to_owned(),
+        Value::Target     =>
+            "COMPREPLY=($(compgen -W \"$(__baps3_targets)\" -- \"$cur\"))".to_owned(),
+        Value::File       => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_owned(),
+        Value::Choice(ref ws) =>
+            format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", ws.connect(" "))
+    }
+}
+
+/// The part of a bash completion function that completes the current word,
+/// given the previous one.
+fn bash_body(spec: &Spec) -> String {
+    let mut out   = String::new();
+    let mut names = vec![];
+
+    out.push_str("    case \"$prev\" in\n");
+    for o in spec.options.iter() {
+        names.extend(o.names().into_iter());
+        if let Some(ref v) = o.value {
+            out.push_str(&*format!("        {}) {}; return;;\n",
+                                   o.names().connect("|"), bash_reply(v)));
+        }
+    }
+    out.push_str("    esac\n");
+
+    out.push_str(&*format!("    if [[ \"$cur\" == -* ]]; then\n        \
+                            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n    \
+                            else\n        {}\n    fi\n",
+                           names.connect(" "),
+                           bash_reply(spec.args.as_ref().unwrap_or(&Value::Any))));
+    out
+}
+
+/// The start of every bash completion function, finding the current and
+/// previous words.
+const BASH_LOCALS: &'static str = "    local cur=\"${COMP_WORDS[COMP_CWORD]}\" \
+                                   prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n";
+
+fn bash_prelude() -> String {
+    format!("__baps3_targets() {{\n    \
+             {} \"${{BAPS3_HOME:-$HOME/.baps3}}/targets\" 2>/dev/null\n\
+             }}\n\n", TARGETS_SED)
+}
+
+fn bash_command(command: &Spec) -> String {
+    format!("{}() {{\n{}{}}}\ncomplete -o filenames -F {} {}\n\n",
+            function(&*command.name), BASH_LOCALS, bash_body(command),
+            function(&*command.name), command.name)
+}
+
+fn bash(umbrella: &Spec, commands: &[Spec]) -> String {
+    let mut out = bash_prelude();
+
+    for c in commands.iter() {
+        out.push_str(&*bash_command(c));
+    }
+
+    // The umbrella hands over to the subcommand's function once it has seen
+    // the subcommand, skipping its own options and their values.
+    let valued: Vec<&str> = umbrella.options.iter()
+                                            .filter(|o| o.value.is_some())
+                                            .flat_map(|o| o.names().into_iter())
+                                            .collect();
+    let skip = if valued.is_empty() {
+        String::new()
+    } else {
+        format!("            {}) ((i++));;\n", valued.connect("|"))
+    };
+
+    let f = function(&*umbrella.name);
+    out.push_str(&*format!("{}() {{\n{}    local i\n    \
+                            for ((i = 1; i < COMP_CWORD; i++)); do\n        \
+                            case \"${{COMP_WORDS[i]}}\" in\n\
+                            {}            -*) ;;\n            \
+                            *) declare -F \"{}_${{COMP_WORDS[i]}}\" >/dev/null \
+                            && \"{}_${{COMP_WORDS[i]}}\"; return;;\n        \
+                            esac\n    done\n{}}}\n\
+                            complete -o filenames -F {} {}\n",
+                           f, BASH_LOCALS, skip, f, f,
+                           bash_body(&umbrella_spec(umbrella, commands)),
+                           f, umbrella.name));
+    out
+}
+
+/// Escapes text for use inside a single-quoted `_arguments` description.
+fn zsh_escape(s: &str) -> String {
+    s.replace("'", "'\\''")
+     .replace("[", "\\[")
+     .replace("]", "\\]")
+     .replace(":", "\\:")
+}
+
+fn zsh_action(value: &Value) -> String {
+    match *value {
+        Value::Any            => " ".to_owned(),
+        Value::Target         => "__baps3_targets".to_owned(),
+        Value::File           => "_files".to_owned(),
+        Value::Choice(ref ws) => format!("({})", ws.connect(" "))
+    }
+}
+
+/// The `_arguments` specs for a command's options.
+fn zsh_options(spec: &Spec) -> Vec<String> {
+    spec.options.iter().map(|o| {
+        let names = o.names();
+        let body  = format!("[{}]{}",
+                            zsh_escape(&*o.description),
+                            match o.value {
+                                Some(ref v) => format!(":value:{}", zsh_action(v)),
+                                None        => String::new()
+                            });
+
+        if names.len() == 1 {
+            format!("'{}{}'", names[0], body)
+        } else {
+            format!("'({})'{{{}}}'{}'", names.connect(" "), names.connect(","), body)
+        }
+    }).collect()
+}
+
+/// The end of a zsh completion script, running the function for whichever
+/// of its commands is being completed.
+const ZSH_DISPATCH: &'static str = "_${service//-/_} \"$@\"\n";
+
+fn zsh_prelude(names: &[&str]) -> String {
+    format!("#compdef {}\n\n\
+             __baps3_targets() {{\n    \
+             compadd -- ${{(f)\"$({} \
+             \"${{BAPS3_HOME:-$HOME/.baps3}}/targets\" 2>/dev/null)\"}}\n\
+             }}\n\n",
+            names.connect(" "), TARGETS_SED)
+}
+
+fn zsh_command(command: &Spec) -> String {
+    let mut specs = zsh_options(command);
+    if let Some(ref v) = command.args {
+        specs.push(format!("'*:argument:{}'", zsh_action(v)));
+    }
+
+    format!("{}() {{\n    _arguments -s \\\n        {}\n}}\n\n",
+            function(&*command.name),
+            specs.connect(" \\\n        "))
+}
+
+fn zsh(umbrella: &Spec, commands: &[Spec]) -> String {
+    let mut names = vec![&*umbrella.name];
+    names.extend(commands.iter().map(|c| &*c.name));
+
+    let mut out = zsh_prelude(&*names);
+
+    for c in commands.iter() {
+        out.push_str(&*zsh_command(c));
+    }
+
+    let f         = function(&*umbrella.name);
+    let mut specs = zsh_options(umbrella);
+    specs.push(format!("'1:command:{}'",
+                       zsh_action(umbrella_spec(umbrella, commands)
+                                      .args.as_ref().unwrap())));
+    specs.push("'*::argument:->args'".to_owned());
+
+    out.push_str(&*format!("{}() {{\n    local curcontext=\"$curcontext\" state line\n    \
+                            _arguments -C -s \\\n        {}\n\n    \
+                            if [[ $state == args ]] \
+                            && (( $+functions[{}_$words[1]] )); then\n        \
+                            {}_$words[1]\n    fi\n}}\n\n{}",
+                           f, specs.connect(" \\\n        "), f, f, ZSH_DISPATCH));
+    out
+}
+
+/// Escapes text for use inside a single-quoted fish string.
+fn fish_escape(s: &str) -> String {
+    s.replace("\\", "\\\\").replace("'", "\\'")
+}
+
+/// Returns the `complete` flags for offering `value`.
+fn fish_value(value: &Value) -> String {
+    match *value {
+        Value::Any            => "-x".to_owned(),
+        Value::Target         => "-x -a '(__baps3_targets)'".to_owned(),
+        Value::File           => "-r -F".to_owned(),
+        Value::Choice(ref ws) => format!("-x -a '{}'", fish_escape(&*ws.connect(" ")))
+    }
+}
+
+/// The `complete` lines for a command's options and arguments, each starting
+/// with `prefix`.
+fn fish_lines(prefix: &str, spec: &Spec) -> String {
+    let mut out = String::new();
+
+    for o in spec.options.iter() {
+        let mut line = prefix.to_owned();
+        if let Some(ref s) = o.short {
+            line.push_str(&*format!(" -s {}", s.trim_left_matches('-')));
+        }
+        if let Some(ref l) = o.long {
+            line.push_str(&*format!(" -l {}", l.trim_left_matches('-')));
+        }
+        if let Some(ref v) = o.value {
+            line.push_str(&*format!(" {}", fish_value(v)));
+        }
+        line.push_str(&*format!(" -d '{}'\n", fish_escape(&*o.description)));
+        out.push_str(&*line);
+    }
+
+    match spec.args {
+        Some(Value::File) => out.push_str(&*format!("{} -F\n", prefix)),
+        Some(Value::Target) | Some(Value::Choice(_)) => out.push_str(
+            &*format!("{} -f {}\n", prefix,
+                      fish_value(spec.args.as_ref().unwrap())
+                          .trim_left_matches("-x "))),
+        _ => ()
+    }
+
+    out
+}
+
+fn fish_prelude() -> String {
+    format!("function __baps3_targets\n    \
+             set -l dir $HOME/.baps3\n    \
+             set -q BAPS3_HOME; and set dir $BAPS3_HOME\n    \
+             {} $dir/targets 2>/dev/null\n\
+             end\n\n", TARGETS_SED)
+}
+
+fn fish_command(command: &Spec) -> String {
+    format!("complete -c {} -f\n{}",
+            command.name,
+            fish_lines(&*format!("complete -c {}", command.name), command))
+}
+
+fn fish(umbrella: &Spec, commands: &[Spec]) -> String {
+    let mut out = fish_prelude();
+
+    for c in commands.iter() {
+        let sub = subcommand(umbrella, c);
+
+        out.push_str(&*fish_command(c));
+        out.push_str(&*format!("complete -c {} -f -n __fish_use_subcommand -a {} -d '{}'\n",
+                               umbrella.name, sub, fish_escape(&*c.summary)));
+        out.push_str(&*fish_lines(&*format!("complete -c {} -n '__fish_seen_subcommand_from {}'",
+                                            umbrella.name, sub), c));
+        out.push_str("\n");
+    }
+
+    out.push_str(&*format!("complete -c {} -f\n", umbrella.name));
+    out.push_str(&*fish_lines(&*format!("complete -c {} -n __fish_use_subcommand",
+                                         umbrella.name),
+                              &Spec { name:    umbrella.name.clone(),
+                                      summary: umbrella.summary.clone(),
+                                      options: umbrella.options.clone(),
+                                      args:    None }));
+    out
+}
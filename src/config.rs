@@ -0,0 +1,228 @@
+//! Named BAPS3 targets, read from the user's configuration.
+//!
+//! The configuration lives in `~/.baps3/targets` (or `$BAPS3_HOME/targets`),
+//! and consists of one section per named target:
+//!
+//! ```text
+//! # The studio 1 playout box.
+//! [studio1]
+//! addr = playout1:1350
+//! map  = /home/me/music=/srv/music
+//! ```
+//!
+//! Anywhere a tool accepts a target, it accepts either the name of one of
+//! these sections or a literal `host:port` address.
+//!
+//! Defaults for options shared by all of the tools can also be given in the
+//! environment, which is how the `baps3` command passes its global options on
+//! to its subcommands:
+//!
+//! - `BAPS3_TARGET`: the target to use if none is given;
+//! - `BAPS3_VERBOSE`: if set and not empty, log as if `-v` were given;
+//! - `BAPS3_TIMEOUT`: how long, in seconds, to wait for the server;
+//! - `BAPS3_FORMAT`: the output format for tools that print messages.
+
+use std::borrow::ToOwned;
+use std::io::{ File, IoErrorKind };
+use std::os;
+use std::time::Duration;
+
+use super::{ Baps3Error, Baps3Result };
+use super::path::PathMap;
+
+/// A BAPS3 server the user can refer to by name.
+#[derive(Clone, Show)]
+pub struct Target {
+    /// The name of the target, if it came from the configuration.
+    pub name: Option<String>,
+
+    /// The TCP address of the server, in `host:port` form.
+    pub addr: String,
+
+    /// Path mapping rules to apply to files sent to this server.
+    pub maps: Vec<PathMap>
+}
+
+impl Target {
+    /// Constructs an unnamed target with no path mapping rules.
+    pub fn literal(addr: &str) -> Target {
+        Target { name: None, addr: addr.to_owned(), maps: vec![] }
+    }
+}
+
+/// The set of named targets known to the user.
+pub struct Config {
+    pub targets: Vec<Target>
+}
+
+impl Config {
+    /// Loads the user's configuration.
+    ///
+    /// A missing configuration file is treated as an empty one.
+    pub fn load() -> Baps3Result<Config> {
+        let path = match config_file("targets") {
+            Some(p) => p,
+            None    => return Ok(Config { targets: vec![] })
+        };
+
+        match File::open(&path).read_to_string() {
+            Ok(src) => Config::parse(&*src),
+            Err(ref e) if e.kind == IoErrorKind::FileNotFound
+                    => Ok(Config { targets: vec![] }),
+            Err(e)  => Err(Baps3Error::Io { err: e })
+        }
+    }
+
+    /// Parses a configuration from its textual form.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::config::Config;
+    /// let c = Config::parse("[studio1]\naddr = playout1:1350\n").ok().unwrap();
+    /// assert_eq!(&*c.resolve("studio1").addr, "playout1:1350");
+    /// assert_eq!(&*c.resolve("localhost:1350").addr, "localhost:1350");
+    /// ```
+    ///
+    /// Errors give the line they were found on; a target without an address
+    /// is reported at its section header:
+    ///
+    /// ```rust
+    /// use baps3_cli::Baps3Error;
+    /// use baps3_cli::config::Config;
+    /// match Config::parse("[a]\naddr = a:1350\n\n[b]\nmap = /x=/y\n") {
+    ///     Err(Baps3Error::BadConfig { line, .. }) => assert_eq!(line, 4),
+    ///     _                                       => panic!("expected an error")
+    /// }
+    /// ```
+    pub fn parse(src: &str) -> Baps3Result<Config> {
+        let mut targets: Vec<Target> = vec![];
+        // The line each target's section header is on, for error reports.
+        let mut headers: Vec<usize>  = vec![];
+
+        for (n, raw) in src.lines().enumerate() {
+            let line = raw.trim();
+            let bad  = |&: reason: &str| Baps3Error::BadConfig {
+                line:   n + 1,
+                reason: reason.to_owned()
+            };
+
+            if line.is_empty() || line.starts_with("#") {
+                continue;
+            }
+
+            if line.starts_with("[") {
+                if !line.ends_with("]") || line.len() < 3 {
+                    return Err(bad("malformed section header"));
+                }
+                let mut t = Target::literal("");
+                t.name = Some(line[1..line.len() - 1].trim().to_owned());
+                targets.push(t);
+                headers.push(n + 1);
+                continue;
+            }
+
+            let target = match targets.last_mut() {
+                Some(t) => t,
+                None    => return Err(bad("setting outside of a [target]"))
+            };
+
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None    => return Err(bad("expected key = value"))
+            };
+
+            match key {
+                "addr" => target.addr = value.to_owned(),
+                "map"  => match PathMap::parse(value) {
+                    Some(m) => target.maps.push(m),
+                    None    => return Err(bad("expected map = CLIENT=SERVER"))
+                },
+                _      => return Err(bad("unknown setting"))
+            }
+        }
+
+        if let Some((t, &line)) = targets.iter()
+                                         .zip(headers.iter())
+                                         .find(|&(t, _)| t.addr.is_empty()) {
+            return Err(Baps3Error::BadConfig {
+                line:   line,
+                reason: format!("target {:?} has no addr",
+                                t.name.clone().unwrap_or(String::new()))
+            });
+        }
+
+        Ok(Config { targets: targets })
+    }
+
+    /// Looks up a target by name.
+    pub fn target(&self, name: &str) -> Option<&Target> {
+        self.targets.iter().find(|t| t.name.as_ref().map(|n| &**n) == Some(name))
+    }
+
+    /// Resolves a target given on the command line.
+    ///
+    /// If `target` names a configured target, that target is returned;
+    /// otherwise, `target` is taken to be a literal `host:port` address.
+    pub fn resolve(&self, target: &str) -> Target {
+        self.target(target)
+            .map(|t| t.clone())
+            .unwrap_or_else(|| Target::literal(target))
+    }
+}
+
+/// Returns the directory holding the user's BAPS3 configuration, if any.
+pub fn config_dir() -> Option<Path> {
+    os::getenv("BAPS3_HOME")
+      .map(|d| Path::new(d))
+      .or_else(|| os::homedir().map(|h| h.join(".baps3")))
+}
+
+/// Returns the path of the named file in the user's BAPS3 configuration.
+pub fn config_file(name: &str) -> Option<Path> {
+    config_dir().map(|d| d.join(name))
+}
+
+/// Resolves a target given on the command line against the user's
+/// configuration.
+///
+/// An empty `target` means the default target: `$BAPS3_TARGET` if set, or
+/// `localhost:1350` otherwise.
+pub fn resolve_target(target: &str) -> Baps3Result<Target> {
+    let target = if target.is_empty() { default_target() }
+                 else                 { target.to_owned() };
+    Config::load().map(|c| c.resolve(&*target))
+}
+
+/// The address or name of the target to use when none is given.
+pub fn default_target() -> String {
+    env_default("BAPS3_TARGET").unwrap_or("localhost:1350".to_owned())
+}
+
+/// Whether verbose logging has been asked for in the environment.
+pub fn default_verbose() -> bool {
+    env_default("BAPS3_VERBOSE").is_some()
+}
+
+/// How long to wait for the server before giving up, if at all.
+///
+/// Malformed or non-positive timeouts are ignored.
+pub fn default_timeout() -> Option<Duration> {
+    env_default("BAPS3_TIMEOUT")
+        .and_then(|t| t.parse::<f64>())
+        .and_then(|s| if 0.0 < s {
+            Some(Duration::milliseconds((s * 1000.0) as i64))
+        } else {
+            None
+        })
+}
+
+/// The output format asked for in the environment, if any.
+pub fn default_format() -> Option<String> {
+    env_default("BAPS3_FORMAT")
+}
+
+/// Reads an environment variable, treating an empty value as unset.
+fn env_default(var: &str) -> Option<String> {
+    os::getenv(var).and_then(|v| if v.is_empty() { None } else { Some(v) })
+}
@@ -0,0 +1,160 @@
+//! Named cue points within files, such as "vocal in" or "outro".
+//!
+//! Cues are kept in `~/.baps3/cues` (or `$BAPS3_HOME/cues`), one per line, as
+//! the file's absolute path (as the server sees it), the cue's name and its
+//! position in microseconds, separated by tabs.
+
+use std::borrow::ToOwned;
+use std::io::{ File, IoErrorKind, USER_RWX };
+use std::io::fs;
+
+use super::{ Baps3Error, Baps3Result };
+use super::config::config_file;
+
+/// A named position in a file.
+#[derive(Clone, PartialEq, Show)]
+pub struct Cue {
+    /// The absolute path of the file, as the server sees it.
+    pub file: String,
+
+    pub name: String,
+
+    /// The position of the cue, in microseconds.
+    pub micros: u64
+}
+
+/// The set of cues known to the user.
+pub struct CueStore {
+    pub cues: Vec<Cue>
+}
+
+impl CueStore {
+    /// Loads the user's cues.
+    ///
+    /// A missing cue file is treated as an empty one.
+    pub fn load() -> Baps3Result<CueStore> {
+        let path = match config_file("cues") {
+            Some(p) => p,
+            None    => return Ok(CueStore { cues: vec![] })
+        };
+
+        match File::open(&path).read_to_string() {
+            Ok(src) => CueStore::parse(&*src),
+            Err(ref e) if e.kind == IoErrorKind::FileNotFound
+                    => Ok(CueStore { cues: vec![] }),
+            Err(e)  => Err(Baps3Error::Io { err: e })
+        }
+    }
+
+    /// Parses cues from their textual form.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::cue::CueStore;
+    /// let s = CueStore::parse("/srv/a.mp3\tvocal in\t12500000\n").ok().unwrap();
+    /// assert_eq!(s.get("/srv/a.mp3", "vocal in").map(|c| c.micros),
+    ///            Some(12500000));
+    /// assert!(CueStore::parse("/srv/a.mp3\toutro\n").is_err());
+    /// ```
+    pub fn parse(src: &str) -> Baps3Result<CueStore> {
+        let mut cues = vec![];
+
+        for (n, line) in src.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                [file, name, us] => match us.parse::<u64>() {
+                    Some(micros) => cues.push(Cue { file:   file.to_owned(),
+                                                    name:   name.to_owned(),
+                                                    micros: micros }),
+                    None => return Err(Baps3Error::BadConfig {
+                        line:   n + 1,
+                        reason: "cue position is not a number".to_owned()
+                    })
+                },
+                _ => return Err(Baps3Error::BadConfig {
+                    line:   n + 1,
+                    reason: "expected FILE<tab>NAME<tab>MICROSECONDS".to_owned()
+                })
+            }
+        }
+
+        Ok(CueStore { cues: cues })
+    }
+
+    /// Saves the cues, replacing the user's cue file.
+    pub fn save(&self) -> Baps3Result<()> {
+        let path = try!(config_file("cues").ok_or(Baps3Error::InvalidPath {
+            path: "~/.baps3/cues".to_owned()
+        }));
+
+        try!(fs::mkdir_recursive(&path.dir_path(), USER_RWX));
+        let mut f = try!(File::create(&path));
+        for c in self.cues.iter() {
+            try!(f.write_line(&*format!("{}\t{}\t{}", c.file, c.name, c.micros)));
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the cue called `name` in `file`.
+    pub fn get(&self, file: &str, name: &str) -> Option<&Cue> {
+        self.cues.iter().find(|c| &*c.file == file && &*c.name == name)
+    }
+
+    /// Returns the cues in `file`, in order of position.
+    pub fn for_file(&self, file: &str) -> Vec<&Cue> {
+        let mut cues: Vec<&Cue> = self.cues.iter()
+                                           .filter(|c| &*c.file == file)
+                                           .collect();
+        cues.sort_by(|a, b| a.micros.cmp(&b.micros));
+        cues
+    }
+
+    /// Sets the cue called `name` in `file`, replacing any existing one.
+    ///
+    /// Neither names nor files may contain tabs or newlines, as these would
+    /// corrupt the cue file.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::cue::CueStore;
+    /// let mut s = CueStore { cues: vec![] };
+    /// assert!(s.set("/srv/a.mp3", "outro", 1000000).is_ok());
+    /// assert!(s.set("/srv/a.mp3", "out\tro", 1000000).is_err());
+    /// assert!(s.set("/srv/a\nb.mp3", "outro", 1000000).is_err());
+    /// assert_eq!(s.cues.len(), 1);
+    /// ```
+    pub fn set(&mut self, file: &str, name: &str, micros: u64) -> Baps3Result<()> {
+        let unstorable = |&: s: &str| s.contains("\t") || s.contains("\n");
+
+        if name.is_empty() || unstorable(name) {
+            return Err(Baps3Error::CmdInvalid {
+                advice: format!("bad cue name {:?}", name)
+            });
+        }
+        if unstorable(file) {
+            return Err(Baps3Error::InvalidPath { path: format!("{:?}", file) });
+        }
+
+        self.remove(file, name);
+        self.cues.push(Cue { file:   file.to_owned(),
+                             name:   name.to_owned(),
+                             micros: micros });
+        Ok(())
+    }
+
+    /// Removes the cue called `name` in `file`.
+    ///
+    /// Returns false if there was no such cue.
+    pub fn remove(&mut self, file: &str, name: &str) -> bool {
+        let before = self.cues.len();
+        self.cues.retain(|c| !(&*c.file == file && &*c.name == name));
+        self.cues.len() != before
+    }
+}
@@ -0,0 +1,384 @@
+//! A small line editor with history and tab completion, for interactive
+//! BAPS3 clients.
+//!
+//! On a terminal, this puts the terminal into non-canonical mode with `stty`
+//! and does its own editing; elsewhere (for example, when input is piped in),
+//! it just reads lines.
+
+use std::borrow::ToOwned;
+use std::cmp;
+use std::io::{ File, IoErrorKind, IoResult, Append, Write, USER_RWX };
+use std::io::fs;
+use std::io::process::{ Command, InheritFd };
+use std::io::stdio::stdin_raw;
+
+use libc;
+
+/// The most history entries to keep.
+const MAX_HISTORY: usize = 1000;
+
+/// Something that can suggest completions for a partial line.
+pub trait Completer {
+    /// Given the line so far (up to the cursor), returns the byte offset at
+    /// which the text being completed starts, and the candidates that could
+    /// replace it.
+    fn complete(&self, line: &str) -> (usize, Vec<String>);
+}
+
+/// A completer that never suggests anything.
+pub struct NoCompleter;
+
+impl Completer for NoCompleter {
+    fn complete(&self, line: &str) -> (usize, Vec<String>) {
+        (line.len(), vec![])
+    }
+}
+
+/// Completes `word` against a fixed list of `candidates`.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::edit::complete_word;
+/// assert_eq!(complete_word("pl", &["play", "stop", "plop"]),
+///            vec!["play".to_string(), "plop".to_string()]);
+/// ```
+pub fn complete_word(word: &str, candidates: &[&str]) -> Vec<String> {
+    candidates.iter()
+              .filter(|c| c.starts_with(word))
+              .map(|c| (*c).to_owned())
+              .collect()
+}
+
+/// Completes `partial` as a filesystem path.
+///
+/// Directories are suggested with a trailing `/`.
+pub fn complete_path(partial: &str) -> Vec<String> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(i) => (&partial[..i + 1], &partial[i + 1..]),
+        None    => ("", partial)
+    };
+    let dir_path = Path::new(if dir.is_empty() { "." } else { dir });
+
+    let mut candidates: Vec<String> = match fs::readdir(&dir_path) {
+        Ok(entries) => entries.iter().filter_map(|e| {
+            let name = match e.filename_str() {
+                Some(n) => n,
+                None    => return None
+            };
+            // Hidden files only appear if asked for.
+            if !name.starts_with(prefix)
+               || (name.starts_with(".") && !prefix.starts_with(".")) {
+                return None;
+            }
+            Some(format!("{}{}{}", dir, name, if e.is_dir() { "/" } else { "" }))
+        }).collect(),
+        Err(_) => vec![]
+    };
+
+    candidates.sort();
+    candidates
+}
+
+/// Returns the longest common prefix of `strings`.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::edit::common_prefix;
+/// assert_eq!(&*common_prefix(&["seek".to_string(), "seen".to_string()]),
+///            "see");
+/// ```
+pub fn common_prefix(strings: &[String]) -> String {
+    let first = match strings.first() {
+        Some(f) => f,
+        None    => return String::new()
+    };
+
+    let mut len = first.len();
+    for s in strings.iter().skip(1) {
+        len = cmp::min(len, first.chars()
+                                 .zip(s.chars())
+                                 .take_while(|&(a, b)| a == b)
+                                 .fold(0, |n, (a, _)| n + a.len_utf8()));
+    }
+    first[..len].to_owned()
+}
+
+/// A key press, decoded from the terminal's input.
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Tab,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    KillToEnd,
+    KillToStart,
+    KillWord,
+    Interrupt,
+    Eof,
+    Other
+}
+
+/// A line editor.
+pub struct LineEditor {
+    history:      Vec<String>,
+    history_file: Option<Path>,
+    tty:          bool
+}
+
+impl LineEditor {
+    /// Creates a line editor.
+    ///
+    /// If `history_file` is given, history is loaded from it, and each line
+    /// entered is appended to it.  The file is cut down to the last
+    /// `MAX_HISTORY` lines whenever it grows past them.
+    pub fn new(history_file: Option<Path>) -> LineEditor {
+        let mut history: Vec<String> = history_file.as_ref()
+            .and_then(|p| File::open(p).read_to_string().ok())
+            .map(|h| h.lines()
+                      .filter(|l| !l.is_empty())
+                      .map(|l| l.to_owned())
+                      .collect())
+            .unwrap_or(vec![]);
+
+        let excess = if MAX_HISTORY < history.len() { history.len() - MAX_HISTORY }
+                     else                           { 0                            };
+        history    = history[excess..].to_vec();
+
+        let editor = LineEditor { history:      history,
+                                  history_file: history_file,
+                                  tty:          unsafe { libc::isatty(0) != 0 } };
+        if 0 < excess {
+            editor.save_history();
+        }
+        editor
+    }
+
+    /// Reads a line, showing `prompt` if on a terminal.
+    ///
+    /// Returns `None` at the end of input.
+    pub fn read_line<C: Completer>(&mut self, prompt: &str, completer: &C)
+      -> IoResult<Option<String>> {
+        if !self.tty {
+            return match ::std::io::stdin().read_line() {
+                Ok(l) => Ok(Some(l.trim_right_matches('\n').to_owned())),
+                Err(ref e) if e.kind == IoErrorKind::EndOfFile => Ok(None),
+                Err(e) => Err(e)
+            };
+        }
+
+        let saved  = try!(stty(&["-g"]));
+        try!(stty(&["-icanon", "-echo", "-isig", "min", "1"]));
+        let result = self.edit(prompt, completer);
+        try!(stty(&[saved.trim()]));
+        print!("\n");
+
+        if let Ok(Some(ref l)) = result {
+            self.remember(&**l);
+        }
+        result
+    }
+
+    /// Adds a line to the history, and to the history file if any.
+    fn remember(&mut self, line: &str) {
+        if line.trim().is_empty()
+           || self.history.last().map(|l| &**l) == Some(line) {
+            return;
+        }
+
+        self.history.push(line.to_owned());
+        if MAX_HISTORY < self.history.len() {
+            self.history.remove(0);
+            self.save_history();
+            return;
+        }
+
+        if let Some(ref p) = self.history_file {
+            // Losing history is not worth interrupting the user over.
+            let _ = fs::mkdir_recursive(&p.dir_path(), USER_RWX);
+            let _ = File::open_mode(p, Append, Write)
+                         .and_then(|mut f| f.write_line(line));
+        }
+    }
+
+    /// Rewrites the history file, if any, with just the history we keep.
+    fn save_history(&self) {
+        if let Some(ref p) = self.history_file {
+            let _ = fs::mkdir_recursive(&p.dir_path(), USER_RWX);
+            let _ = File::create(p).and_then(|mut f| {
+                for l in self.history.iter() {
+                    try!(f.write_line(&**l));
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Runs the editor proper, on a terminal in non-canonical mode.
+    fn edit<C: Completer>(&mut self, prompt: &str, completer: &C)
+      -> IoResult<Option<String>> {
+        let mut buf: Vec<char> = vec![];
+        let mut pos            = 0us;
+        // Where we are in the history; history.len() is the line being edited.
+        let mut hpos           = self.history.len();
+        let mut stash          = String::new();
+
+        refresh(prompt, &*buf, pos);
+
+        loop {
+            match try!(read_key()) {
+                Key::Char(c) => { buf.insert(pos, c); pos += 1; },
+                Key::Enter   => return Ok(Some(buf.into_iter().collect())),
+                Key::Eof if buf.is_empty() => return Ok(None),
+                Key::Eof | Key::Delete => if pos < buf.len() {
+                    buf.remove(pos);
+                },
+                Key::Backspace => if 0 < pos {
+                    pos -= 1;
+                    buf.remove(pos);
+                },
+                Key::Left  => if 0 < pos         { pos -= 1 },
+                Key::Right => if pos < buf.len() { pos += 1 },
+                Key::Home  => pos = 0,
+                Key::End   => pos = buf.len(),
+                Key::KillToEnd   => buf.truncate(pos),
+                Key::KillToStart => {
+                    buf = buf[pos..].to_vec();
+                    pos = 0;
+                },
+                Key::KillWord => {
+                    let mut start = pos;
+                    while 0 < start && buf[start - 1] == ' ' { start -= 1; }
+                    while 0 < start && buf[start - 1] != ' ' { start -= 1; }
+                    buf = buf[..start].iter().chain(buf[pos..].iter())
+                                      .map(|&c| c).collect();
+                    pos = start;
+                },
+                Key::Interrupt => {
+                    buf.clear();
+                    pos = 0;
+                    print!("^C\n");
+                },
+                Key::Up => if 0 < hpos {
+                    if hpos == self.history.len() {
+                        stash = buf.iter().map(|&c| c).collect();
+                    }
+                    hpos -= 1;
+                    buf = self.history[hpos].chars().collect();
+                    pos = buf.len();
+                },
+                Key::Down => if hpos < self.history.len() {
+                    hpos += 1;
+                    buf = if hpos == self.history.len() { stash.chars().collect() }
+                          else { self.history[hpos].chars().collect() };
+                    pos = buf.len();
+                },
+                Key::Tab => {
+                    let before: String = buf[..pos].iter().map(|&c| c).collect();
+                    let (start, candidates) = completer.complete(&*before);
+                    let prefix = common_prefix(&*candidates);
+                    let word   = &before[start..];
+
+                    let insert = if candidates.len() == 1 {
+                        let c = &candidates[0];
+                        if c.ends_with("/") { c.clone() } else { format!("{} ", c) }
+                    } else {
+                        prefix
+                    };
+
+                    if word.len() < insert.len() && insert.starts_with(word) {
+                        let tail: Vec<char> = insert[word.len()..].chars().collect();
+                        for (i, &c) in tail.iter().enumerate() {
+                            buf.insert(pos + i, c);
+                        }
+                        pos += tail.len();
+                    } else if 1 < candidates.len() {
+                        print!("\n{}\n", candidates.connect("  "));
+                    }
+                },
+                Key::Other => ()
+            }
+
+            refresh(prompt, &*buf, pos);
+        }
+    }
+}
+
+/// Redraws the line being edited, leaving the cursor at `pos`.
+fn refresh(prompt: &str, buf: &[char], pos: usize) {
+    let line: String = buf.iter().map(|&c| c).collect();
+    let back         = buf.len() - pos;
+
+    let mut out = ::std::io::stdout();
+    let _ = out.write_str(&*format!("\r{}{}\x1b[K", prompt, line))
+               .and_then(|_| if 0 < back {
+                   out.write_str(&*format!("\x1b[{}D", back))
+               } else {
+                   Ok(())
+               })
+               .and_then(|_| out.flush());
+}
+
+/// Reads and decodes one key press from the terminal.
+fn read_key() -> IoResult<Key> {
+    let mut input = stdin_raw();
+
+    Ok(match try!(input.read_byte()) {
+        1          => Key::Home,
+        2          => Key::Left,
+        3          => Key::Interrupt,
+        4          => Key::Eof,
+        5          => Key::End,
+        6          => Key::Right,
+        8 | 127    => Key::Backspace,
+        9          => Key::Tab,
+        10 | 13    => Key::Enter,
+        11         => Key::KillToEnd,
+        14         => Key::Down,
+        16         => Key::Up,
+        21         => Key::KillToStart,
+        23         => Key::KillWord,
+        27         => match try!(input.read_byte()) {
+            b'[' | b'O' => match try!(input.read_byte()) {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'C' => Key::Right,
+                b'D' => Key::Left,
+                b'H' => Key::Home,
+                b'F' => Key::End,
+                b'3' => { try!(input.read_byte()); Key::Delete },
+                _    => Key::Other
+            },
+            _ => Key::Other
+        },
+        b if b < 32 => Key::Other,
+        b if b < 128 => Key::Char(b as char),
+        b => {
+            // A UTF-8 sequence; the lead byte says how long it is.
+            let n = if b >= 0xf0 { 3 } else if b >= 0xe0 { 2 } else { 1 };
+            let mut bytes = vec![b];
+            for _ in range(0, n) { bytes.push(try!(input.read_byte())); }
+
+            match ::std::str::from_utf8(&*bytes).ok().and_then(|s| s.chars().next()) {
+                Some(c) => Key::Char(c),
+                None    => Key::Other
+            }
+        }
+    })
+}
+
+/// Runs `stty` on the controlling terminal, returning what it prints.
+pub fn stty(args: &[&str]) -> IoResult<String> {
+    Command::new("stty")
+        .args(args)
+        .stdin(InheritFd(0))
+        .output()
+        .map(|o| String::from_utf8_lossy(&*o.output).into_owned())
+}
@@ -0,0 +1,91 @@
+//! Formats for showing BAPS3 messages to humans and other programs.
+
+use std::borrow::ToOwned;
+
+use rustc_serialize::json;
+
+use baps3_protocol::proto::Message;
+
+use clock;
+
+/// A way of printing BAPS3 messages.
+#[derive(Copy, PartialEq, Show)]
+pub enum Format {
+    /// The message exactly as it was sent over the wire.
+    Raw,
+
+    /// The command word followed by a list of arguments, as `baps3-cli`
+    /// shows messages.
+    Pretty,
+
+    /// A JSON array of the command word and arguments.
+    Json
+}
+
+impl Format {
+    /// Looks up a format by the name used on the command line.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::format::Format;
+    /// assert_eq!(Format::from_name("json"), Some(Format::Json));
+    /// assert_eq!(Format::from_name("xml"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "raw"    => Some(Format::Raw),
+            "pretty" => Some(Format::Pretty),
+            "json"   => Some(Format::Json),
+            _        => None
+        }
+    }
+
+    /// Formats `msg`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// extern crate baps3_cli;
+    /// extern crate baps3_protocol;
+    /// use baps3_cli::format::Format;
+    /// use baps3_protocol::proto::Message;
+    ///
+    /// # fn main() {
+    /// let msg = Message::new("FILE").arg("/a b.mp3");
+    /// assert_eq!(&*Format::Json.message(&msg), r#"["FILE","/a b.mp3"]"#);
+    /// # }
+    /// ```
+    pub fn message(&self, msg: &Message) -> String {
+        match *self {
+            Format::Raw    => msg.pack().trim_right().to_owned(),
+            Format::Pretty => format!("{} {:?}", msg.word(), msg.args()),
+            Format::Json   => json::encode(&msg.as_str_vec())
+        }
+    }
+
+    /// Formats `msg`, along with the time `stamp` at which it was seen.
+    pub fn stamped_message(&self, stamp: &str, msg: &Message) -> String {
+        match *self {
+            Format::Json => format!("{{\"time\":{},\"message\":{}}}",
+                                    json::encode(&stamp),
+                                    self.message(msg)),
+            _            => format!("{} {}", stamp, self.message(msg))
+        }
+    }
+}
+
+/// Returns the current local wall-clock time, to the millisecond, in a form
+/// suitable for logs.
+pub fn timestamp() -> String {
+    timestamp_of(clock::get_time())
+}
+
+/// As `timestamp`, but for the given time rather than now.
+pub fn timestamp_of(time: clock::Timespec) -> String {
+    let tm = clock::at(time);
+
+    format!("{}.{:03}",
+            tm.strftime("%Y-%m-%d %H:%M:%S").unwrap(),
+            tm.tm_nsec / 1000000)
+}
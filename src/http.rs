@@ -0,0 +1,470 @@
+//! Just enough HTTP/1.1 to serve small JSON APIs and event streams to local
+//! clients, such as web-based studio panels.
+//!
+//! Every response closes its connection, apart from server-sent event
+//! streams, which stay open for as long as the client wants them.  As with
+//! `server`, requests are read in the background and handed to one loop over
+//! a channel, along with the connection to answer them on.
+
+use std::ascii::AsciiExt;
+use std::borrow::ToOwned;
+use std::error::Error;
+use std::io::{ Acceptor, BufferedReader, IoError, IoErrorKind, IoResult,
+               Listener };
+use std::io::net::ip::{ SocketAddr, ToSocketAddr };
+use std::io::net::tcp::{ TcpListener, TcpStream };
+use std::sync::mpsc::Sender;
+use std::thread::Thread;
+
+use rustc_serialize::json::{ self, Json };
+
+use super::Baps3Error;
+
+/// The largest request body we accept, in bytes.  Anything bigger than a
+/// few parameters is not meant for us.
+pub const MAX_BODY: usize = 64 * 1024;
+
+/// How long a client has to send its request, in milliseconds.
+const READ_TIMEOUT_MS: u64 = 10000;
+
+const BODY_TOO_LARGE: &'static str = "request body too large";
+
+/// An HTTP request.
+pub struct Request {
+    pub method:  String,
+
+    /// The path requested, without its query string.
+    pub path:    String,
+
+    /// The decoded query string parameters, in order.
+    pub query:   Vec<(String, String)>,
+
+    pub headers: Vec<(String, String)>,
+    pub body:    String
+}
+
+impl Request {
+    /// Looks up a header, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+                    .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+                    .map(|&(_, ref v)| &**v)
+    }
+
+    /// Looks up a parameter, first in the query string and then, if the body
+    /// is a JSON object, among its fields.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::http::Request;
+    /// let req = Request { method:  "POST".to_string(),
+    ///                     path:    "/seek".to_string(),
+    ///                     query:   vec![],
+    ///                     headers: vec![],
+    ///                     body:    r#"{"pos": 1500000}"#.to_string() };
+    /// assert_eq!(req.param("pos"), Some("1500000".to_string()));
+    /// assert_eq!(req.param("file"), None);
+    /// ```
+    pub fn param(&self, name: &str) -> Option<String> {
+        if let Some(&(_, ref v)) = self.query.iter().find(|&&(ref n, _)| &**n == name) {
+            return Some(v.clone());
+        }
+
+        let body = match Json::from_str(&*self.body) {
+            Ok(b)  => b,
+            Err(_) => return None
+        };
+        match body.find(name) {
+            Some(&Json::String(ref s)) => Some(s.clone()),
+            Some(&Json::U64(n))        => Some(n.to_string()),
+            Some(&Json::I64(n))        => Some(n.to_string()),
+            Some(&Json::F64(n))        => Some(n.to_string()),
+            Some(&Json::Boolean(b))    => Some(b.to_string()),
+            _                          => None
+        }
+    }
+}
+
+/// An HTTP response with a complete body.
+pub struct Response {
+    pub status:       u16,
+    pub content_type: &'static str,
+
+    /// Any headers beyond those every response has.
+    pub headers:      Vec<(String, String)>,
+
+    pub body:         String
+}
+
+impl Response {
+    /// Creates a response carrying JSON.
+    pub fn json(status: u16, body: String) -> Response {
+        Response { status:       status,
+                   content_type: "application/json",
+                   headers:      vec![],
+                   body:         body }
+    }
+
+    /// Creates a response carrying plain text.
+    pub fn text(status: u16, body: &str) -> Response {
+        Response { status:       status,
+                   content_type: "text/plain; charset=utf-8",
+                   headers:      vec![],
+                   body:         body.to_owned() }
+    }
+
+    /// Adds a header to the response.
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Looks up one of the response's extra headers, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+                    .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+                    .map(|&(_, ref v)| &**v)
+    }
+
+    /// Creates a JSON response describing `err`, with the status from
+    /// `status_for`.
+    pub fn error(err: &Baps3Error) -> Response {
+        Response::json(status_for(err),
+                       format!("{{\"error\":{},\"detail\":{}}}",
+                               json::encode(&err.description()),
+                               json::encode(&err.detail())))
+    }
+
+    /// Writes the response, which should be the last thing on `w`.
+    pub fn write_to<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+        try!(write!(w,
+                    "HTTP/1.1 {} {}\r\n\
+                     Content-Type: {}\r\n\
+                     Content-Length: {}\r\n",
+                    self.status,
+                    reason(self.status),
+                    self.content_type,
+                    self.body.len()));
+        try!(write_headers(w, &*self.headers));
+        try!(w.write_str("Connection: close\r\n\r\n"));
+        w.write_str(&*self.body)
+    }
+}
+
+/// Returns the HTTP status code best describing `err`.
+///
+/// Problems with the request are 4xx codes; problems with the BAPS3 server
+/// behind us are 5xx codes.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::Baps3Error;
+/// use baps3_cli::http::status_for;
+/// assert_eq!(status_for(&Baps3Error::CmdInvalid { advice: "x".to_string() }), 400);
+/// assert_eq!(status_for(&Baps3Error::CmdFailed { advice: "x".to_string() }), 409);
+/// assert_eq!(status_for(&Baps3Error::TimedOut), 504);
+/// ```
+pub fn status_for(err: &Baps3Error) -> u16 {
+    match *err {
+        Baps3Error::CmdInvalid         { .. } => 400,
+        Baps3Error::BadScript          { .. } => 400,
+        Baps3Error::InvalidPath        { .. } => 400,
+        Baps3Error::CmdFailed          { .. } => 409,
+        Baps3Error::Interrupted        { .. } => 409,
+        Baps3Error::BadConfig          { .. } => 500,
+        Baps3Error::MissingFeatures    { .. } => 501,
+        Baps3Error::HungUp                    => 502,
+        Baps3Error::Io                 { .. } => 502,
+        Baps3Error::NotBaps3Server            => 502,
+        Baps3Error::UnexpectedResponse { .. } => 502,
+        Baps3Error::TimedOut                  => 504
+    }
+}
+
+/// Returns the reason phrase for the status codes we use.
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _   => "Unknown"
+    }
+}
+
+/// Decodes a percent-encoded URL component, in which `+` also stands for a
+/// space.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::http::percent_decode;
+/// assert_eq!(&*percent_decode("%2Fmusic%2Fa+b.mp3"), "/music/a b.mp3");
+/// assert_eq!(&*percent_decode("100%"), "100%");
+/// ```
+pub fn percent_decode(s: &str) -> String {
+    let bytes   = s.as_bytes();
+    let mut out = vec![];
+    let mut i   = 0;
+
+    while i < bytes.len() {
+        let hex = |&: j: usize| (bytes[j] as char).to_digit(16);
+
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len()
+                 && hex(i + 1).is_some() && hex(i + 2).is_some() => {
+                out.push((hex(i + 1).unwrap() * 16 + hex(i + 2).unwrap()) as u8);
+                i += 2;
+            },
+            b => out.push(b)
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&*out).into_owned()
+}
+
+/// Splits a query string into decoded name-value pairs.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::http::parse_query;
+/// let q = parse_query("file=%2Fa.mp3&play");
+/// assert_eq!(q[0], ("file".to_string(), "/a.mp3".to_string()));
+/// assert_eq!(q[1], ("play".to_string(), "".to_string()));
+/// ```
+pub fn parse_query(s: &str) -> Vec<(String, String)> {
+    s.split('&')
+     .filter(|p| !p.is_empty())
+     .map(|p| match p.find('=') {
+         Some(i) => (percent_decode(&p[..i]), percent_decode(&p[i + 1..])),
+         None    => (percent_decode(p), String::new())
+     })
+     .collect()
+}
+
+fn malformed(detail: &str) -> IoError {
+    IoError { kind:   IoErrorKind::InvalidInput,
+              desc:   "malformed HTTP request",
+              detail: Some(detail.to_owned()) }
+}
+
+/// Returns the HTTP status code to answer a request that `read_request`
+/// failed to read with.
+pub fn error_status(err: &IoError) -> u16 {
+    match err.kind {
+        IoErrorKind::TimedOut           => 408,
+        _ if err.desc == BODY_TOO_LARGE => 413,
+        _                               => 400
+    }
+}
+
+/// Reads one request.
+///
+/// Bodies longer than `MAX_BODY` are refused without being read.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::BufReader;
+/// use baps3_cli::http::{ error_status, read_request };
+///
+/// let mut r = BufReader::new(b"POST /seek HTTP/1.1\r\n\
+///                              Content-Length: 12\r\n\r\n\
+///                              {\"pos\": 150}");
+/// assert_eq!(&*read_request(&mut r).unwrap().body, "{\"pos\": 150}");
+///
+/// let mut r = BufReader::new(b"POST /load HTTP/1.1\r\n\
+///                              Content-Length: 1000000000\r\n\r\n");
+/// assert_eq!(error_status(&read_request(&mut r).err().unwrap()), 413);
+/// ```
+pub fn read_request<B: Buffer>(r: &mut B) -> IoResult<Request> {
+    let line  = try!(r.read_line());
+    let parts: Vec<&str> = line.trim().split(' ').collect();
+    let (method, target) = match parts.as_slice() {
+        [m, t, v] if v.starts_with("HTTP/1.") => (m, t),
+        _ => return Err(malformed("bad request line"))
+    };
+
+    let mut headers = vec![];
+    loop {
+        let h = try!(r.read_line());
+        let h = h.trim_right();
+        if h.is_empty() {
+            break;
+        }
+        match h.find(':') {
+            Some(i) => headers.push((h[..i].trim().to_owned(),
+                                     h[i + 1..].trim().to_owned())),
+            None    => return Err(malformed("bad header"))
+        }
+    }
+
+    let (path, query) = match target.find('?') {
+        Some(i) => (&target[..i], parse_query(&target[i + 1..])),
+        None    => (target, vec![])
+    };
+
+    let mut req = Request { method:  method.to_owned(),
+                            path:    percent_decode(path),
+                            query:   query,
+                            headers: headers,
+                            body:    String::new() };
+
+    let length = match req.header("Content-Length") {
+        Some(l) => try!(l.parse::<usize>().ok_or(malformed("bad Content-Length"))),
+        None    => 0
+    };
+    if MAX_BODY < length {
+        return Err(IoError { kind:   IoErrorKind::InvalidInput,
+                             desc:   BODY_TOO_LARGE,
+                             detail: Some(length.to_string()) });
+    }
+    if 0 < length {
+        let body = try!(r.read_exact(length));
+        req.body = String::from_utf8_lossy(&*body).into_owned();
+    }
+
+    Ok(req)
+}
+
+/// Writes `headers`, one per line.
+fn write_headers<W: Writer>(w: &mut W, headers: &[(String, String)]) -> IoResult<()> {
+    for &(ref name, ref value) in headers.iter() {
+        try!(write!(w, "{}: {}\r\n", name, value));
+    }
+    Ok(())
+}
+
+/// Determines whether a request comes from somewhere allowed to make it.
+///
+/// Browsers name the site behind a cross-site request in its `Origin`
+/// header, and such requests are only allowed from the `allowed` origins.
+/// Requests without an `Origin` come from things other than web pages, such
+/// as `curl`, and are always allowed.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::http::{ Request, origin_allowed };
+/// let req = |&: headers: Vec<(String, String)>| Request { method:  "POST".to_string(),
+///                                                         path:    "/stop".to_string(),
+///                                                         query:   vec![],
+///                                                         headers: headers,
+///                                                         body:    String::new() };
+/// let from  = |&: o: &str| vec![("Origin".to_string(), o.to_string())];
+/// let ok    = vec!["http://studio.example".to_string()];
+///
+/// assert!(origin_allowed(&req(vec![]), &*ok));
+/// assert!(origin_allowed(&req(from("http://studio.example")), &*ok));
+/// assert!(!origin_allowed(&req(from("http://evil.example")), &*ok));
+/// assert!(!origin_allowed(&req(from("http://studio.example")), &[]));
+/// ```
+pub fn origin_allowed(req: &Request, allowed: &[String]) -> bool {
+    match req.header("Origin") {
+        Some(o) => allowed.iter().any(|a| &**a == o),
+        None    => true
+    }
+}
+
+/// Starts a server-sent event stream on `w`, in answer to a request, with
+/// any extra `headers`.
+pub fn start_event_stream<W: Writer>(w: &mut W, headers: &[(String, String)])
+  -> IoResult<()> {
+    try!(w.write_str("HTTP/1.1 200 OK\r\n\
+                      Content-Type: text/event-stream\r\n\
+                      Cache-Control: no-cache\r\n"));
+    try!(write_headers(w, headers));
+    w.write_str("Connection: close\r\n\r\n")
+}
+
+/// Sends one server-sent event, whose `data` must be a single line.
+pub fn write_event<W: Writer>(w: &mut W, event: &str, data: &str) -> IoResult<()> {
+    write!(w, "event: {}\ndata: {}\n\n", event, data)
+}
+
+/// Starts accepting HTTP clients on `addr`, passing each request on to
+/// `requests` with the connection it came in on.
+///
+/// Requests that can't be read are answered without being passed on: with a
+/// 400 if malformed, a 413 if their body is too large, or a 408 if the client
+/// takes too long to send them.
+///
+/// Returns the address actually listened on, which tells us the port when
+/// `addr` leaves it to the system.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::net::tcp::TcpStream;
+/// use std::sync::mpsc::channel;
+/// use baps3_cli::http::{ self, Response };
+///
+/// let (tx, rx) = channel();
+/// let addr     = http::serve("127.0.0.1:0", tx).unwrap();
+///
+/// let mut browser = TcpStream::connect(addr).unwrap();
+/// browser.write_str("GET /state?x=1 HTTP/1.1\r\n\r\n").unwrap();
+///
+/// let (req, mut stream) = rx.recv().unwrap();
+/// assert_eq!(&*req.path, "/state");
+/// Response::text(200, "hello").write_to(&mut stream).unwrap();
+/// drop(stream);
+///
+/// let reply = browser.read_to_string().unwrap();
+/// assert!(reply.starts_with("HTTP/1.1 200 OK"));
+/// assert!(reply.ends_with("hello"));
+///
+/// let mut greedy = TcpStream::connect(addr).unwrap();
+/// greedy.write_str("POST /load HTTP/1.1\r\n\
+///                   Content-Length: 1000000000\r\n\r\n").unwrap();
+/// assert!(greedy.read_to_string().unwrap().starts_with("HTTP/1.1 413"));
+/// ```
+pub fn serve<A: ToSocketAddr>(addr: A, requests: Sender<(Request, TcpStream)>)
+  -> IoResult<SocketAddr> {
+    let mut acceptor = try!(TcpListener::bind(addr).listen());
+    let bound        = try!(acceptor.socket_name());
+
+    Thread::spawn(move || {
+        for stream in acceptor.incoming() {
+            let mut stream = match stream {
+                Ok(s)  => s,
+                Err(_) => continue
+            };
+
+            let tx = requests.clone();
+            Thread::spawn(move || {
+                // Only the reading side times out: event streams and
+                // WebSockets made from this connection are long-lived.
+                let mut conn = stream.clone();
+                conn.set_read_timeout(Some(READ_TIMEOUT_MS));
+
+                let mut reader = BufferedReader::new(conn);
+                match read_request(&mut reader) {
+                    Ok(req) => { let _ = tx.send((req, stream)); },
+                    Err(e)  => {
+                        let _ = Response::text(error_status(&e), e.desc)
+                                         .write_to(&mut stream);
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(bound)
+}
@@ -2,6 +2,9 @@
 #![feature(unboxed_closures)]
 
 extern crate baps3_protocol;
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate "time" as clock;
+extern crate libc;
 extern crate docopt;
 #[macro_use] extern crate docopt_macros;
 
@@ -10,15 +13,16 @@ use std::error::{ Error, FromError };
 use std::fmt;
 use std::io::{ IoError, IoErrorKind, IoResult };
 use std::io::net::ip::ToSocketAddr;
-use std::sync::mpsc::{ Receiver, RecvError, SendError };
+use std::io::timer::Timer;
+use std::sync::{ Once, ONCE_INIT };
+use std::sync::mpsc::{ Receiver, RecvError, SendError, Sender };
+use std::time::Duration;
 
 use baps3_protocol::client::{ Client, Request, Response };
 use baps3_protocol::proto::Message;
 use baps3_protocol::util::unslicify;
 
-pub mod util;
-pub mod time;
-
+// Macros must be defined before the modules that use them.
 #[macro_export]
 macro_rules! log(
     ($l:ident, $($arg:tt)*) => (
@@ -26,20 +30,107 @@ macro_rules! log(
     )
 );
 
+/// The usage text for the options every command shares, to be put on the end
+/// of a `docopt!` usage message with `concat!`.
+///
+/// `common_options!(untargeted)` leaves out `-t`, for commands that are told
+/// about their servers some other way.
+#[macro_export]
+macro_rules! common_options(
+    (untargeted) => ("
+Common options:
+  -v, --verbose          Prints a trail of miscellaneous information
+                         about the action.
+  --completions <shell>  Print a completion script for this command, for
+                         bash, zsh or fish.
+");
+    () => (concat!(common_options!(untargeted),
+"  -t, --target <target>  The target BAPS3 server (host:port or the name of
+                         a configured target).  Defaults to $BAPS3_TARGET,
+                         or localhost:1350 if that is not set.
+"))
+);
+
+/// Parses the command line into the `Args` defined by `docopt!`, exiting with
+/// docopt's message if it is malformed.
+///
+/// If `--completions` was given, prints the completion script it asks for
+/// and returns from the calling function instead.
+#[macro_export]
+macro_rules! parse_args(
+    () => ({
+        let args: Args = Args::docopt().decode().unwrap_or_else(|e| e.exit());
+        if !args.flag_completions.is_empty() {
+            match $crate::completion::own_script(&*args.flag_completions) {
+                Ok(script) => print!("{}", script),
+                Err(e)     => $crate::exit_with(Err(e))
+            }
+            return;
+        }
+        args
+    })
+);
+
+/// Defines `main` for a command that runs `$run` on its parsed `Args`,
+/// reporting any error it returns and failing.
+#[macro_export]
+macro_rules! command_main(
+    ($run:expr) => (
+        fn main() {
+            $crate::exit_with($run(parse_args!()));
+        }
+    )
+);
+
+pub mod api;
+pub mod alias;
+pub mod asrun;
+pub mod completion;
+pub mod config;
+pub mod cue;
+pub mod edit;
+pub mod format;
+pub mod http;
+pub mod list;
+pub mod metrics;
+pub mod osc;
+pub mod path;
+pub mod queue;
+pub mod schedule;
+pub mod state;
+pub mod tui;
+pub mod util;
+pub mod script;
+pub mod server;
+pub mod session;
+pub mod time;
+pub mod transcript;
+pub mod watchdog;
+pub mod websocket;
+
 /// Error type for high-level BAPS3 client errors.
 pub enum Baps3Error {
+    /// The user's configuration was malformed.
+    BadConfig { line: usize, reason: String },
+
     /// A command failed.
     CmdFailed { advice: String },
 
     /// A command was invalid.
     CmdInvalid { advice: String },
 
+    /// A script was malformed, or one of its commands failed.
+    BadScript { path: String, line: usize, reason: String },
+
     /// The server hung up while we were waiting for it to tell us something.
     HungUp,
 
     /// A path somewhere was invalid.
     InvalidPath { path: String },
 
+    /// Playback of a file was interrupted before it ended.
+    Interrupted { reason: String },
+
     /// General IO error.
     Io { err: IoError },
 
@@ -53,18 +144,25 @@ pub enum Baps3Error {
     UnexpectedResponse { code:        String,
                          args:        Vec<String>,
                          expectation: String },
+
+    /// The server took too long to tell us something.
+    TimedOut
 }
 
 fn baps3_err_desc(err: &Baps3Error) -> &'static str {
     match *err {
+        Baps3Error::BadConfig          { .. } => "bad configuration",
         Baps3Error::CmdFailed          { .. } => "command failed",
         Baps3Error::CmdInvalid         { .. } => "command invalid",
+        Baps3Error::BadScript          { .. } => "script error",
         Baps3Error::HungUp                    => "server hung up",
+        Baps3Error::Interrupted        { .. } => "playback interrupted",
         Baps3Error::InvalidPath        { .. } => "invalid path",
         Baps3Error::Io         { err: ref e } => e.desc,
         Baps3Error::MissingFeatures    { .. } => "server missing features",
         Baps3Error::NotBaps3Server            => "not a BAPS3 server",
-        Baps3Error::UnexpectedResponse { .. } => "unexpected response"
+        Baps3Error::UnexpectedResponse { .. } => "unexpected response",
+        Baps3Error::TimedOut                  => "timed out waiting for server"
     }
 }
 
@@ -75,8 +173,13 @@ impl Error for Baps3Error {
 
     fn detail(&self) -> Option<String> {
         match *self {
+            Baps3Error::BadConfig { line: l, reason: ref r }
+                => Some(format!("line {}: {}", l, r)),
             Baps3Error::CmdFailed   { advice: ref a } => Some(a.to_owned()),
             Baps3Error::CmdInvalid  { advice: ref a } => Some(a.to_owned()),
+            Baps3Error::BadScript { path: ref p, line: l, reason: ref r }
+                => Some(format!("{}:{}: {}", p, l, r)),
+            Baps3Error::Interrupted { reason: ref r } => Some(r.to_owned()),
             Baps3Error::InvalidPath { path:   ref p } => Some(p.to_owned()),
             Baps3Error::Io          { err:    ref e } => e.detail.clone(),
             Baps3Error::MissingFeatures { wanted: ref w, have: ref h }
@@ -117,12 +220,23 @@ impl std::fmt::String for Baps3Error {
 pub type Baps3Result<A> = Result<A, Baps3Error>;
 
 pub fn check_baps3<L: Fn(&str)>
-  (log: &L, Client{request_tx, response_rx}: Client) -> Baps3Result<Client> {
+  (log: &L, client: Client) -> Baps3Result<Client> {
+    check_baps3_ident(log, client).map(|(c, _)| c)
+}
+
+/// As `check_baps3`, but also returns the identifier the server sent in its
+/// OHAI.
+pub fn check_baps3_ident<L: Fn(&str)>
+  (log: &L, Client{request_tx, response_rx}: Client)
+  -> Baps3Result<(Client, String)> {
+    let ident: String;
+
     'l: loop {
-        match response_rx.recv() {
-            Ok(Response::Message(msg)) => match msg.as_str_vec().as_slice() {
-                ["OHAI", ident] => {
-                    log!(log, "Server ident: {}", ident);
+        match try!(recv_within(&response_rx, config::default_timeout())) {
+            Response::Message(msg) => match msg.as_str_vec().as_slice() {
+                ["OHAI", id] => {
+                    log!(log, "Server ident: {}", id);
+                    ident = id.to_owned();
                     break 'l;
                 }
                 _ => return Err(Baps3Error::NotBaps3Server)
@@ -131,10 +245,9 @@ pub fn check_baps3<L: Fn(&str)>
         }
     }
 
-    Ok(Client{
-        request_tx: request_tx,
-        response_rx: response_rx
-    })
+    Ok(( Client { request_tx: request_tx,
+                  response_rx: response_rx },
+         ident ))
 }
 
 /// Determines if a BAPS3 server is missing features needed by this client.
@@ -163,6 +276,33 @@ pub fn missing_features(needed: &[&str], have: &[&str]) -> bool {
     needed.iter().any(|n| !have.contains(n))
 }
 
+/// Returns the BAPS3 command words a server with the given features accepts.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::feature_commands;
+/// assert_eq!(feature_commands(&["FileLoad", "TimeReport"]),
+///            vec!["load", "eject"]);
+/// ```
+pub fn feature_commands(features: &[&str]) -> Vec<&'static str> {
+    let mut commands = vec![];
+
+    for f in features.iter() {
+        let words: &[&'static str] = match *f {
+            "FileLoad" => &["load", "eject"],
+            "PlayStop" => &["play", "stop"],
+            "Seek"     => &["seek"],
+            "End"      => &["quit"],
+            "PlayList" => &["enqueue", "dequeue", "select", "list"],
+            _          => &[]
+        };
+        commands.push_all(words);
+    }
+
+    commands
+}
+
 pub fn check_features<L: Fn(&str)>(log: &L,
                                    needed: &[&str],
                                    Client{request_tx, response_rx}: Client)
@@ -170,8 +310,8 @@ pub fn check_features<L: Fn(&str)>(log: &L,
     let mut vhave : Vec<String> = vec![];
 
     'l: loop {
-        match response_rx.recv() {
-            Ok(Response::Message(msg)) => match msg.as_str_vec().as_slice() {
+        match try!(recv_within(&response_rx, config::default_timeout())) {
+            Response::Message(msg) => match msg.as_str_vec().as_slice() {
                 ["FEATURES", have..] => {
                     log!(log, "Server features: {:?}", have);
                     if missing_features(needed, have) {
@@ -205,13 +345,35 @@ pub fn check_features<L: Fn(&str)>(log: &L,
 
 pub fn send_command<L: Fn(&str)>(log: &L, client: &mut Client, msg: &Message)
   -> Baps3Result<()> {
+    send_command_via(log, &client.request_tx, &client.response_rx, msg)
+}
+
+/// As `send_command`, but for code that holds the two halves of a Client
+/// separately.
+pub fn send_command_via<L: Fn(&str)>(log: &L,
+                                     tx: &Sender<Request>,
+                                     rx: &Receiver<Response>,
+                                     msg: &Message) -> Baps3Result<()> {
+    send_command_collecting_via(log, tx, rx, msg).map(|_| ())
+}
+
+/// As `send_command_via`, but returns every other message the server sent
+/// while we waited for the acknowledgement, in order.
+///
+/// This is for commands, such as `list`, whose results are sent back as
+/// separate messages.
+pub fn send_command_collecting_via<L: Fn(&str)>(log: &L,
+                                                tx: &Sender<Request>,
+                                                rx: &Receiver<Response>,
+                                                msg: &Message)
+  -> Baps3Result<Vec<Message>> {
     let word = msg.word();
     let args = msg.args();
     log!(log, "Sending command: {} {:?}", word, args);
 
-    try!(client.request_tx.send(Request::SendMessage(msg.clone())));
+    try!(tx.send(Request::SendMessage(msg.clone())));
 
-    let result = wait_response(&client.response_rx, word, &*args);
+    let result = wait_response(rx, word, &*args);
 
     if let Ok(_) = result {
         log!(log, "success!");
@@ -220,26 +382,77 @@ pub fn send_command<L: Fn(&str)>(log: &L, client: &mut Client, msg: &Message)
     result
 }
 
-fn wait_response(rx: &Receiver<Response>, word: &str, args: &[&str]) -> Baps3Result<()> {
+fn wait_response(rx: &Receiver<Response>, word: &str, args: &[&str])
+  -> Baps3Result<Vec<Message>> {
+    let timeout  = config::default_timeout();
+    let mut seen = vec![];
+
     loop {
-        match rx.recv() {
-            Ok(Response::Message(msg)) => match msg.as_str_vec().as_slice() {
-                ["OK", cword, cargs..]
-                  if cword == word && cargs == args =>
-                    return Ok(()),
-                ["WHAT", advice, cword, cargs..]
-                  if cword == word && cargs == args =>
-                    return Err(Baps3Error::CmdInvalid { advice: advice.to_owned() }),
-                ["FAIL", advice, cword, cargs..]
-                  if cword == word && cargs == args =>
-                    return Err(Baps3Error::CmdFailed { advice: advice.to_owned() }),
-                _ => ()
+        match try!(recv_within(rx, timeout)) {
+            Response::Message(msg) => match acknowledgement(word, args, &msg) {
+                Some(result) => return result.map(|_| seen),
+                None         => seen.push(msg)
             },
             _ => return Err(Baps3Error::HungUp)
         }
     }
 }
 
+/// Receives the next response from the server.
+///
+/// If `timeout` is given and nothing arrives within it, this fails with
+/// `TimedOut`.
+fn recv_within(rx: &Receiver<Response>, timeout: Option<Duration>)
+  -> Baps3Result<Response> {
+    let timeout = match timeout {
+        Some(t) => t,
+        None    => return Ok(try!(rx.recv()))
+    };
+
+    let mut timer = try!(Timer::new());
+    let expired   = timer.oneshot(timeout);
+
+    select! {
+        res = rx.recv() => Ok(try!(res)),
+        _ = expired.recv() => Err(Baps3Error::TimedOut)
+    }
+}
+
+/// Determines whether `msg` is the server's acknowledgement of the command
+/// with the given `word` and `args`.
+///
+/// Returns the outcome of the command if so, and `None` otherwise.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate baps3_cli;
+/// extern crate baps3_protocol;
+/// use baps3_cli::acknowledgement;
+/// use baps3_protocol::proto::Message;
+///
+/// # fn main() {
+/// let ok = Message::new("OK").arg("seek").arg("0");
+/// assert!(acknowledgement("seek", &["0"], &ok).unwrap().is_ok());
+/// assert!(acknowledgement("seek", &["1"], &ok).is_none());
+/// # }
+/// ```
+pub fn acknowledgement(word: &str, args: &[&str], msg: &Message)
+  -> Option<Baps3Result<()>> {
+    match msg.as_str_vec().as_slice() {
+        ["OK", cword, cargs..]
+          if cword == word && cargs == args =>
+            Some(Ok(())),
+        ["WHAT", advice, cword, cargs..]
+          if cword == word && cargs == args =>
+            Some(Err(Baps3Error::CmdInvalid { advice: advice.to_owned() })),
+        ["FAIL", advice, cword, cargs..]
+          if cword == word && cargs == args =>
+            Some(Err(Baps3Error::CmdFailed { advice: advice.to_owned() })),
+        _ => None
+    }
+}
+
 pub fn quit_client<L: Fn(&str)>(log: &L, Client { request_tx, .. }: Client)
   -> Baps3Result<()> {
     log!(log, "Closing client connection");
@@ -253,6 +466,7 @@ pub fn quit_client<L: Fn(&str)>(log: &L, Client { request_tx, .. }: Client)
 pub struct Baps3<L: Fn(&str)> {
     client:   Client,
     logger:   L,
+    ident:    String,
     features: Vec<String>
 }
 
@@ -262,22 +476,74 @@ impl<L: Fn(&str)> Baps3<L> {
                   addr:     T,
                   features: &[&str]) -> Baps3Result<Baps3<L>>
     where T: ToSocketAddr {
+        let ( client, ident ) = try!(
+            check_baps3_ident(&logger, try!(Client::new(addr)))
+        );
         let ( client, all_features ) = try!(
-            check_baps3(&logger, try!(Client::new(addr)))
-              .and_then(|c| check_features(&logger, features, c))
+            check_features(&logger, features, client)
         );
 
         Ok( Baps3 { client:   client,
                     logger:   logger,
+                    ident:    ident,
                     features: all_features } )
     }
 
+    /// Returns the identifier the server sent in its OHAI.
+    pub fn ident(&self) -> &str {
+        &*self.ident
+    }
+
+    /// Returns every feature the server reported, not just those we needed.
+    pub fn features(&self) -> &[String] {
+        &*self.features
+    }
+
     /// Sends a command.
     /// Blocks until the command is acknowledged.
     pub fn send(&mut self, msg: &Message) -> Baps3Result<()> {
         send_command(&self.logger, &mut self.client, msg)
     }
 
+    /// As `send`, but also returns the other messages that arrived before
+    /// the acknowledgement.
+    pub fn send_collecting(&mut self, msg: &Message) -> Baps3Result<Vec<Message>> {
+        send_command_collecting_via(&self.logger,
+                                    &self.client.request_tx,
+                                    &self.client.response_rx,
+                                    msg)
+    }
+
+    /// Blocks until the server sends a message, and returns it.
+    ///
+    /// Responses to commands sent with `send` are consumed by `send`, so
+    /// this mainly returns notifications such as TIME, STATE and END.
+    pub fn recv(&mut self) -> Baps3Result<Message> {
+        match self.client.response_rx.recv() {
+            Ok(Response::Message(msg))  => Ok(msg),
+            Ok(Response::ClientError(e)) => Err(Baps3Error::Io { err: e }),
+            _                           => Err(Baps3Error::HungUp)
+        }
+    }
+
+    /// As `recv`, but gives up and returns `None` if no message arrives
+    /// within `timeout`.
+    pub fn recv_timeout(&mut self, timeout: Duration)
+      -> Baps3Result<Option<Message>> {
+        let mut timer = try!(Timer::new());
+        let expired   = timer.oneshot(timeout);
+        let rx        = &self.client.response_rx;
+
+        select! {
+            res = rx.recv() => match res {
+                Ok(Response::Message(msg))   => Ok(Some(msg)),
+                Ok(Response::ClientError(e)) => Err(Baps3Error::Io { err: e }),
+                _                            => Err(Baps3Error::HungUp)
+            },
+            _ = expired.recv() => Ok(None)
+        }
+    }
+
     pub fn quit(self) {
         // It doesn't matter if the client has already quit.
         let _ = self.client.request_tx.send(Request::Quit);
@@ -307,10 +573,53 @@ where L: Fn(&str),
     res
 }
 
+/// Performs a one-shot request against the target given on a command line.
+///
+/// This is all most single-command tools need to do: `target` and `verbose`
+/// are the tool's `-t` and `-v` flags, so the defaults set in the environment
+/// (by `baps3`, for example) apply as usual.  Each of `msgs` is sent in turn,
+/// stopping at the first that fails.
+pub fn one_shot_target(target:   &str,
+                       verbose:  bool,
+                       features: &[&str],
+                       msgs:     &[Message]) -> Baps3Result<()> {
+    let log    = |&:s:&str| verbose_logger(verbose, s);
+    let target = try!(config::resolve_target(target));
+
+    let mut b3  = try!(Baps3::new(log, &*target.addr, features));
+    let mut res = Ok(());
+    for msg in msgs.iter() {
+        res = b3.send(msg);
+        if res.is_err() { break; }
+    }
+    b3.quit();
+
+    res
+}
+
+/// Reports the outcome of a command-line tool, setting a failing exit status
+/// if it went wrong.
+pub fn exit_with(result: Baps3Result<()>) {
+    if let Err(e) = result {
+        let _ = std::io::stderr().write_line(&*format!("error: {}", e));
+        std::os::set_exit_status(1);
+    }
+}
+
+/// Whether $BAPS3_VERBOSE asked for logging, read once.
+static mut ENV_VERBOSE: bool = false;
+static ENV_VERBOSE_READ: Once = ONCE_INIT;
+
 /// Creates a Logger from the -v/--verbose flag of a command.
 ///
-/// If verbose is on (-v/--verbose == true), we dump log messages to stderr,
-/// else we ignore them.
+/// If verbose is on (-v/--verbose == true, or $BAPS3_VERBOSE is set, as it is
+/// by `baps3 -v`), we dump log messages to stderr, else we ignore them.
 pub fn verbose_logger(verbose: bool, s: &str) {
-    if verbose { let _ = std::io::stderr().write_line(s); }
+    ENV_VERBOSE_READ.call_once(|| unsafe {
+        ENV_VERBOSE = config::default_verbose();
+    });
+
+    if verbose || unsafe { ENV_VERBOSE } {
+        let _ = std::io::stderr().write_line(s);
+    }
 }
@@ -0,0 +1,295 @@
+//! Support for BAPS3 playlist servers, which keep a list of files in front of
+//! a player.
+//!
+//! A playlist server has the `PlayList` feature, and understands:
+//!
+//! - `enqueue INDEX HASH FILE`, which inserts `FILE` at `INDEX`;
+//! - `dequeue INDEX HASH`, which removes the item at `INDEX`;
+//! - `select INDEX HASH`, which loads the item at `INDEX` into the player;
+//! - `list`, which reports the whole list as a `COUNT` followed by one `ITEM`
+//!   per entry.
+//!
+//! Each item carries a hash, chosen by whoever enqueued it, so that a command
+//! naming an index can't act on the wrong item if the list has changed under
+//! it.  Changes to the list are announced with `ENQUEUE`, `DEQUEUE` and
+//! `SELECT` notifications, which echo the commands that caused them.
+
+use std::borrow::ToOwned;
+use std::time::Duration;
+
+use baps3_protocol::proto::Message;
+
+use clock;
+use rustc_serialize::{ Encodable, Encoder };
+
+use super::{ Baps3, Baps3Error, Baps3Result };
+use super::config::default_timeout;
+
+/// The feature flag of a playlist server.
+pub const FEATURE: &'static str = "PlayList";
+
+/// One entry in a playlist.
+#[derive(Clone, PartialEq, Show, RustcEncodable)]
+pub struct Item {
+    /// The position of the item in the list, counting from 0.
+    pub index: usize,
+
+    /// The hash identifying the item.
+    pub hash: String,
+
+    /// The file the item plays, as the server sees it.
+    pub file: String
+}
+
+/// Builds an `enqueue` command.
+pub fn enqueue(index: usize, hash: &str, file: &str) -> Message {
+    Message::new("enqueue").arg(&*index.to_string()).arg(hash).arg(file)
+}
+
+/// Builds a `dequeue` command for `item`.
+pub fn dequeue(item: &Item) -> Message {
+    Message::new("dequeue").arg(&*item.index.to_string()).arg(&*item.hash)
+}
+
+/// Builds a `select` command for `item`.
+pub fn select(item: &Item) -> Message {
+    Message::new("select").arg(&*item.index.to_string()).arg(&*item.hash)
+}
+
+/// Builds a `list` command.
+pub fn list() -> Message {
+    Message::new("list")
+}
+
+/// Makes up a hash for a new item.
+///
+/// Hashes only need to tell apart items that might share an index, so the
+/// current time is good enough.
+pub fn new_hash() -> String {
+    format!("{:x}", clock::precise_time_ns())
+}
+
+/// A message from a playlist server.
+#[derive(Clone, PartialEq, Show)]
+pub enum Notification {
+    /// An item was enqueued.
+    Enqueue(Item),
+
+    /// The item at an index, with a hash, was dequeued.
+    Dequeue(usize, String),
+
+    /// The item at an index, with a hash, was selected.
+    Select(usize, String),
+
+    /// A listing follows, with this many items.
+    Count(usize),
+
+    /// An item in a listing.
+    Item(Item)
+}
+
+impl Notification {
+    /// Reads a playlist notification, if `msg` is one.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// extern crate baps3_cli;
+    /// extern crate baps3_protocol;
+    /// use baps3_cli::list::Notification;
+    /// use baps3_protocol::proto::Message;
+    ///
+    /// # fn main() {
+    /// let m = Message::new("SELECT").arg("2").arg("f00");
+    /// assert_eq!(Notification::from_message(&m),
+    ///            Some(Notification::Select(2, "f00".to_string())));
+    /// assert_eq!(Notification::from_message(&Message::new("END")), None);
+    /// # }
+    /// ```
+    pub fn from_message(msg: &Message) -> Option<Notification> {
+        let item = |&: index: &str, hash: &str, file: &str| {
+            index.parse::<usize>().map(|i| Item { index: i,
+                                                  hash:  hash.to_owned(),
+                                                  file:  file.to_owned() })
+        };
+
+        match msg.as_str_vec().as_slice() {
+            ["ENQUEUE", i, h, f] => item(i, h, f).map(Notification::Enqueue),
+            ["ITEM", i, h, f]    => item(i, h, f).map(Notification::Item),
+            ["DEQUEUE", i, h]    =>
+                i.parse().map(|i| Notification::Dequeue(i, h.to_owned())),
+            ["SELECT", i, h]     =>
+                i.parse().map(|i| Notification::Select(i, h.to_owned())),
+            ["COUNT", n]         => n.parse().map(Notification::Count),
+            _                    => None
+        }
+    }
+}
+
+/// A local mirror of a playlist server's list.
+#[derive(Clone)]
+pub struct Playlist {
+    pub items: Vec<Item>,
+
+    /// The index of the selected item, if we know it.
+    pub selected: Option<usize>,
+
+    /// The length of the listing being received, if any.
+    count: Option<usize>
+}
+
+/// Encodes the items and selection only, as the state of a listing being
+/// received is of no interest to anyone else.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate baps3_cli;
+/// extern crate "rustc-serialize" as rustc_serialize;
+/// use baps3_cli::list::{ Notification, Playlist };
+/// use rustc_serialize::json;
+///
+/// # fn main() {
+/// let mut p = Playlist::new();
+/// p.update(&Notification::Count(0));
+/// assert_eq!(&*json::encode(&p), r#"{"items":[],"selected":null}"#);
+/// # }
+/// ```
+impl Encodable for Playlist {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_struct("Playlist", 2, |s| {
+            try!(s.emit_struct_field("items", 0, |s| self.items.encode(s)));
+            s.emit_struct_field("selected", 1, |s| self.selected.encode(s))
+        })
+    }
+}
+
+impl Playlist {
+    pub fn new() -> Playlist {
+        Playlist { items: vec![], selected: None, count: None }
+    }
+
+    /// Updates the mirror with a notification from the server.
+    ///
+    /// Returns true if the notification changed the mirror.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::list::{ Item, Notification, Playlist };
+    /// let item = |&: i: usize, h: &str| Item { index: i,
+    ///                                          hash:  h.to_string(),
+    ///                                          file:  format!("/{}.mp3", h) };
+    ///
+    /// let mut p = Playlist::new();
+    /// p.update(&Notification::Enqueue(item(0, "a")));
+    /// p.update(&Notification::Enqueue(item(0, "b")));
+    /// assert_eq!(p.items, vec![item(0, "b"), item(1, "a")]);
+    ///
+    /// p.update(&Notification::Dequeue(0, "b".to_string()));
+    /// assert_eq!(p.items, vec![item(0, "a")]);
+    /// ```
+    pub fn update(&mut self, n: &Notification) -> bool {
+        match *n {
+            Notification::Enqueue(ref item) => {
+                let at = if item.index < self.items.len() { item.index }
+                         else                             { self.items.len() };
+                self.items.insert(at, item.clone());
+                if let Some(s) = self.selected {
+                    if at <= s { self.selected = Some(s + 1); }
+                }
+                self.renumber();
+                true
+            },
+            Notification::Dequeue(index, ref hash) => {
+                if self.find(index).map_or(true, |i| i.hash != *hash) {
+                    return false;
+                }
+                self.items.remove(index);
+                self.selected = match self.selected {
+                    Some(s) if s == index => None,
+                    Some(s) if index < s  => Some(s - 1),
+                    s                     => s
+                };
+                self.renumber();
+                true
+            },
+            Notification::Select(index, _) => {
+                let changed   = self.selected != Some(index);
+                self.selected = Some(index);
+                changed
+            },
+            Notification::Count(n) => {
+                self.items.clear();
+                self.count = Some(n);
+                true
+            },
+            Notification::Item(ref item) => {
+                self.items.retain(|i| i.index != item.index);
+                let at = self.items.iter()
+                                   .position(|i| item.index < i.index)
+                                   .unwrap_or(self.items.len());
+                self.items.insert(at, item.clone());
+                true
+            }
+        }
+    }
+
+    /// Returns true unless we are part way through receiving a listing.
+    pub fn is_complete(&self) -> bool {
+        self.count.map_or(true, |n| n <= self.items.len())
+    }
+
+    /// Looks up the item at `index`.
+    pub fn find(&self, index: usize) -> Option<&Item> {
+        self.items.iter().find(|i| i.index == index)
+    }
+
+    /// Makes each item's index match its position.
+    fn renumber(&mut self) {
+        for (n, item) in self.items.iter_mut().enumerate() {
+            item.index = n;
+        }
+    }
+}
+
+/// How long to wait for a listing, in milliseconds, if $BAPS3_TIMEOUT
+/// doesn't say.
+const LIST_TIMEOUT_MS: i64 = 5000;
+
+/// Asks a playlist server for its whole list.
+///
+/// Fails with `TimedOut` if the listing doesn't arrive in full within
+/// $BAPS3_TIMEOUT, or five seconds if that isn't set.
+pub fn fetch<L: Fn(&str)>(baps3: &mut Baps3<L>) -> Baps3Result<Playlist> {
+    let mut playlist = Playlist::new();
+    let mut listed   = false;
+
+    let timeout  = default_timeout().unwrap_or(Duration::milliseconds(LIST_TIMEOUT_MS));
+    let deadline = clock::get_time() + timeout;
+
+    // The listing may come before the acknowledgement, after it, or both.
+    for msg in try!(baps3.send_collecting(&list())).iter() {
+        if let Some(n) = Notification::from_message(msg) {
+            if let Notification::Count(_) = n { listed = true; }
+            playlist.update(&n);
+        }
+    }
+    while !listed || !playlist.is_complete() {
+        let now = clock::get_time();
+        if deadline <= now {
+            return Err(Baps3Error::TimedOut);
+        }
+
+        let msg = match try!(baps3.recv_timeout(deadline - now)) {
+            Some(m) => m,
+            None    => return Err(Baps3Error::TimedOut)
+        };
+        if let Some(n) = Notification::from_message(&msg) {
+            if let Notification::Count(_) = n { listed = true; }
+            playlist.update(&n);
+        }
+    }
+
+    Ok(playlist)
+}
@@ -0,0 +1,68 @@
+//! Metrics in the Prometheus text exposition format, for monitoring BAPS3
+//! servers from tools such as Grafana.
+
+/// A page of metrics being built up.
+pub struct Metrics {
+    text: String
+}
+
+/// Escapes a label value.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::metrics::escape;
+/// assert_eq!(&*escape("a \"b\"\\c\n"), "a \\\"b\\\"\\\\c\\n");
+/// ```
+pub fn escape(value: &str) -> String {
+    value.replace("\\", "\\\\")
+         .replace("\"", "\\\"")
+         .replace("\n", "\\n")
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics { text: String::new() }
+    }
+
+    /// Starts a metric family; its samples should follow.
+    ///
+    /// `kind` is one of `counter`, `gauge`, `summary` or `untyped`.
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        self.text.push_str(&*format!("# HELP {} {}\n# TYPE {} {}\n",
+                                     name, help.replace("\n", " "), name, kind));
+    }
+
+    /// Adds a sample.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::metrics::Metrics;
+    /// let mut m = Metrics::new();
+    /// m.family("baps3_up", "gauge", "Whether the server is connected.");
+    /// m.sample("baps3_up", &[("target", "studio1")], 1.0);
+    /// assert_eq!(&*m.finish(),
+    ///            "# HELP baps3_up Whether the server is connected.\n\
+    ///             # TYPE baps3_up gauge\n\
+    ///             baps3_up{target=\"studio1\"} 1\n");
+    /// ```
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let labels: Vec<String> = labels.iter()
+                                        .map(|&(k, v)| {
+                                            format!("{}=\"{}\"", k, escape(v))
+                                        })
+                                        .collect();
+        self.text.push_str(&*if labels.is_empty() {
+            format!("{} {}\n", name, value)
+        } else {
+            format!("{}{{{}}} {}\n", name, labels.connect(","), value)
+        });
+    }
+
+    /// Returns the finished page.
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
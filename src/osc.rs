@@ -0,0 +1,290 @@
+//! Open Sound Control (OSC 1.0) packets, and how they map onto BAPS3
+//! messages, for talking to broadcast and lighting desks.
+//!
+//! Commands arrive at addresses of the form `/baps3/TARGET/COMMAND`, with the
+//! command's arguments as OSC arguments; `seek` takes seconds, given as a
+//! number or a string holding one.  Notifications from TARGET go out to
+//! `/baps3/TARGET/WORD`, with the command word in lower case and `TIME`
+//! given in seconds, as a float.
+
+use std::ascii::AsciiExt;
+use std::borrow::ToOwned;
+use std::mem;
+
+use baps3_protocol::proto::Message;
+
+/// As `try!`, but for `Option`s.
+macro_rules! try_opt {
+    ($e:expr) => (match $e { Some(x) => x, None => return None })
+}
+
+/// The first component of every address we deal in.
+const PREFIX: &'static str = "baps3";
+
+/// An OSC argument.
+#[derive(Clone, PartialEq, Show)]
+pub enum Arg {
+    Int(i32),
+    Float(f32),
+    Str(String)
+}
+
+impl Arg {
+    /// Renders the argument as a BAPS3 argument.
+    pub fn to_word(&self) -> String {
+        match *self {
+            Arg::Int(n)     => n.to_string(),
+            Arg::Float(f)   => f.to_string(),
+            Arg::Str(ref s) => s.clone()
+        }
+    }
+}
+
+/// An OSC message.
+#[derive(Clone, PartialEq, Show)]
+pub struct OscMessage {
+    pub address: String,
+    pub args:    Vec<Arg>
+}
+
+fn push_padded(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.push_all(bytes);
+    out.push(0);
+    while out.len() % 4 != 0 { out.push(0); }
+}
+
+fn push_u32(out: &mut Vec<u8>, n: u32) {
+    for i in range(0, 4).rev() { out.push((n >> (i * 8)) as u8); }
+}
+
+impl OscMessage {
+    pub fn new(address: &str) -> OscMessage {
+        OscMessage { address: address.to_owned(), args: vec![] }
+    }
+
+    /// Adds an argument, builder-style.
+    pub fn arg(mut self, arg: Arg) -> OscMessage {
+        self.args.push(arg);
+        self
+    }
+
+    /// Encodes the message as an OSC packet.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::osc::{ Arg, OscMessage, decode };
+    /// let msg = OscMessage::new("/baps3/studio1/seek").arg(Arg::Float(1.5));
+    /// assert_eq!(decode(&*msg.encode()), Some(vec![msg]));
+    /// ```
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        push_padded(&mut out, self.address.as_bytes());
+
+        let mut tags = ",".to_string();
+        for a in self.args.iter() {
+            tags.push(match *a {
+                Arg::Int(_)   => 'i',
+                Arg::Float(_) => 'f',
+                Arg::Str(_)   => 's'
+            });
+        }
+        push_padded(&mut out, tags.as_bytes());
+
+        for a in self.args.iter() {
+            match *a {
+                Arg::Int(n)     => push_u32(&mut out, n as u32),
+                Arg::Float(f)   => push_u32(&mut out, unsafe { mem::transmute(f) }),
+                Arg::Str(ref s) => push_padded(&mut out, s.as_bytes())
+            }
+        }
+
+        out
+    }
+}
+
+/// Reads the parts of an OSC packet in order.
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Cursor<'a> {
+    fn done(&self) -> bool {
+        self.buf.len() <= self.pos
+    }
+
+    fn string(&mut self) -> Option<String> {
+        // The padding after the last string may run past the end of a
+        // truncated packet.
+        if self.done() {
+            return None;
+        }
+        let rest = &self.buf[self.pos..];
+        let len  = match rest.iter().position(|&b| b == 0) {
+            Some(l) => l,
+            None    => return None
+        };
+        let s = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.pos += (len + 4) & !3;
+        Some(s)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        if self.buf.len() < self.pos + 4 {
+            return None;
+        }
+        let b = &self.buf[self.pos..self.pos + 4];
+        self.pos += 4;
+        Some((b[0] as u32) << 24
+             | (b[1] as u32) << 16
+             | (b[2] as u32) << 8
+             | (b[3] as u32))
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < self.pos + n {
+            return None;
+        }
+        let b = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Some(b)
+    }
+}
+
+fn decode_message(c: &mut Cursor, address: String) -> Option<OscMessage> {
+    let mut msg = OscMessage::new(&*address);
+    if c.done() {
+        // Very old senders leave out the type tags when there are no
+        // arguments.
+        return Some(msg);
+    }
+
+    let tags = match c.string() {
+        Some(t) if t.starts_with(",") => t,
+        _                             => return None
+    };
+    for t in tags.chars().skip(1) {
+        msg.args.push(match t {
+            'i' => Arg::Int(try_opt!(c.u32()) as i32),
+            'f' => Arg::Float(unsafe { mem::transmute(try_opt!(c.u32())) }),
+            's' => Arg::Str(try_opt!(c.string())),
+            // Booleans, nils and impulses carry no data, and mean nothing to
+            // BAPS3.
+            'T' | 'F' | 'N' | 'I' => continue,
+            _ => return None
+        });
+    }
+    Some(msg)
+}
+
+fn decode_into(buf: &[u8], out: &mut Vec<OscMessage>) -> Option<()> {
+    let mut c = Cursor { buf: buf, pos: 0 };
+    let first = try_opt!(c.string());
+
+    if &*first == "#bundle" {
+        // The time tag: we act on everything straight away.
+        try_opt!(c.bytes(8));
+        while !c.done() {
+            let size = try_opt!(c.u32()) as usize;
+            try_opt!(decode_into(try_opt!(c.bytes(size)), out));
+        }
+    } else {
+        out.push(try_opt!(decode_message(&mut c, first)));
+    }
+    Some(())
+}
+
+/// Decodes an OSC packet, flattening any bundles into their messages.
+///
+/// Returns `None` if the packet is malformed.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::osc::decode;
+/// assert_eq!(decode(b"/x\0\0,s\0"), None);
+/// assert_eq!(decode(b"/x\0\0,i\0\0\0\0"), None);
+/// assert_eq!(decode(b"#bundle\0\0\0\0\0"), None);
+/// assert_eq!(decode(b""), None);
+/// ```
+pub fn decode(packet: &[u8]) -> Option<Vec<OscMessage>> {
+    let mut out = vec![];
+    decode_into(packet, &mut out).map(|_| out)
+}
+
+/// Converts an OSC message into a BAPS3 command and the target it is for.
+///
+/// Returns `None` if the address is not one of ours, or if a string given to
+/// `seek` is not a number.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::osc::{ Arg, OscMessage, to_command };
+/// let (target, cmd) = to_command(&OscMessage::new("/baps3/studio1/seek")
+///                                             .arg(Arg::Float(1.5))).unwrap();
+/// assert_eq!(&*target, "studio1");
+/// assert_eq!(cmd.word(), "seek");
+/// assert_eq!(cmd.args(), vec!["1500000"]);
+///
+/// let (_, cmd) = to_command(&OscMessage::new("/baps3/studio1/seek")
+///                                       .arg(Arg::Str("12.5".to_string()))).unwrap();
+/// assert_eq!(cmd.args(), vec!["12500000"]);
+/// assert!(to_command(&OscMessage::new("/baps3/studio1/seek")
+///                                .arg(Arg::Str("soon".to_string()))).is_none());
+///
+/// assert!(to_command(&OscMessage::new("/lights/1/on")).is_none());
+/// ```
+pub fn to_command(msg: &OscMessage) -> Option<(String, Message)> {
+    let parts: Vec<&str> = msg.address.split('/').collect();
+    let (target, word) = match parts.as_slice() {
+        ["", p, t, w] if p == PREFIX && !t.is_empty() && !w.is_empty() => (t, w),
+        _ => return None
+    };
+
+    let mut cmd = Message::new(word);
+    for a in msg.args.iter() {
+        let secs = match (word, a) {
+            ("seek", &Arg::Int(n))     => Some(n as f64),
+            ("seek", &Arg::Float(f))   => Some(f as f64),
+            // Some desks can only send strings; anything else is a mistake,
+            // not a position in microseconds.
+            ("seek", &Arg::Str(ref s)) => Some(try_opt!(s.trim().parse::<f64>())),
+            _                          => None
+        };
+        cmd = match secs {
+            Some(s) => cmd.arg(&*((s * 1000000.0) as i64).to_string()),
+            None    => cmd.arg(&*a.to_word())
+        };
+    }
+    Some((target.to_owned(), cmd))
+}
+
+/// Converts a BAPS3 message from `target` into an OSC message.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate baps3_cli;
+/// extern crate baps3_protocol;
+/// use baps3_cli::osc::{ Arg, from_message };
+/// use baps3_protocol::proto::Message;
+///
+/// # fn main() {
+/// let osc = from_message("studio1", &Message::new("TIME").arg("2500000"));
+/// assert_eq!(&*osc.address, "/baps3/studio1/time");
+/// assert_eq!(osc.args, vec![Arg::Float(2.5)]);
+/// # }
+/// ```
+pub fn from_message(target: &str, msg: &Message) -> OscMessage {
+    let address = format!("/{}/{}/{}",
+                          PREFIX, target, msg.word().to_ascii_lowercase());
+
+    msg.args().iter().fold(OscMessage::new(&*address), |m, a| {
+        match (msg.word(), a.parse::<u64>()) {
+            ("TIME", Some(us)) => m.arg(Arg::Float(us as f32 / 1000000.0)),
+            _                  => m.arg(Arg::Str((*a).to_owned()))
+        }
+    })
+}
@@ -0,0 +1,146 @@
+//! Utilities for turning client-side file paths into paths a BAPS3 server
+//! can understand.
+
+use std::borrow::ToOwned;
+use std::os;
+use std::path;
+
+use super::{ Baps3Error, Baps3Result };
+use super::config::Target;
+
+/// Converts a potentially-relative path string to an absolute path string.
+pub fn to_absolute_path_str(rel: &str) -> Baps3Result<String> {
+    // This is a convoluted, entangled mess of Results and Options.
+    // I sincerely apologise.
+
+    let badpath = |&:| Baps3Error::InvalidPath { path: rel.to_owned() };
+
+    path::Path::new_opt(rel)
+      .ok_or(badpath())
+      .and_then(|&:p| os::make_absolute(&p).map_err(|_| badpath()))
+      .and_then(|&:ap| ap.as_str().map(|&:s| s.to_string()).ok_or(badpath()))
+}
+
+/// A rule rewriting a client-side path prefix into a server-side one.
+///
+/// This is for servers that see our files at a different place in their
+/// filesystem from us, for example when a music share is mounted at
+/// different points on the client and the playout machine.
+#[derive(Clone, Show)]
+pub struct PathMap {
+    /// The prefix, as the client sees it.
+    pub client: String,
+
+    /// The prefix, as the server sees it.
+    pub server: String
+}
+
+impl PathMap {
+    /// Parses a path mapping rule of the form `CLIENT=SERVER`.
+    ///
+    /// Trailing slashes on either side are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::path::PathMap;
+    /// let m = PathMap::parse("/home/me/music/=/srv/music").unwrap();
+    /// assert_eq!(&*m.client, "/home/me/music");
+    /// assert_eq!(&*m.server, "/srv/music");
+    /// ```
+    ///
+    /// Rules without an `=` are rejected:
+    ///
+    /// ```rust
+    /// use baps3_cli::path::PathMap;
+    /// assert!(PathMap::parse("/home/me/music").is_none());
+    /// ```
+    pub fn parse(rule: &str) -> Option<PathMap> {
+        rule.find('=').and_then(|i| {
+            let client = trim_slashes(rule[..i].trim());
+            let server = trim_slashes(rule[i + 1..].trim());
+
+            if client.is_empty() || server.is_empty() {
+                None
+            } else {
+                Some(PathMap { client: client.to_owned(),
+                               server: server.to_owned() })
+            }
+        })
+    }
+
+    /// Applies this rule to `path`, if `path` lies under the client prefix.
+    ///
+    /// Prefixes only match whole path components.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::path::PathMap;
+    /// let m = PathMap::parse("/home/me/music=/srv/music").unwrap();
+    /// assert_eq!(m.apply("/home/me/music/a.mp3"),
+    ///            Some("/srv/music/a.mp3".to_string()));
+    /// assert_eq!(m.apply("/home/me/musicals/a.mp3"), None);
+    /// ```
+    ///
+    /// Either prefix may be the root directory:
+    ///
+    /// ```rust
+    /// use baps3_cli::path::PathMap;
+    /// let m = PathMap::parse("/=/srv/music").unwrap();
+    /// assert_eq!(m.apply("/a/b.mp3"), Some("/srv/music/a/b.mp3".to_string()));
+    ///
+    /// let m = PathMap::parse("/home/me/music/=/").unwrap();
+    /// assert_eq!(m.apply("/home/me/music/a.mp3"), Some("/a.mp3".to_string()));
+    /// assert_eq!(m.apply("/home/me/music"), Some("/".to_string()));
+    /// ```
+    pub fn apply(&self, path: &str) -> Option<String> {
+        // The root is the only prefix that keeps its trailing slash, so take
+        // it off here; the rest of the path supplies the separator.
+        let c = self.client.trim_right_matches('/');
+        let s = self.server.trim_right_matches('/');
+
+        if path == &*self.client || path == c {
+            Some(self.server.clone())
+        } else if path.starts_with(c) && path[c.len()..].starts_with("/") {
+            Some(format!("{}{}", s, &path[c.len()..]))
+        } else {
+            None
+        }
+    }
+}
+
+/// Strips any trailing slashes from `s`, leaving a lone `/` intact.
+fn trim_slashes(s: &str) -> &str {
+    let t = s.trim_right_matches('/');
+    if t.is_empty() && !s.is_empty() { "/" } else { t }
+}
+
+/// Rewrites `path` using the first rule in `maps` that applies to it.
+///
+/// If no rule applies, the path is returned unchanged.
+pub fn map_path(maps: &[PathMap], path: &str) -> String {
+    maps.iter()
+        .filter_map(|m| m.apply(path))
+        .next()
+        .unwrap_or_else(|| path.to_owned())
+}
+
+/// Collects the path mapping rules for a command: those given on its command
+/// line as `CLIENT=SERVER`, followed by those configured for `target`.
+pub fn command_maps(rules: &[String], target: &Target) -> Baps3Result<Vec<PathMap>> {
+    let mut maps = vec![];
+    for r in rules.iter() {
+        maps.push(try!(PathMap::parse(&**r).ok_or(
+            Baps3Error::InvalidPath { path: r.clone() })));
+    }
+    maps.push_all(&*target.maps);
+
+    Ok(maps)
+}
+
+/// Turns a file named on the command line into the path to send the server:
+/// absolute, then rewritten by the first of `maps` that applies.
+pub fn server_path(file: &str, maps: &[PathMap]) -> Baps3Result<String> {
+    to_absolute_path_str(file).map(|ap| map_path(maps, &*ap))
+}
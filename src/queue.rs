@@ -0,0 +1,187 @@
+//! Queues of files for unattended playout.
+//!
+//! A queue draws from a fixed pool of files, either in order (going round
+//! again at the end) or shuffled.  When shuffled, a file is not picked again
+//! until a given number of other files have been played since.  Files can
+//! also be queued to be played before any more are drawn from the pool.
+
+use std::ascii::AsciiExt;
+use std::borrow::ToOwned;
+use std::collections::RingBuf;
+use std::io::{ File, fs };
+use std::io::fs::PathExtensions;
+use std::rand;
+
+use super::{ Baps3Error, Baps3Result };
+use super::path::to_absolute_path_str;
+
+/// The extensions of files picked up from directories.
+const AUDIO_EXTENSIONS: [&'static str; 7] =
+    ["aac", "flac", "m4a", "mp3", "ogg", "opus", "wav"];
+
+/// A queue of files.
+pub struct Queue {
+    /// The files drawn from.
+    pool: Vec<String>,
+
+    /// Where we are in the pool, when not shuffling.
+    pos: usize,
+
+    shuffle: bool,
+
+    /// How many other files must be played before one is repeated, when
+    /// shuffling.
+    no_repeat: usize,
+
+    /// The files most recently picked, most recent last.
+    history: RingBuf<String>,
+
+    /// Files put back to be retried, to be played before anything else.
+    retry: RingBuf<String>,
+
+    /// Files to be played, in order, before any more are drawn from the pool.
+    pending: RingBuf<String>
+}
+
+impl Queue {
+    /// Creates a queue drawing from `pool`.
+    pub fn new(pool: Vec<String>, shuffle: bool, no_repeat: usize) -> Queue {
+        Queue { pool:      pool,
+                pos:       0,
+                shuffle:   shuffle,
+                no_repeat: no_repeat,
+                history:   RingBuf::new(),
+                retry:     RingBuf::new(),
+                pending:   RingBuf::new() }
+    }
+
+    /// Returns the next file to play, or `None` if there is nothing to play.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::queue::Queue;
+    /// let mut q = Queue::new(vec!["/a".to_string(), "/b".to_string()], false, 0);
+    /// q.insert_next("/c");
+    /// q.insert_next("/d");
+    /// q.put_back("/e");
+    /// let order: Vec<String> = range(0, 6).filter_map(|_| q.next()).collect();
+    /// assert_eq!(order.connect(" "), "/e /c /d /a /b /a");
+    /// ```
+    pub fn next(&mut self) -> Option<String> {
+        let queued = match self.retry.pop_front() {
+            Some(f) => Some(f),
+            None    => self.pending.pop_front()
+        };
+
+        let file = match queued {
+            Some(f) => f,
+            None if self.pool.is_empty() => return None,
+            None if self.shuffle => self.pick(),
+            None => {
+                let f    = self.pool[self.pos % self.pool.len()].clone();
+                self.pos = (self.pos + 1) % self.pool.len();
+                f
+            }
+        };
+
+        self.history.push_back(file.clone());
+        while self.no_repeat < self.history.len() {
+            self.history.pop_front();
+        }
+
+        Some(file)
+    }
+
+    /// Picks a file at random from those in the pool not recently played.
+    ///
+    /// If every file has been played recently, the least recent is picked.
+    fn pick(&mut self) -> String {
+        let history = &self.history;
+        let fresh: Vec<&String> = self.pool.iter()
+                                           .filter(|f| !history.iter().any(|h| h == *f))
+                                           .collect();
+
+        if fresh.is_empty() {
+            self.history.iter()
+                        .find(|h| self.pool.contains(*h))
+                        .unwrap_or(&self.pool[0])
+                        .clone()
+        } else {
+            fresh[rand::random::<usize>() % fresh.len()].clone()
+        }
+    }
+
+    /// Queues `file` to be played before any more are drawn from the pool,
+    /// after any others queued this way.
+    pub fn insert_next(&mut self, file: &str) {
+        self.pending.push_back(file.to_owned());
+    }
+
+    /// Puts `file` back at the front of the queue, to be played next, as when
+    /// it couldn't be played just now.
+    pub fn put_back(&mut self, file: &str) {
+        self.retry.push_front(file.to_owned());
+    }
+
+    /// Returns the files waiting to be played before any more are drawn from
+    /// the pool, in order.
+    pub fn pending(&self) -> Vec<String> {
+        self.retry.iter().chain(self.pending.iter()).map(|f| f.clone()).collect()
+    }
+
+    /// Returns the number of files in the pool.
+    pub fn len(&self) -> usize {
+        self.pool.len()
+    }
+}
+
+/// Reads the files in a playlist, one per line.
+///
+/// Blank lines and lines starting with `#` are ignored, and relative paths
+/// are taken relative to the playlist.  The files are returned as absolute
+/// paths.
+pub fn read_playlist(path: &Path) -> Baps3Result<Vec<String>> {
+    let src = try!(File::open(path).read_to_string());
+    let dir = path.dir_path();
+
+    let mut files = vec![];
+    for line in src.lines().map(|l| l.trim()) {
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+
+        let p = dir.join(line);
+        files.push(try!(p.as_str()
+                         .ok_or(Baps3Error::InvalidPath { path: line.to_owned() })
+                         .and_then(to_absolute_path_str)));
+    }
+
+    Ok(files)
+}
+
+/// Finds the audio files in and under `dir`, as sorted absolute paths.
+pub fn read_dir(dir: &Path) -> Baps3Result<Vec<String>> {
+    let mut files = vec![];
+
+    for p in try!(fs::walk_dir(dir)) {
+        let audio = p.extension_str().map_or(false, |e| {
+            let e = e.to_ascii_lowercase();
+            AUDIO_EXTENSIONS.iter().any(|a| *a == &*e)
+        });
+        if audio && p.is_file() {
+            if let Some(s) = p.as_str() {
+                files.push(try!(to_absolute_path_str(s)));
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Reads the files named by `source`: the files in a directory, or those
+/// listed in a playlist.
+pub fn read_source(source: &Path) -> Baps3Result<Vec<String>> {
+    if source.is_dir() { read_dir(source) } else { read_playlist(source) }
+}
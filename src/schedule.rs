@@ -0,0 +1,255 @@
+//! Schedules of BAPS3 commands to be run at given wall-clock times.
+//!
+//! A schedule has one entry per line: a time, followed by commands separated
+//! by `;`, each written as it would be on a line of a script (see `script`).
+//! Times are local, and either one-off, as `YYYY-MM-DD HH:MM[:SS[.FFF]]`, or
+//! recurring, as `every DAYS HH:MM[:SS[.FFF]]`, where `DAYS` is `day` or a
+//! comma-separated list of `mon` to `sun`.  Blank lines and lines starting
+//! with `#` are ignored.
+//!
+//! ```text
+//! every day 07:00 load news.wav; play
+//! every sat,sun 09:59:58.5 stop
+//! 2015-02-14 12:00 load valentine.mp3; play
+//! ```
+//!
+//! Files given to `load` are taken relative to the schedule.
+
+use std::borrow::ToOwned;
+
+use baps3_protocol::proto::Unpacker;
+
+use clock::{ self, Timespec };
+
+use super::{ Baps3Error, Baps3Result };
+use super::path::{ PathMap, map_path, to_absolute_path_str };
+use super::script::{ Line, split_commands, step };
+
+/// The names of the days of the week, in `tm_wday` order.
+const DAYS: [&'static str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// When a schedule entry is due.
+#[derive(Clone, PartialEq, Show)]
+pub enum When {
+    /// Once, at the given time.
+    Once(Timespec),
+
+    /// On each of the given days of the week (0 being Sunday), at the given
+    /// local time of day.
+    Every { days: Vec<i32>, hour: i32, min: i32, sec: i32, nsec: i32 }
+}
+
+impl When {
+    /// Returns the first time the entry is due after `now`, if ever.
+    pub fn next_after(&self, now: Timespec) -> Option<Timespec> {
+        match *self {
+            When::Once(t) => if now < t { Some(t) } else { None },
+            When::Every { ref days, hour, min, sec, nsec } =>
+                // A week and a day covers every possible weekday, even if
+                // today's slot has passed.
+                range(0i64, 8).map(|d| {
+                    let mut tm = clock::at(Timespec { sec:  now.sec + d * 86400,
+                                                      nsec: 0 });
+                    tm.tm_hour  = hour;
+                    tm.tm_min   = min;
+                    tm.tm_sec   = sec;
+                    tm.tm_nsec  = nsec;
+                    tm.tm_isdst = -1;
+                    tm
+                }).filter(|tm| days.contains(&tm.tm_wday))
+                  .map(|tm| tm.to_timespec())
+                  .find(|t| now < *t)
+        }
+    }
+}
+
+/// One entry in a schedule.
+pub struct Entry {
+    /// The line of the schedule the entry came from.
+    pub line: usize,
+
+    pub when: When,
+
+    /// The commands, as written.
+    pub text: String,
+
+    pub commands: Vec<Line>
+}
+
+/// Parses a time of day, `HH:MM[:SS[.FFF]]`, into hours, minutes, seconds
+/// and nanoseconds.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::schedule::parse_time_of_day;
+/// assert_eq!(parse_time_of_day("07:00"), Some((7, 0, 0, 0)));
+/// assert_eq!(parse_time_of_day("09:59:58.5"), Some((9, 59, 58, 500000000)));
+/// assert_eq!(parse_time_of_day("25:00"), None);
+/// ```
+pub fn parse_time_of_day(s: &str) -> Option<(i32, i32, i32, i32)> {
+    let (hms, frac) = match s.find('.') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None    => (s, "")
+    };
+
+    let parts: Vec<Option<i32>> = hms.split(':').map(|p| p.parse()).collect();
+    let (h, m, sec) = match parts.as_slice() {
+        [Some(h), Some(m)]          => (h, m, 0),
+        [Some(h), Some(m), Some(s)] => (h, m, s),
+        _                           => return None
+    };
+
+    let nsec = if frac.is_empty() {
+        0
+    } else if frac.len() <= 9 && frac.chars().all(|c| c.is_digit(10)) {
+        let mut n: i32 = frac.parse().unwrap_or(0);
+        for _ in range(frac.len(), 9) { n *= 10; }
+        n
+    } else {
+        return None
+    };
+
+    if 0 <= h && h < 24 && 0 <= m && m < 60 && 0 <= sec && sec < 60 {
+        Some((h, m, sec, nsec))
+    } else {
+        None
+    }
+}
+
+/// Parses a list of days, `day` or `mon,tue,...`, into `tm_wday` numbers.
+fn parse_days(s: &str) -> Option<Vec<i32>> {
+    if s == "day" {
+        return Some(range(0, 7).collect());
+    }
+
+    let mut days = vec![];
+    for d in s.split(',') {
+        match DAYS.iter().position(|n| *n == d) {
+            Some(i) => days.push(i as i32),
+            None    => return None
+        }
+    }
+    Some(days)
+}
+
+/// Parses a local date and time of day into a point in time.
+fn parse_once(date: &str, time: &str) -> Option<Timespec> {
+    let ymd: Vec<Option<i32>> = date.split('-').map(|p| p.parse()).collect();
+    let (y, mo, d) = match ymd.as_slice() {
+        [Some(y), Some(mo), Some(d)] if 1 <= mo && mo <= 12
+                                     && 1 <= d && d <= 31 => (y, mo, d),
+        _ => return None
+    };
+
+    parse_time_of_day(time).map(|(h, m, s, ns)| {
+        // Starting from now gets us the local time zone.
+        let mut tm  = clock::now();
+        tm.tm_year  = y - 1900;
+        tm.tm_mon   = mo - 1;
+        tm.tm_mday  = d;
+        tm.tm_hour  = h;
+        tm.tm_min   = m;
+        tm.tm_sec   = s;
+        tm.tm_nsec  = ns;
+        tm.tm_isdst = -1;
+        tm.to_timespec()
+    })
+}
+
+/// Parses a schedule.
+///
+/// `path` is used to resolve relative files and for error messages, and
+/// files to be loaded are then rewritten with `maps`.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::schedule::parse;
+/// let src = "# news\nevery day 07:00 load /n.wav; play\n";
+/// let entries = parse(&Path::new("/s/x.sched"), src, &[]).ok().unwrap();
+/// assert_eq!(entries.len(), 1);
+/// assert_eq!(entries[0].line, 2);
+/// assert_eq!(entries[0].commands.len(), 2);
+///
+/// assert!(parse(&Path::new("x"), "every fortnight 07:00 play\n", &[]).is_err());
+/// ```
+///
+/// Files to `load` are relative to the schedule, whatever follows them:
+///
+/// ```rust
+/// use baps3_cli::schedule::parse;
+/// use baps3_cli::script::Step;
+/// let src = "every day 07:00 load n.wav 1\n";
+/// let entries = parse(&Path::new("/s/x.sched"), src, &[]).ok().unwrap();
+/// match entries[0].commands[0].step {
+///     Step::Send(ref m) => assert_eq!(m.args(), vec!["/s/n.wav", "1"]),
+///     _                 => panic!("expected a command")
+/// }
+/// ```
+pub fn parse(path: &Path, src: &str, maps: &[PathMap]) -> Baps3Result<Vec<Entry>> {
+    let mut unpacker = Unpacker::new();
+    let mut entries  = vec![];
+    let dir          = path.dir_path();
+
+    for (n, raw) in src.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+
+        let bad = |&: reason: &str| Baps3Error::BadScript {
+            path:   path.display().to_string(),
+            line:   n + 1,
+            reason: reason.to_owned()
+        };
+
+        for words in unpacker.feed(&*format!("{}\n", line)).iter() {
+            let ws: Vec<&str> = words.iter().map(|w| &**w).collect();
+
+            let (when, rest) = match ws.as_slice() {
+                ["every", days, time, rest..] => {
+                    let days = try!(parse_days(days).ok_or(
+                        bad("expected day, or days like mon,wed,fri")));
+                    let (h, m, s, ns) = try!(parse_time_of_day(time).ok_or(
+                        bad("expected a time like HH:MM[:SS[.FFF]]")));
+                    (When::Every { days: days, hour: h, min: m, sec: s, nsec: ns },
+                     rest)
+                },
+                [date, time, rest..] => (
+                    When::Once(try!(parse_once(date, time).ok_or(
+                        bad("expected YYYY-MM-DD HH:MM[:SS[.FFF]], or every")))),
+                    rest),
+                _ => return Err(bad("expected a time and some commands"))
+            };
+
+            let mut commands = vec![];
+            for mut c in split_commands(rest).into_iter() {
+                // Any arguments after the file are passed on untouched.
+                if 2 <= c.len() && &*c[0] == "load" {
+                    let file = dir.join(&*c[1]);
+                    let ap   = try!(file.as_str()
+                                        .ok_or(bad("bad file name"))
+                                        .and_then(|f| to_absolute_path_str(f)));
+                    c[1] = map_path(maps, &*ap);
+                }
+
+                match step(&dir, &*c) {
+                    Ok(Some(st)) => commands.push(Line { number: n + 1, step: st }),
+                    Ok(None)     => (),
+                    Err(reason)  => return Err(bad(reason))
+                }
+            }
+            if commands.is_empty() {
+                return Err(bad("expected a time and some commands"));
+            }
+
+            entries.push(Entry { line:     n + 1,
+                                 when:     when,
+                                 text:     rest.connect(" "),
+                                 commands: commands });
+        }
+    }
+
+    Ok(entries)
+}
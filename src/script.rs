@@ -0,0 +1,313 @@
+//! Scripts of BAPS3 commands, run one at a time.
+//!
+//! A script has one BAPS3 command per line, written as it would be typed into
+//! `baps3-cli`.  Blank lines and lines starting with `#` are ignored, and two
+//! directives are understood:
+//!
+//! - `!sleep SECS` waits for `SECS` (possibly fractional) seconds;
+//! - `!source FILE` runs another script, relative to this one, in place.
+
+use std::borrow::ToOwned;
+use std::io::File;
+use std::io::timer::sleep;
+use std::os;
+use std::time::Duration;
+
+use baps3_protocol::proto::{ Message, Unpacker };
+
+use super::{ Baps3Error, Baps3Result };
+use super::session::is_connection_error;
+
+/// One step of a script.
+pub enum Step {
+    /// Send a command and wait for its acknowledgement.
+    Send(Message),
+
+    /// Wait for a while.
+    Sleep(Duration),
+
+    /// Run another script.
+    Source(Path)
+}
+
+/// A step, along with the line of the script it came from.
+pub struct Line {
+    pub number: usize,
+    pub step:   Step
+}
+
+/// Parses a script.
+///
+/// `path` is used to resolve `!source` directives and for error messages.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::script::parse;
+/// let src   = "# warm up\nload /a.mp3\n!sleep 0.5\n\nplay\n";
+/// let lines = parse(&Path::new("x.b3s"), src).ok().unwrap();
+/// assert_eq!(lines.iter().map(|l| l.number).collect::<Vec<usize>>(),
+///            vec![2, 3, 5]);
+/// ```
+pub fn parse(path: &Path, src: &str) -> Baps3Result<Vec<Line>> {
+    let mut unpacker = Unpacker::new();
+    let mut lines    = vec![];
+
+    for (n, raw) in src.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+
+        let bad = |&: reason: &str| Baps3Error::BadScript {
+            path:   path.display().to_string(),
+            line:   n + 1,
+            reason: reason.to_owned()
+        };
+
+        for words in unpacker.feed(&*format!("{}\n", line)).iter() {
+            match step(&path.dir_path(), &**words) {
+                Ok(Some(st)) => lines.push(Line { number: n + 1, step: st }),
+                Ok(None)     => (),
+                Err(reason)  => return Err(bad(reason))
+            }
+        }
+    }
+
+    Ok(lines)
+}
+
+/// Turns the words of one line of a script into a step.
+///
+/// `!source` paths are taken relative to `dir`.  Returns `None` for a line
+/// with no words, and a reason if the line is malformed.
+pub fn step(dir: &Path, words: &[String]) -> Result<Option<Step>, &'static str> {
+    Ok(Some(match words {
+        [ref d, ref secs] if &**d == "!sleep" => match secs.parse::<f64>() {
+            Some(s) if 0.0 <= s =>
+                Step::Sleep(Duration::milliseconds((s * 1000.0) as i64)),
+            _ => return Err("expected !sleep SECONDS")
+        },
+        [ref d, ref file] if &**d == "!source" =>
+            Step::Source(dir.join(&**file)),
+        [ref d, ..] if d.starts_with("!") => return Err("unknown directive"),
+        [ref word, args..] => {
+            let mut msg = Message::new(&**word);
+            for arg in args.iter() {
+                msg = msg.arg(&**arg);
+            }
+            Step::Send(msg)
+        },
+        [] => return Ok(None)
+    }))
+}
+
+/// Splits a sequence of words into commands, at words ending in `;`.
+///
+/// Empty commands are dropped.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::script::split_commands;
+/// assert_eq!(split_commands(&["load", "/a.wav;", "play"]),
+///            vec![vec!["load".to_string(), "/a.wav".to_string()],
+///                 vec!["play".to_string()]]);
+/// ```
+pub fn split_commands(words: &[&str]) -> Vec<Vec<String>> {
+    let mut commands = vec![];
+    let mut command  = vec![];
+
+    for w in words.iter() {
+        if w.ends_with(";") {
+            let bare = w.trim_right_matches(';');
+            if !bare.is_empty() { command.push(bare.to_owned()); }
+            if !command.is_empty() { commands.push(command); }
+            command = vec![];
+        } else {
+            command.push((*w).to_owned());
+        }
+    }
+    if !command.is_empty() { commands.push(command); }
+
+    commands
+}
+
+/// How deeply `!source` directives may nest.
+const MAX_DEPTH: usize = 16;
+
+/// Runs the script at `path`, handing each command to `send`.
+///
+/// `send` should block until the command is acknowledged.  If a command
+/// fails, the script stops with an error, unless `keep_going` is set, in which
+/// case the failure is reported on stderr and the script carries on.  Losing
+/// the connection stops the script either way.
+///
+/// Returns the number of commands that failed.
+///
+/// # Examples
+///
+/// A script that `!source`s itself is an error, rather than a stack overflow:
+///
+/// ```rust
+/// extern crate baps3_cli;
+/// extern crate baps3_protocol;
+/// use std::io::{ File, TempDir };
+/// use baps3_cli::Baps3Result;
+/// use baps3_cli::script::run;
+/// use baps3_protocol::proto::Message;
+///
+/// # fn main() {
+/// let dir  = TempDir::new("baps3-script").unwrap();
+/// let path = dir.path().join("loop.b3s");
+/// File::create(&path).write_str("!source loop.b3s\n").unwrap();
+///
+/// let mut send = |&mut: _: &Message| -> Baps3Result<()> { Ok(()) };
+/// assert!(run(&path, true, &mut send).is_err());
+/// # }
+/// ```
+pub fn run<F: ?Sized>(path: &Path, keep_going: bool, send: &mut F) -> Baps3Result<usize>
+where F: FnMut(&Message) -> Baps3Result<()> {
+    run_reporting(path, keep_going, send, &mut to_stderr)
+}
+
+/// As `run`, but handing failures to `report` rather than writing them to
+/// stderr when `keep_going` is set.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate baps3_cli;
+/// extern crate baps3_protocol;
+/// use std::io::{ File, TempDir };
+/// use baps3_cli::{ Baps3Error, Baps3Result };
+/// use baps3_cli::script::run_reporting;
+/// use baps3_protocol::proto::Message;
+///
+/// # fn main() {
+/// let dir  = TempDir::new("baps3-script").unwrap();
+/// let path = dir.path().join("fail.b3s");
+/// File::create(&path).write_str("play\nstop\n").unwrap();
+///
+/// let mut send = |&mut: m: &Message| -> Baps3Result<()> {
+///     if m.word() != "play" { return Ok(()); }
+///     Err(Baps3Error::CmdFailed { advice: "nothing loaded".to_string() })
+/// };
+/// let mut reported = vec![];
+/// let failures = run_reporting(&path, true, &mut send, &mut |&mut: e: &Baps3Error| {
+///     reported.push(e.to_string())
+/// });
+/// assert_eq!(failures.ok(), Some(1));
+/// assert_eq!(reported.len(), 1);
+/// # }
+/// ```
+pub fn run_reporting<F: ?Sized, R: ?Sized>(path:       &Path,
+                                           keep_going: bool,
+                                           send:       &mut F,
+                                           report:     &mut R) -> Baps3Result<usize>
+where F: FnMut(&Message) -> Baps3Result<()>, R: FnMut(&Baps3Error) {
+    run_sourced(path, keep_going, send, report, &mut vec![])
+}
+
+/// Runs already-parsed script lines, as `run` does.
+///
+/// `name` says where the lines came from, for error messages.
+pub fn run_lines<F: ?Sized>(name: &str, lines: Vec<Line>, keep_going: bool, send: &mut F)
+  -> Baps3Result<usize>
+where F: FnMut(&Message) -> Baps3Result<()> {
+    run_lines_reporting(name, lines, keep_going, send, &mut to_stderr)
+}
+
+/// Runs already-parsed script lines, as `run_reporting` does.
+pub fn run_lines_reporting<F: ?Sized, R: ?Sized>(name:       &str,
+                                                 lines:      Vec<Line>,
+                                                 keep_going: bool,
+                                                 send:       &mut F,
+                                                 report:     &mut R)
+  -> Baps3Result<usize>
+where F: FnMut(&Message) -> Baps3Result<()>, R: FnMut(&Baps3Error) {
+    run_lines_sourced(name, lines, keep_going, send, report, &mut vec![])
+}
+
+/// Reports a failure that a script carried on past on stderr.
+fn to_stderr(err: &Baps3Error) {
+    let _ = ::std::io::stderr().write_line(&*err.to_string());
+}
+
+/// As `run_reporting`, where `sources` holds the absolute paths of the
+/// scripts that have `!source`d this one.
+fn run_sourced<F: ?Sized, R: ?Sized>(path:       &Path,
+                                     keep_going: bool,
+                                     send:       &mut F,
+                                     report:     &mut R,
+                                     sources:    &mut Vec<Path>) -> Baps3Result<usize>
+where F: FnMut(&Message) -> Baps3Result<()>, R: FnMut(&Baps3Error) {
+    let src   = try!(File::open(path).read_to_string());
+    let lines = try!(parse(path, &*src));
+
+    sources.push(os::make_absolute(path).unwrap_or_else(|_| path.clone()));
+    let result = run_lines_sourced(&*path.display().to_string(),
+                                   lines,
+                                   keep_going,
+                                   send,
+                                   report,
+                                   sources);
+    sources.pop();
+    result
+}
+
+/// As `run_lines_reporting`, where `sources` is as in `run_sourced`.
+fn run_lines_sourced<F: ?Sized, R: ?Sized>(name:       &str,
+                                           lines:      Vec<Line>,
+                                           keep_going: bool,
+                                           send:       &mut F,
+                                           report:     &mut R,
+                                           sources:    &mut Vec<Path>)
+  -> Baps3Result<usize>
+where F: FnMut(&Message) -> Baps3Result<()>, R: FnMut(&Baps3Error) {
+    let mut failures = 0;
+
+    for Line { number, step } in lines.into_iter() {
+        let bad = |&: reason: String| Baps3Error::BadScript {
+            path:   name.to_owned(),
+            line:   number,
+            reason: reason
+        };
+
+        let result = match step {
+            Step::Send(msg) => match send(&msg) {
+                // Carrying on without a server would only fail every
+                // command that follows.
+                Err(ref e) if is_connection_error(e) =>
+                    return Err(bad(e.to_string())),
+                r => r.map(|_| 0)
+            },
+            Step::Sleep(d)  => { sleep(d); Ok(0) },
+            Step::Source(p) => {
+                let ap = os::make_absolute(&p).unwrap_or_else(|_| p.clone());
+                if sources.contains(&ap) {
+                    return Err(bad("recursive !source".to_owned()));
+                }
+                if MAX_DEPTH <= sources.len() {
+                    return Err(bad("!source nested too deeply".to_owned()));
+                }
+                run_sourced(&p, keep_going, send, report, sources)
+            }
+        };
+
+        match result {
+            Ok(n) => failures += n,
+            // Errors from nested scripts already say where they happened.
+            Err(e @ Baps3Error::BadScript { .. }) => return Err(e),
+            Err(e) => {
+                let err = bad(e.to_string());
+
+                if !keep_going { return Err(err); }
+                report(&err);
+                failures += 1;
+            }
+        }
+    }
+
+    Ok(failures)
+}
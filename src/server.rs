@@ -0,0 +1,304 @@
+//! The server side of the BAPS3 protocol, for tools that accept connections
+//! from BAPS3 clients, such as control channels and proxies.
+//!
+//! `listen` accepts clients in the background, and reports everything that
+//! happens to them as `Event`s on a channel, so that a tool can handle all of
+//! its clients (and its own upstream connection) from one loop.  `Relay`
+//! builds on this to share one connection to a BAPS3 server between many
+//! clients, whatever they are connected by.
+
+use std::borrow::ToOwned;
+use std::io::{ Acceptor, BufferedReader, IoResult, Listener };
+use std::io::net::ip::ToSocketAddr;
+use std::io::net::tcp::{ TcpListener, TcpStream };
+use std::sync::mpsc::Sender;
+use std::thread::Thread;
+use std::time::Duration;
+
+use baps3_protocol::proto::{ Message, Unpacker };
+
+use super::{ Baps3Error, Baps3Result };
+use super::format::timestamp;
+use super::session::Session;
+
+/// A client that a `Relay` can pass a server's messages on to.
+pub trait Peer {
+    /// The client's id, as used in events about it.
+    fn id(&self) -> usize;
+
+    /// The client's address, for logging.
+    fn peer(&self) -> &str;
+
+    /// Sends a message to the client.
+    fn deliver(&mut self, msg: &Message) -> IoResult<()>;
+
+    /// Hangs up on the client.
+    fn hang_up(&mut self);
+}
+
+/// A connected client, as far as writing to it goes.
+pub struct Downstream {
+    pub id:   usize,
+
+    /// The client's address, for logging.
+    pub peer: String,
+
+    stream: TcpStream
+}
+
+impl Downstream {
+    /// Sends a message to the client.
+    pub fn send(&mut self, msg: &Message) -> IoResult<()> {
+        self.stream.write_str(&*msg.pack())
+    }
+
+    /// Hangs up on the client.
+    pub fn close(&mut self) {
+        let _ = self.stream.close_read();
+        let _ = self.stream.close_write();
+    }
+}
+
+impl Peer for Downstream {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn peer(&self) -> &str {
+        &*self.peer
+    }
+
+    fn deliver(&mut self, msg: &Message) -> IoResult<()> {
+        self.send(msg)
+    }
+
+    fn hang_up(&mut self) {
+        self.close()
+    }
+}
+
+/// Something that happened to one of our clients.
+pub enum Event {
+    /// A client connected.
+    Connected(Downstream),
+
+    /// A client, identified by its id, sent a message.
+    Message(usize, Message),
+
+    /// A client went away.
+    Disconnected(usize)
+}
+
+/// Builds a message from its words, as unpacked from the wire.
+///
+/// Returns `None` if there are no words.
+pub fn message_from_words(words: &[String]) -> Option<Message> {
+    words.first().map(|w| words[1..].iter()
+                                    .fold(Message::new(&**w),
+                                          |m, a| m.arg(&**a)))
+}
+
+/// Builds the acknowledgement a BAPS3 server sends for the command `msg`,
+/// given how it went: OK on success, WHAT if it was invalid, and FAIL
+/// otherwise.  The advice of a failed or invalid command is passed through
+/// as is, so acknowledgements from another server can be relayed.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate baps3_cli;
+/// extern crate baps3_protocol;
+/// use baps3_cli::acknowledgement;
+/// use baps3_cli::server::ack;
+/// use baps3_protocol::proto::Message;
+///
+/// # fn main() {
+/// let skip = Message::new("next").arg("/a.mp3");
+/// assert!(acknowledgement("next", &["/a.mp3"], &ack(&skip, &Ok(())))
+///           .unwrap().is_ok());
+/// # }
+/// ```
+pub fn ack(msg: &Message, result: &Baps3Result<()>) -> Message {
+    let head = match *result {
+        Ok(_) => Message::new("OK"),
+        Err(Baps3Error::CmdInvalid { ref advice }) =>
+            Message::new("WHAT").arg(&**advice),
+        Err(Baps3Error::CmdFailed { ref advice }) =>
+            Message::new("FAIL").arg(&**advice),
+        Err(ref e) => Message::new("FAIL").arg(&*e.to_string())
+    };
+
+    msg.args().iter().fold(head.arg(msg.word()), |m, a| m.arg(*a))
+}
+
+/// One connection to a BAPS3 server, shared between many clients.
+///
+/// Each client is greeted with the server's OHAI and FEATURES, gets the
+/// acknowledgements of its own commands, and gets every other message from
+/// the server.  Clients are hung up on if the server goes away, as their view
+/// of it is then stale; they should start afresh when it comes back.
+pub struct Relay<C> {
+    pub session: Session,
+    pub clients: Vec<C>,
+
+    /// Whether to log each command.
+    log:         bool,
+
+    /// Whether we were connected to the server when we last looked.
+    up:          bool
+}
+
+impl<C: Peer> Relay<C> {
+    /// Creates a relay with no clients yet, logging each command if `log`.
+    pub fn new(session: Session, log: bool) -> Relay<C> {
+        Relay { session: session, clients: vec![], log: log, up: false }
+    }
+
+    /// Sends `msg` to every client, dropping those that have gone away.
+    pub fn broadcast(&mut self, msg: &Message) {
+        let mut gone = vec![];
+        for c in self.clients.iter_mut() {
+            if c.deliver(msg).is_err() { gone.push(c.id()); }
+        }
+        self.clients.retain(|c| !gone.contains(&c.id()));
+    }
+
+    /// Waits up to `tick` for a message from the server and passes it on.
+    pub fn poll_server(&mut self, tick: Duration) {
+        match self.session.recv_timeout(tick) {
+            Ok(Some(msg)) => self.broadcast(&msg),
+            Ok(None)      => (),
+            Err(e)        => {
+                let _ = ::std::io::stderr().write_line(&*format!(
+                    "{} {}: {}", timestamp(), self.session.addr(), e));
+            }
+        }
+
+        let up = self.session.get().is_some();
+        if self.up && !up {
+            for c in self.clients.iter_mut() {
+                c.hang_up();
+            }
+            self.clients.clear();
+        }
+        self.up = up;
+    }
+
+    /// Returns the messages to greet a new client with on behalf of the
+    /// server, or `None` if the server is not connected.
+    pub fn greeting(&mut self) -> Option<Vec<Message>> {
+        self.session.get().map(|b| {
+            vec![Message::new("OHAI").arg(b.ident()),
+                 b.features().iter().fold(Message::new("FEATURES"),
+                                          |m, f| m.arg(&**f))]
+        })
+    }
+
+    /// Sends a new client `greeting`, and takes it on if that worked.
+    pub fn join(&mut self, mut client: C, greeting: &[Message]) {
+        if greeting.iter().all(|m| client.deliver(m).is_ok()) {
+            if self.log { println!("{} {}: connected", timestamp(), client.peer()); }
+            self.clients.push(client);
+        }
+    }
+
+    /// Looks up a client by its id.
+    pub fn client(&mut self, id: usize) -> Option<&mut C> {
+        self.clients.iter_mut().find(|c| c.id() == id)
+    }
+
+    /// Passes a client's command on to the server, and its outcome back.
+    pub fn forward(&mut self, id: usize, msg: Message) {
+        let reply = match self.session.send_collecting(&msg) {
+            Ok(others) => {
+                for m in others.iter() {
+                    self.broadcast(m);
+                }
+                ack(&msg, &Ok(()))
+            },
+            Err(e) => ack(&msg, &Err(e))
+        };
+
+        let log = self.log;
+        if let Some(c) = self.client(id) {
+            if log {
+                println!("{} {}: {} -> {}",
+                         timestamp(),
+                         c.peer(),
+                         msg.pack().trim_right(),
+                         reply.word());
+            }
+            let _ = c.deliver(&reply);
+        }
+    }
+
+    /// Forgets a client that has gone away.
+    pub fn disconnected(&mut self, id: usize) {
+        if self.log {
+            if let Some(c) = self.clients.iter().find(|c| c.id() == id) {
+                println!("{} {}: disconnected", timestamp(), c.peer());
+            }
+        }
+        self.clients.retain(|c| c.id() != id);
+    }
+}
+
+/// Starts accepting clients on `addr`, reporting them on `events`.
+///
+/// The listening socket is set up before this returns, so that problems
+/// binding to `addr` can be reported; clients are then accepted in the
+/// background for as long as `events` has a receiver.
+pub fn listen<A: ToSocketAddr>(addr: A, events: Sender<Event>) -> IoResult<()> {
+    let mut acceptor = try!(TcpListener::bind(addr).listen());
+
+    Thread::spawn(move || {
+        let mut next_id = 0us;
+
+        for stream in acceptor.incoming() {
+            let mut stream = match stream {
+                Ok(s)  => s,
+                Err(_) => continue
+            };
+
+            let id   = next_id;
+            next_id += 1;
+            let peer = stream.peer_name()
+                             .map(|p| p.to_string())
+                             .unwrap_or("(unknown)".to_owned());
+
+            let reader = stream.clone();
+            let down   = Downstream { id: id, peer: peer, stream: stream };
+            if events.send(Event::Connected(down)).is_err() {
+                return;
+            }
+
+            let tx = events.clone();
+            Thread::spawn(move || read_client(id, reader, tx));
+        }
+    });
+
+    Ok(())
+}
+
+/// Reads messages from one client until it goes away.
+fn read_client(id: usize, stream: TcpStream, events: Sender<Event>) {
+    let mut reader   = BufferedReader::new(stream);
+    let mut unpacker = Unpacker::new();
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(l)  => l,
+            Err(_) => break
+        };
+
+        for words in unpacker.feed(&*line).iter() {
+            if let Some(msg) = message_from_words(&**words) {
+                if events.send(Event::Message(id, msg)).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    let _ = events.send(Event::Disconnected(id));
+}
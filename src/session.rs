@@ -0,0 +1,194 @@
+//! Long-lived connections to BAPS3 servers, for daemons that should ride out
+//! the server going away and coming back.
+
+use std::borrow::ToOwned;
+use std::cmp;
+use std::io::timer::sleep;
+use std::time::Duration;
+
+use baps3_protocol::proto::Message;
+
+use clock::{ self, Timespec };
+
+use super::{ Baps3, Baps3Error, Baps3Result, verbose_logger };
+use super::state::ServerState;
+
+/// The logger used by sessions.
+///
+/// This is a plain function, rather than a closure, so that sessions have a
+/// type that can be named.
+pub type Logger = fn(&str);
+
+fn quiet_log(s: &str)   { verbose_logger(false, s) }
+fn verbose_log(s: &str) { verbose_logger(true, s)  }
+
+/// Determines whether `err` means the connection is no longer usable.
+pub fn is_connection_error(err: &Baps3Error) -> bool {
+    match *err {
+        Baps3Error::HungUp | Baps3Error::Io { .. } | Baps3Error::TimedOut => true,
+        _ => false
+    }
+}
+
+/// A connection to a BAPS3 server that is remade when it is lost.
+pub struct Session {
+    addr:         String,
+    features:     Vec<String>,
+    retry:        Duration,
+    log:          Logger,
+    baps3:        Option<Baps3<Logger>>,
+    next_attempt: Timespec,
+
+    /// Our mirror of the server, for the current connection only.
+    mirror:       Option<ServerState>,
+
+    /// Whether the last attempt to connect failed, so that `recv_timeout`
+    /// only reports the server being unreachable once.
+    failing:      bool,
+
+    /// How many times we have connected to the server, including the first.
+    ///
+    /// Anything that needs to act on a fresh connection, such as checking
+    /// what the server is up to, should do so whenever this changes.
+    pub connects: u64,
+
+    /// How long the most recent successful handshake took.
+    pub handshake: Option<Duration>
+}
+
+impl Session {
+    /// Creates a session, which will connect to the server at `addr` when
+    /// first needed and insist on it having `features`.
+    ///
+    /// After the connection is lost, or an attempt to connect fails, the
+    /// session waits `retry` before trying again.
+    pub fn new(addr: &str, features: &[&str], retry: Duration, verbose: bool)
+      -> Session {
+        Session { addr:         addr.to_owned(),
+                  features:     features.iter().map(|f| (*f).to_owned()).collect(),
+                  retry:        retry,
+                  log:          if verbose { verbose_log } else { quiet_log },
+                  baps3:        None,
+                  next_attempt: clock::get_time(),
+                  mirror:       None,
+                  failing:      false,
+                  connects:     0,
+                  handshake:    None }
+    }
+
+    /// Returns the address of the server.
+    pub fn addr(&self) -> &str {
+        &*self.addr
+    }
+
+    /// Returns the connection, if there is one.
+    pub fn get(&mut self) -> Option<&mut Baps3<Logger>> {
+        self.baps3.as_mut()
+    }
+
+    /// Returns our mirror of the server's state, if connected.
+    ///
+    /// The mirror starts afresh with each connection, and is dropped when the
+    /// connection is lost; messages from the server should be passed to
+    /// `update` to keep it current.
+    pub fn state(&self) -> Option<&ServerState> {
+        self.mirror.as_ref()
+    }
+
+    /// Updates the mirror with a message from the server.
+    ///
+    /// Returns true if the message changed the mirror.
+    pub fn update(&mut self, msg: &Message) -> bool {
+        self.mirror.as_mut().map_or(false, |s| s.update(msg))
+    }
+
+    /// Returns the connection, connecting first if need be.
+    pub fn connect(&mut self) -> Baps3Result<&mut Baps3<Logger>> {
+        if self.baps3.is_none() {
+            let log = self.log;
+            let features: Vec<&str> = self.features.iter().map(|f| &**f).collect();
+            let started = clock::get_time();
+
+            match Baps3::new(log, &*self.addr, &*features) {
+                Ok(b) => {
+                    log!(log, "connected to {} ({})", self.addr, b.ident());
+                    self.mirror    = Some(ServerState::new(b.ident(), b.features()));
+                    self.baps3     = Some(b);
+                    self.failing   = false;
+                    self.connects += 1;
+                    self.handshake = Some(clock::get_time() - started);
+                },
+                Err(e) => {
+                    self.next_attempt = clock::get_time() + self.retry;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(self.baps3.as_mut().unwrap())
+    }
+
+    /// Drops the connection, if any.
+    ///
+    /// The session will not try to reconnect on its own until `retry` has
+    /// passed.
+    pub fn disconnect(&mut self) {
+        if let Some(b) = self.baps3.take() {
+            let log = self.log;
+            log!(log, "disconnected from {}", self.addr);
+            b.quit();
+        }
+        self.mirror       = None;
+        self.next_attempt = clock::get_time() + self.retry;
+    }
+
+    /// Sends a command, connecting first if need be, and waits for its
+    /// acknowledgement.
+    pub fn send(&mut self, msg: &Message) -> Baps3Result<()> {
+        let result = try!(self.connect()).send(msg);
+        if let Err(ref e) = result {
+            if is_connection_error(e) { self.disconnect(); }
+        }
+        result
+    }
+
+    /// As `send`, but also returns the other messages that arrived before the
+    /// acknowledgement, so that notifications aren't lost.
+    pub fn send_collecting(&mut self, msg: &Message) -> Baps3Result<Vec<Message>> {
+        let result = try!(self.connect()).send_collecting(msg);
+        if let Err(ref e) = result {
+            if is_connection_error(e) { self.disconnect(); }
+        }
+        result
+    }
+
+    /// Waits up to `timeout` for a message from the server.
+    ///
+    /// While disconnected, this tries to reconnect once `retry` has passed
+    /// since the last attempt, and otherwise just waits.  Losing the
+    /// connection, and the first of any run of failures to reconnect, are
+    /// returned as errors, but the session carries on.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Baps3Result<Option<Message>> {
+        if self.baps3.is_none() {
+            let now = clock::get_time();
+            if now < self.next_attempt {
+                sleep(cmp::min(timeout, self.next_attempt - now));
+                return Ok(None);
+            }
+            let connected = self.connect().map(|_| ());
+            if let Err(e) = connected {
+                let log = self.log;
+                if self.failing {
+                    log!(log, "still can't connect to {}: {}", self.addr, e);
+                    return Ok(None);
+                }
+                self.failing = true;
+                return Err(e);
+            }
+        }
+
+        let result = self.baps3.as_mut().unwrap().recv_timeout(timeout);
+        if result.is_err() { self.disconnect(); }
+        result
+    }
+}
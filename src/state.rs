@@ -0,0 +1,167 @@
+//! A local mirror of a BAPS3 server's state, kept up to date from the
+//! notifications the server sends.
+
+use std::borrow::ToOwned;
+use std::time::Duration;
+
+use baps3_protocol::proto::Message;
+
+use super::{ Baps3, Baps3Result };
+use super::time::format_micros;
+
+/// How long to wait for more of the server's initial state dump, in
+/// milliseconds, before settling for what we have.
+const DUMP_TIMEOUT_MS: i64 = 500;
+
+/// What we know about a BAPS3 server.
+#[derive(Clone, RustcEncodable)]
+pub struct ServerState {
+    /// The server's identifier, from its OHAI.
+    pub ident: String,
+
+    /// The server's feature flags, from its FEATURES.
+    pub features: Vec<String>,
+
+    /// The last STATE reported (`Playing`, `Stopped`, `Ejected`, ...).
+    pub state: Option<String>,
+
+    /// The last FILE reported, if a file is loaded.
+    pub file: Option<String>,
+
+    /// The last TIME reported, in microseconds.
+    pub time: Option<u64>,
+
+    /// The length of the loaded file, in microseconds, if the server reports
+    /// it with LENGTH.
+    pub length: Option<u64>
+}
+
+impl ServerState {
+    /// Creates a mirror for a server that has just finished its handshake.
+    pub fn new(ident: &str, features: &[String]) -> ServerState {
+        ServerState { ident:    ident.to_owned(),
+                      features: features.to_vec(),
+                      state:    None,
+                      file:     None,
+                      time:     None,
+                      length:   None }
+    }
+
+    /// Updates the mirror with a message from the server.
+    ///
+    /// Returns true if the message changed the mirror.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// extern crate baps3_cli;
+    /// extern crate baps3_protocol;
+    /// use baps3_cli::state::ServerState;
+    /// use baps3_protocol::proto::Message;
+    ///
+    /// # fn main() {
+    /// let mut s = ServerState::new("playd", &[]);
+    /// assert!(s.update(&Message::new("STATE").arg("Playing")));
+    /// assert!(!s.update(&Message::new("STATE").arg("Playing")));
+    /// assert!(s.update(&Message::new("STATE").arg("Ejected")));
+    /// assert_eq!(s.state, Some("Ejected".to_string()));
+    /// # }
+    /// ```
+    pub fn update(&mut self, msg: &Message) -> bool {
+        match msg.as_str_vec().as_slice() {
+            ["OHAI", id] => {
+                let changed = &*self.ident != id;
+                self.ident = id.to_owned();
+                changed
+            },
+            ["FEATURES", fs..] => {
+                let features: Vec<String> = fs.iter()
+                                              .map(|f| (*f).to_owned())
+                                              .collect();
+                let changed = self.features != features;
+                self.features = features;
+                changed
+            },
+            ["STATE", st] => {
+                let changed = self.state.as_ref().map(|s| &**s) != Some(st);
+                self.state = Some(st.to_owned());
+
+                // An ejected server has no file, and hence no position.
+                if st == "Ejected" {
+                    self.file   = None;
+                    self.time   = None;
+                    self.length = None;
+                }
+                changed
+            },
+            ["FILE", f] => {
+                let changed = self.file.as_ref().map(|s| &**s) != Some(f);
+                // Any LENGTH we had was for the old file.
+                if changed { self.length = None; }
+                self.file = Some(f.to_owned());
+                changed
+            },
+            ["TIME", t] => match t.parse::<u64>() {
+                Some(us) if self.time != Some(us) => {
+                    self.time = Some(us);
+                    true
+                },
+                _ => false
+            },
+            ["LENGTH", l] => match l.parse::<u64>() {
+                Some(us) if self.length != Some(us) => {
+                    self.length = Some(us);
+                    true
+                },
+                _ => false
+            },
+            _ => false
+        }
+    }
+
+    /// Returns true if the mirror holds a full picture of the server.
+    ///
+    /// This is the case once the server has told us its state and, if it has
+    /// a file loaded, which file and where in it the server is.
+    pub fn is_complete(&self) -> bool {
+        match self.state {
+            None                             => false,
+            Some(ref s) if &**s == "Ejected" => true,
+            Some(_) => self.file.is_some() && self.time.is_some()
+        }
+    }
+
+    /// Returns a human-readable, multi-line summary of the mirror.
+    pub fn summary(&self) -> String {
+        let unknown = "(unknown)".to_owned();
+
+        format!("ident:    {}\n\
+                 features: {}\n\
+                 state:    {}\n\
+                 file:     {}\n\
+                 position: {}",
+                self.ident,
+                self.features.connect(" "),
+                self.state.clone().unwrap_or(unknown.clone()),
+                self.file.clone().unwrap_or("(none)".to_owned()),
+                self.time.map(format_micros).unwrap_or(unknown))
+    }
+}
+
+/// Builds a mirror of a server we have just connected to, from the state it
+/// sends straight after its FEATURES.
+///
+/// There is no marker for the end of this state dump, so this stops once the
+/// mirror is complete or the server goes quiet.
+pub fn read_state<L: Fn(&str)>(baps3: &mut Baps3<L>) -> Baps3Result<ServerState> {
+    let mut state = ServerState::new(baps3.ident(), baps3.features());
+
+    while !state.is_complete() {
+        match try!(baps3.recv_timeout(Duration::milliseconds(DUMP_TIMEOUT_MS))) {
+            Some(msg) => { state.update(&msg); },
+            None      => break
+        }
+    }
+
+    Ok(state)
+}
@@ -86,4 +86,24 @@ impl TimeUnit {
         else if ms { TimeUnit::Milliseconds }
         else       { TimeUnit::Microseconds }
     }
-}
\ No newline at end of file
+}
+
+/// Formats a BAPS3 time, in microseconds, as a human-readable `[h:]mm:ss`
+/// string.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::time::format_micros;
+/// assert_eq!(&*format_micros(83000000), "01:23");
+/// assert_eq!(&*format_micros(3723000000), "1:02:03");
+/// ```
+pub fn format_micros(us: u64) -> String {
+    let secs = TimeUnit::Seconds.from_micros(us);
+    let (h, m, s) = (secs / 3600, (secs / 60) % 60, secs % 60);
+
+    format!("{}{:02}:{:02}",
+            if 0 < h { format!("{}:", h) } else { String::new() },
+            m,
+            s)
+}
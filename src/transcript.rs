@@ -0,0 +1,66 @@
+//! Timestamped transcripts of BAPS3 sessions.
+//!
+//! Each line of a transcript is one event:
+//!
+//! ```text
+//! 2015-01-10 12:34:56.789 studio1 > load "/srv/music/a b.mp3"
+//! 2015-01-10 12:34:56.801 studio1 < OK load "/srv/music/a b.mp3"
+//! ```
+//!
+//! The fields are the local time, the session name (`-` if there is none),
+//! a marker, and the rest of the line.  The markers are `>` for a message
+//! sent to a server, `<` for a message received from one, `!` for a command
+//! given to the client itself, and `*` for anything else (connections,
+//! disconnections and so on).  Messages are written as they are sent over the
+//! wire, so the text after `>` or `<` can be fed back to a server as-is.
+
+use std::io::{ Append, File, IoResult, Write };
+
+use baps3_protocol::proto::Message;
+
+use super::format::{ Format, timestamp };
+
+/// An open transcript file.
+pub struct Transcript {
+    file: File
+}
+
+impl Transcript {
+    /// Opens a transcript, appending to the file at `path` if it exists.
+    pub fn open(path: &Path) -> IoResult<Transcript> {
+        File::open_mode(path, Append, Write).map(|f| Transcript { file: f })
+    }
+
+    /// Records a message sent to the server of `session`.
+    pub fn outgoing(&mut self, session: &str, msg: &Message) {
+        self.write(session, '>', &*Format::Raw.message(msg));
+    }
+
+    /// Records a message received from the server of `session`.
+    pub fn incoming(&mut self, session: &str, msg: &Message) {
+        self.write(session, '<', &*Format::Raw.message(msg));
+    }
+
+    /// Records a command given to the client itself.
+    pub fn meta(&mut self, session: &str, command: &str) {
+        self.write(session, '!', command);
+    }
+
+    /// Records anything else that happened.
+    pub fn event(&mut self, session: &str, what: &str) {
+        self.write(session, '*', what);
+    }
+
+    fn write(&mut self, session: &str, marker: char, text: &str) {
+        let session = if session.is_empty() { "-" } else { session };
+
+        // A transcript that can't be written shouldn't stop the show, but
+        // there's nowhere sensible to report it either.
+        let _ = self.file.write_line(&*format!("{} {} {} {}",
+                                               timestamp(),
+                                               session,
+                                               marker,
+                                               text))
+                         .and_then(|_| self.file.flush());
+    }
+}
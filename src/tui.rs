@@ -0,0 +1,122 @@
+//! A simple full-screen terminal interface, made of a status header, a
+//! scrolling log, and an input line at the bottom of the screen.
+//!
+//! This only uses VT100 escape codes: the log is a scrolling region between
+//! the header and the input line, so anything written to it scrolls without
+//! disturbing either.  The input line itself belongs to whoever is reading
+//! input (usually an `edit::LineEditor`).
+
+use std::borrow::ToOwned;
+use std::cmp;
+use std::io::{ IoError, IoErrorKind, IoResult };
+use std::iter;
+
+use libc;
+
+use super::edit::stty;
+
+/// A full-screen terminal interface.
+pub struct Tui {
+    rows:   usize,
+    cols:   usize,
+    header: Vec<String>
+}
+
+impl Tui {
+    /// Takes over the terminal, leaving `header_rows` rows for the header.
+    pub fn new(header_rows: usize) -> IoResult<Tui> {
+        if unsafe { libc::isatty(1) } == 0 {
+            return Err(IoError { kind:   IoErrorKind::OtherIoError,
+                                 desc:   "not a terminal",
+                                 detail: None });
+        }
+
+        let size = try!(stty(&["size"]));
+        let dims: Vec<usize> = size.split(' ')
+                                   .filter_map(|d| d.trim().parse())
+                                   .collect();
+        let (rows, cols) = match dims.as_slice() {
+            [r, c] if header_rows + 3 <= r => (r, c),
+            _ => return Err(IoError { kind:   IoErrorKind::OtherIoError,
+                                      desc:   "terminal too small",
+                                      detail: Some(size.trim().to_owned()) })
+        };
+
+        // Header rows, then a rule, then the log, then the input line.
+        print!("\x1b[2J\x1b[{};{}r\x1b[{};1H", header_rows + 2, rows - 1, rows);
+
+        let mut tui = Tui { rows:   rows,
+                            cols:   cols,
+                            header: (0..header_rows).map(|_| String::new()).collect() };
+        tui.draw_header();
+        Ok(tui)
+    }
+
+    /// Replaces the header, redrawing it if it has changed.
+    ///
+    /// Lines beyond the space reserved for the header are dropped.
+    pub fn set_header(&mut self, lines: Vec<String>) {
+        let mut lines = lines;
+        lines.truncate(self.header.len());
+        while lines.len() < self.header.len() { lines.push(String::new()); }
+
+        if lines != self.header {
+            self.header = lines;
+            self.draw_header();
+        }
+    }
+
+    /// Adds a line to the bottom of the log, scrolling the rest up.
+    pub fn log(&mut self, line: &str) {
+        // Save the cursor, go to the bottom of the log, write, and come back
+        // to wherever the input line's cursor was.
+        print!("\x1b7\x1b[{};1H\n{}\x1b8", self.rows - 1, self.fit(line));
+        flush();
+    }
+
+    /// Gives the terminal back.
+    pub fn close(&mut self) {
+        print!("\x1b[r\x1b[2J\x1b[H");
+        flush();
+    }
+
+    fn draw_header(&self) {
+        print!("\x1b7");
+        for (i, line) in self.header.iter().enumerate() {
+            print!("\x1b[{};1H\x1b[7m{:2$}\x1b[0m", i + 1, self.fit(&**line), self.cols);
+        }
+        print!("\x1b[{};1H{}\x1b8",
+               self.header.len() + 1,
+               iter::repeat("-").take(self.cols).collect::<String>());
+        flush();
+    }
+
+    /// Cuts `line` down to the width of the terminal.
+    fn fit(&self, line: &str) -> String {
+        line.chars().take(self.cols).collect()
+    }
+}
+
+/// Draws a bar `width` characters wide, filled in proportion to how much of
+/// `total` is `done`.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::tui::progress_bar;
+/// assert_eq!(&*progress_bar(30, 120, 8), "[##------]");
+/// assert_eq!(&*progress_bar(200, 120, 4), "[####]");
+/// assert_eq!(&*progress_bar(0, 0, 4), "[----]");
+/// ```
+pub fn progress_bar(done: u64, total: u64, width: usize) -> String {
+    let filled = if total == 0 { 0 }
+                 else { (cmp::min(done, total) * (width as u64) / total) as usize };
+
+    format!("[{}{}]",
+            iter::repeat('#').take(filled).collect::<String>(),
+            iter::repeat('-').take(width - filled).collect::<String>())
+}
+
+fn flush() {
+    let _ = ::std::io::stdout().flush();
+}
@@ -0,0 +1,225 @@
+//! Dead-air detection: deciding, from a mirror of a BAPS3 server's state,
+//! when something has gone wrong on air.
+//!
+//! A `Watch` is checked regularly, and reports each alert once when it
+//! starts and once when it clears, rather than on every check.
+
+use std::time::Duration;
+
+use clock::Timespec;
+
+use super::schedule::parse_time_of_day;
+use super::state::ServerState;
+
+/// Something wrong with a server.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum Alert {
+    /// We can't reach the server.
+    Disconnected,
+
+    /// The server has not been playing for too long, while on air.
+    Stopped,
+
+    /// The server says it is playing, but its position hasn't moved for
+    /// too long.
+    Stalled
+}
+
+impl Alert {
+    /// Returns the name of the alert, as given to hooks.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Alert::Disconnected => "disconnected",
+            Alert::Stopped      => "stopped",
+            Alert::Stalled      => "stalled"
+        }
+    }
+}
+
+/// A change in which alerts are active.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum Change {
+    Fired(Alert),
+    Cleared(Alert)
+}
+
+/// A span of local time each day, which may run past midnight.  A span that
+/// ends when it starts, such as `06:00-06:00`, covers the whole day.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub struct Window {
+    /// The start and end of the window, in seconds since midnight.
+    start: i32,
+    end:   i32
+}
+
+impl Window {
+    /// Parses a window, `HH:MM[:SS]-HH:MM[:SS]`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use baps3_cli::watchdog::Window;
+    /// let w = Window::parse("22:00-02:00").unwrap();
+    /// assert!(w.contains(23 * 3600));
+    /// assert!(w.contains(3600));
+    /// assert!(!w.contains(12 * 3600));
+    ///
+    /// let all_day = Window::parse("00:00-00:00").unwrap();
+    /// assert!(all_day.contains(0));
+    /// assert!(all_day.contains(12 * 3600));
+    ///
+    /// assert!(Window::parse("07:00").is_none());
+    /// ```
+    pub fn parse(s: &str) -> Option<Window> {
+        let secs = |&: t: &str| parse_time_of_day(t).map(|(h, m, s, _)| {
+            h * 3600 + m * 60 + s
+        });
+
+        let parts: Vec<&str> = s.split('-').collect();
+        match parts.as_slice() {
+            [start, end] => match (secs(start), secs(end)) {
+                (Some(a), Some(b)) => Some(Window { start: a, end: b }),
+                _                  => None
+            },
+            _ => None
+        }
+    }
+
+    /// Determines whether the time of day `secs`, in seconds since
+    /// midnight, falls in the window.
+    pub fn contains(&self, secs: i32) -> bool {
+        if self.start == self.end {
+            true
+        } else if self.start < self.end {
+            self.start <= secs && secs < self.end
+        } else {
+            self.start <= secs || secs < self.end
+        }
+    }
+}
+
+/// Keeps track of a server's health.
+pub struct Watch {
+    /// How long the server may go without playing, while on air.
+    stopped_after: Duration,
+
+    /// How long the server's position may stay put while playing.
+    stall_after:   Duration,
+
+    /// When the server was first seen not playing, if it isn't.
+    stopped_since: Option<Timespec>,
+
+    /// Whether the server was playing at the last check.
+    was_playing:   bool,
+
+    /// The last position seen, and when it last changed.
+    last_time:     Option<u64>,
+    moved_at:      Timespec,
+
+    active:        Vec<Alert>
+}
+
+impl Watch {
+    pub fn new(stopped_after: Duration, stall_after: Duration) -> Watch {
+        Watch { stopped_after: stopped_after,
+                stall_after:   stall_after,
+                stopped_since: None,
+                was_playing:   false,
+                last_time:     None,
+                moved_at:      Timespec { sec: 0, nsec: 0 },
+                active:        vec![] }
+    }
+
+    /// Determines which alerts should be active at `now`, given the server's
+    /// state (`None` if we can't reach it) and whether we are on air.
+    fn current(&mut self,
+               state:  Option<&ServerState>,
+               on_air: bool,
+               now:    Timespec) -> Vec<Alert> {
+        let s = match state {
+            Some(s) => s,
+            None    => {
+                self.stopped_since = None;
+                self.was_playing   = false;
+                return vec![Alert::Disconnected];
+            }
+        };
+
+        let mut alerts = vec![];
+        let playing    = s.state.as_ref().map(|st| &**st) == Some("Playing");
+        if playing {
+            if !self.was_playing || s.time != self.last_time {
+                self.moved_at = now;
+            }
+            self.stopped_since = None;
+            if self.moved_at + self.stall_after <= now {
+                alerts.push(Alert::Stalled);
+            }
+        } else if s.state.is_some() {
+            // Until the server tells us its state, we can't say it's stopped.
+            if self.stopped_since.is_none() {
+                self.stopped_since = Some(now);
+            }
+            let since = self.stopped_since.unwrap();
+            if on_air && since + self.stopped_after <= now {
+                alerts.push(Alert::Stopped);
+            }
+        }
+        self.was_playing = playing;
+        self.last_time   = s.time;
+
+        alerts
+    }
+
+    /// Checks the server's health at `now`, given its state (`None` if we
+    /// can't reach it) and whether we are on air, and returns which alerts
+    /// have fired or cleared since the last check.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// extern crate baps3_cli;
+    /// extern crate baps3_protocol;
+    /// extern crate time;
+    /// use std::time::Duration;
+    /// use baps3_cli::state::ServerState;
+    /// use baps3_cli::watchdog::{ Alert, Change, Watch };
+    /// use baps3_protocol::proto::Message;
+    /// use time::Timespec;
+    ///
+    /// # fn main() {
+    /// let at = |&: sec: i64| Timespec { sec: sec, nsec: 0 };
+    /// let mut w = Watch::new(Duration::seconds(10), Duration::seconds(5));
+    /// let mut s = ServerState::new("playd", &[]);
+    /// s.update(&Message::new("STATE").arg("Stopped"));
+    ///
+    /// assert_eq!(w.check(Some(&s), true, at(0)), vec![]);
+    /// assert_eq!(w.check(Some(&s), true, at(10)),
+    ///            vec![Change::Fired(Alert::Stopped)]);
+    /// assert_eq!(w.check(Some(&s), true, at(11)), vec![]);
+    ///
+    /// s.update(&Message::new("STATE").arg("Playing"));
+    /// assert_eq!(w.check(Some(&s), true, at(12)),
+    ///            vec![Change::Cleared(Alert::Stopped)]);
+    /// assert_eq!(w.check(Some(&s), true, at(17)),
+    ///            vec![Change::Fired(Alert::Stalled)]);
+    /// # }
+    /// ```
+    pub fn check(&mut self,
+                 state:  Option<&ServerState>,
+                 on_air: bool,
+                 now:    Timespec) -> Vec<Change> {
+        let current = self.current(state, on_air, now);
+
+        let mut changes: Vec<Change> = self.active.iter()
+                                                  .filter(|a| !current.contains(*a))
+                                                  .map(|a| Change::Cleared(*a))
+                                                  .collect();
+        changes.extend(current.iter()
+                              .filter(|a| !self.active.contains(*a))
+                              .map(|a| Change::Fired(*a)));
+
+        self.active = current;
+        changes
+    }
+}
@@ -0,0 +1,356 @@
+//! Just enough of the WebSocket protocol (RFC 6455) to carry BAPS3 messages
+//! to and from browsers.
+//!
+//! Each BAPS3 message travels as one text frame holding a JSON array of its
+//! command word and arguments, as in `format::Format::Json`.  The opening
+//! handshake is an HTTP request, so is read with `http`; after that, each
+//! client's frames are read in the background and reported as `Event`s on a
+//! channel, much as `server` does for plain BAPS3 clients.
+
+use std::ascii::AsciiExt;
+use std::borrow::ToOwned;
+use std::io::{ IoError, IoErrorKind, IoResult };
+use std::io::net::tcp::TcpStream;
+use std::mem;
+use std::sync::mpsc::Sender;
+use std::thread::Thread;
+
+use rustc_serialize::base64::{ STANDARD, ToBase64 };
+use rustc_serialize::json::Json;
+
+use baps3_protocol::proto::Message;
+
+use super::format::Format;
+use super::http::Request;
+use super::server::{ Peer, message_from_words };
+
+/// The GUID the handshake mixes into the client's key.
+const HANDSHAKE_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The largest message we accept from a client, whether in one frame or
+/// spread over several.
+const MAX_PAYLOAD: u64 = 1 << 20;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT:         u8 = 0x1;
+const OP_BINARY:       u8 = 0x2;
+const OP_CLOSE:        u8 = 0x8;
+const OP_PING:         u8 = 0x9;
+const OP_PONG:         u8 = 0xA;
+
+fn rotl(x: u32, n: usize) -> u32 {
+    (x << n) | (x >> (32 - n))
+}
+
+fn add(a: u32, b: u32) -> u32 {
+    (a as u64 + b as u64) as u32
+}
+
+/// Computes the SHA-1 digest of `data`, which the handshake needs.
+fn sha1(data: &[u8]) -> Vec<u8> {
+    let mut h: [u32; 5] =
+        [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut msg = data.to_vec();
+    let bits    = (data.len() as u64) * 8;
+    msg.push(0x80);
+    while msg.len() % 64 != 56 { msg.push(0); }
+    for i in range(0, 8).rev() { msg.push((bits >> (i * 8)) as u8); }
+
+    for chunk in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in range(0, 16) {
+            w[i] = (chunk[4 * i] as u32) << 24
+                 | (chunk[4 * i + 1] as u32) << 16
+                 | (chunk[4 * i + 2] as u32) << 8
+                 | (chunk[4 * i + 3] as u32);
+        }
+        for i in range(16, 80) {
+            w[i] = rotl(w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16], 1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for i in range(0, 80) {
+            let (f, k) = match i {
+                0...19  => ((b & c) | (!b & d), 0x5A827999),
+                20...39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40...59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _       => (b ^ c ^ d, 0xCA62C1D6)
+            };
+            let t = add(add(add(add(rotl(a, 5), f), e), k), w[i]);
+            e = d;
+            d = c;
+            c = rotl(b, 30);
+            b = a;
+            a = t;
+        }
+
+        h[0] = add(h[0], a);
+        h[1] = add(h[1], b);
+        h[2] = add(h[2], c);
+        h[3] = add(h[3], d);
+        h[4] = add(h[4], e);
+    }
+
+    let mut digest = vec![];
+    for x in h.iter() {
+        for i in range(0, 4).rev() { digest.push((*x >> (i * 8)) as u8); }
+    }
+    digest
+}
+
+/// Computes the Sec-WebSocket-Accept value answering a client's
+/// Sec-WebSocket-Key.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::websocket::accept_key;
+/// // From RFC 6455.
+/// assert_eq!(&*accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+///            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+/// ```
+pub fn accept_key(key: &str) -> String {
+    sha1(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()).to_base64(STANDARD)
+}
+
+/// Answers a WebSocket opening handshake, returning false (and writing
+/// nothing) if `req` is not one.
+pub fn upgrade(req: &Request, stream: &mut TcpStream) -> IoResult<bool> {
+    let is_upgrade = req.header("Upgrade")
+                        .map_or(false, |u| u.eq_ignore_ascii_case("websocket"));
+    let key = match req.header("Sec-WebSocket-Key") {
+        Some(k) if &*req.method == "GET" && is_upgrade => k,
+        _ => return Ok(false)
+    };
+
+    try!(write!(stream,
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)));
+    Ok(true)
+}
+
+/// Converts a text frame from a client into a BAPS3 message.
+///
+/// # Examples
+///
+/// ```rust
+/// use baps3_cli::websocket::message_from_json;
+/// let msg = message_from_json(r#"["load", "/a b.mp3"]"#).unwrap();
+/// assert_eq!(msg.word(), "load");
+/// assert_eq!(msg.args(), vec!["/a b.mp3"]);
+///
+/// assert!(message_from_json(r#"{"word": "play"}"#).is_none());
+/// assert!(message_from_json("[]").is_none());
+/// ```
+pub fn message_from_json(text: &str) -> Option<Message> {
+    let words = match Json::from_str(text) {
+        Ok(Json::Array(ws)) => ws,
+        _                   => return None
+    };
+
+    let mut strs = vec![];
+    for w in words.into_iter() {
+        match w {
+            Json::String(s) => strs.push(s),
+            _               => return None
+        }
+    }
+    message_from_words(&*strs)
+}
+
+fn frame_header(opcode: u8, len: usize) -> Vec<u8> {
+    let mut head = vec![0x80 | opcode];
+    if len < 126 {
+        head.push(len as u8);
+    } else if len < 65536 {
+        head.push(126);
+        head.push((len >> 8) as u8);
+        head.push(len as u8);
+    } else {
+        head.push(127);
+        for i in range(0, 8).rev() { head.push(((len as u64) >> (i * 8)) as u8); }
+    }
+    head
+}
+
+fn write_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) -> IoResult<()> {
+    let mut frame = frame_header(opcode, payload.len());
+    frame.push_all(payload);
+    stream.write(&*frame)
+}
+
+fn protocol_error(detail: &str) -> IoError {
+    IoError { kind:   IoErrorKind::InvalidInput,
+              desc:   "WebSocket protocol error",
+              detail: Some(detail.to_owned()) }
+}
+
+/// Reads one frame from a client, returning its FIN bit, opcode and unmasked
+/// payload.
+fn read_frame(stream: &mut TcpStream) -> IoResult<(bool, u8, Vec<u8>)> {
+    let b0 = try!(stream.read_byte());
+    let b1 = try!(stream.read_byte());
+
+    let len = match b1 & 0x7F {
+        126 => try!(stream.read_be_u16()) as u64,
+        127 => try!(stream.read_be_u64()),
+        n   => n as u64
+    };
+    if MAX_PAYLOAD < len {
+        return Err(protocol_error("frame too large"));
+    }
+
+    // Clients must mask everything they send.
+    if b1 & 0x80 == 0 {
+        return Err(protocol_error("unmasked frame"));
+    }
+    let mask        = try!(stream.read_exact(4));
+    let mut payload = try!(stream.read_exact(len as usize));
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+
+    Ok((b0 & 0x80 != 0, b0 & 0x0F, payload))
+}
+
+/// A connected WebSocket client, as far as writing to it goes.
+pub struct Client {
+    pub id:   usize,
+
+    /// The client's address, for logging.
+    pub peer: String,
+
+    stream: TcpStream
+}
+
+impl Client {
+    /// Creates a client on a stream whose handshake has been answered.
+    pub fn new(id: usize, mut stream: TcpStream) -> Client {
+        let peer = stream.peer_name()
+                         .map(|p| p.to_string())
+                         .unwrap_or("(unknown)".to_owned());
+        Client { id: id, peer: peer, stream: stream }
+    }
+
+    /// Sends a message to the client, as a JSON array.
+    pub fn send(&mut self, msg: &Message) -> IoResult<()> {
+        write_frame(&mut self.stream, OP_TEXT, Format::Json.message(msg).as_bytes())
+    }
+
+    /// Answers a ping from the client.
+    pub fn pong(&mut self, payload: &[u8]) -> IoResult<()> {
+        write_frame(&mut self.stream, OP_PONG, payload)
+    }
+
+    /// Says goodbye to the client and hangs up.
+    pub fn close(&mut self) {
+        let _ = write_frame(&mut self.stream, OP_CLOSE, &[]);
+        let _ = self.stream.close_read();
+        let _ = self.stream.close_write();
+    }
+
+    /// Starts reading the client's frames in the background, reporting them
+    /// on `events`.
+    pub fn start_reading(&self, events: Sender<Event>) {
+        let id     = self.id;
+        let stream = self.stream.clone();
+        Thread::spawn(move || read_client(id, stream, events));
+    }
+}
+
+impl Peer for Client {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn peer(&self) -> &str {
+        &*self.peer
+    }
+
+    fn deliver(&mut self, msg: &Message) -> IoResult<()> {
+        self.send(msg)
+    }
+
+    fn hang_up(&mut self) {
+        self.close()
+    }
+}
+
+/// Something that happened to one of our WebSocket clients.
+pub enum Event {
+    /// A client, identified by its id, sent a message.
+    Message(usize, Message),
+
+    /// A client sent a text frame that wasn't a JSON array of strings.
+    Invalid(usize, String),
+
+    /// A client pinged us, with the given payload.
+    Ping(usize, Vec<u8>),
+
+    /// A client went away.
+    Disconnected(usize)
+}
+
+/// Reads frames from one client until it goes away.
+///
+/// Clients that break the protocol, for example by sending a message larger
+/// than `MAX_PAYLOAD` in pieces, are treated as having gone away.
+fn read_client(id: usize, mut stream: TcpStream, events: Sender<Event>) {
+    // The opcode of the message being assembled from fragments, if any, and
+    // what we have of it so far.
+    let mut opening = None;
+    let mut message = vec![];
+
+    loop {
+        let (fin, opcode, payload) = match read_frame(&mut stream) {
+            Ok(f)  => f,
+            Err(_) => break
+        };
+
+        let ev = match opcode {
+            OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                // Continuations only make sense mid-message, and nothing else
+                // may interrupt one but control frames.
+                let first = match (opcode, opening) {
+                    (OP_CONTINUATION, Some(op))            => op,
+                    (OP_CONTINUATION, None) | (_, Some(_)) => break,
+                    (op, None)                             => op
+                };
+                if MAX_PAYLOAD < (message.len() + payload.len()) as u64 {
+                    break;
+                }
+
+                message.push_all(&*payload);
+                if !fin {
+                    opening = Some(first);
+                    continue;
+                }
+                opening = None;
+
+                let whole = mem::replace(&mut message, vec![]);
+                if first == OP_BINARY {
+                    Event::Invalid(id, "(binary frame)".to_owned())
+                } else {
+                    let t = String::from_utf8_lossy(&*whole).into_owned();
+                    match message_from_json(&*t) {
+                        Some(msg) => Event::Message(id, msg),
+                        None      => Event::Invalid(id, t)
+                    }
+                }
+            },
+            OP_PING => Event::Ping(id, payload),
+            OP_PONG => continue,
+            _       => break
+        };
+
+        if events.send(ev).is_err() {
+            return;
+        }
+    }
+
+    let _ = events.send(Event::Disconnected(id));
+}